@@ -1,6 +1,6 @@
 #![cfg_attr(all(windows, not(debug_assertions)), windows_subsystem = "windows")]
 
-use std::sync::Arc;
+use bytemuck::{Pod, Zeroable};
 use koyote::prelude::*;
 use koyote::graphics::pipeline::{RenderPipelineBuilder, RenderPipelineConfig};
 use koyote::graphics::shader::builder::ShaderBuilder;
@@ -20,23 +20,34 @@ fn main() {
 
 struct App {}
 
+// matches the vertex inputs of res/shaders/simple.hlsl
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+struct ColorVertex {
+  position: [f32; 4],
+  color: [f32; 4],
+}
+
 impl Runnable for App {
   #[allow(unused)]
   fn setup(koyote: &mut Koyote) -> Self {
     Self::initialize_render_data(koyote)?;
 
-    let static_mesh_data = (
-      Arc::new([
-        [-0.5, -0.5, 1.0],
-        [0.5, -0.5, 1.0],
-        [0.5, 0.5, 1.0],
-        [-0.5, 0.5, 1.0],
-      ]),
-      Arc::new([
-        0, 1, 2,
-        2, 3, 0
-      ])
+    let quad = Mesh::new(
+      vec![
+        ColorVertex { position: [-0.5, -0.5, 1.0, 1.0], color: [1.0, 0.0, 0.0, 1.0] },
+        ColorVertex { position: [0.5, -0.5, 1.0, 1.0], color: [0.0, 1.0, 0.0, 1.0] },
+        ColorVertex { position: [0.5, 0.5, 1.0, 1.0], color: [0.0, 0.0, 1.0, 1.0] },
+        ColorVertex { position: [-0.5, 0.5, 1.0, 1.0], color: [1.0, 1.0, 1.0, 1.0] },
+      ],
+      // counter-clockwise on screen, where y points down
+      vec![
+        0, 2, 1,
+        2, 0, 3,
+      ],
     );
+    let quad = quad.upload(koyote.graphics().context()).expect("failed to upload quad mesh");
+    koyote.world.spawn(MeshHandle::new(quad));
 
     Self {}
   }
@@ -62,7 +73,8 @@ impl App {
           ShaderBuilder::new("../res/shaders/simple.hlsl".into())
             .with_stages(&[Stage::Vertex, Stage::Fragment])
         )
-        .with_config(RenderPipelineConfig::new(koyote.graphics().window().size()))
+        .with_config(RenderPipelineConfig::new())
+        .with_vertex::<ColorVertex>()
         .build()?
     );
   }
@@ -0,0 +1,94 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitStr};
+
+/// Implements `koyote::graphics::vertex::Vertex` for a `#[repr(C)]` struct.
+///
+/// Each field becomes one attribute (or several, for matrices) at consecutive shader locations,
+/// in declaration order. The format is inferred from the field type, and can be overridden with
+/// `#[vertex(format = "R8G8B8A8_UNORM")]` using any `vk::Format` constant name.
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+  let input = parse_macro_input!(input as DeriveInput);
+  expand(input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+  let name = &input.ident;
+
+  let mut repr_c = false;
+  for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+    attr.parse_nested_meta(|meta| {
+      if meta.path.is_ident("C") {
+        repr_c = true;
+      }
+      Ok(())
+    })?;
+  }
+  if !repr_c {
+    return Err(syn::Error::new_spanned(name, "Vertex can only be derived for #[repr(C)] structs"));
+  }
+
+  let Data::Struct(data) = &input.data else {
+    return Err(syn::Error::new_spanned(name, "Vertex can only be derived for structs"));
+  };
+  let Fields::Named(fields) = &data.fields else {
+    return Err(syn::Error::new_spanned(name, "Vertex can only be derived for structs with named fields"));
+  };
+
+  let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+  let mut pushes = vec![];
+  for field in &fields.named {
+    let field_name = field.ident.as_ref().unwrap();
+    let ty = &field.ty;
+
+    let mut format = None;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("vertex")) {
+      attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("format") {
+          let value: LitStr = meta.value()?.parse()?;
+          format = Some(Ident::new(&value.value(), value.span()));
+          Ok(())
+        } else {
+          Err(meta.error("unknown vertex attribute, expected `format`"))
+        }
+      })?;
+    }
+
+    let offset = quote! { ::std::mem::offset_of!(#name #ty_generics, #field_name) as u32 };
+    pushes.push(match format {
+      Some(format) => quote! {
+        ::koyote::graphics::vertex::push_attribute_with_format(
+          &mut attributes,
+          binding,
+          &mut location,
+          #offset,
+          ::koyote::graphics::vertex::vk::Format::#format,
+        );
+      },
+      None => quote! {
+        ::koyote::graphics::vertex::push_attributes::<#ty>(
+          &mut attributes,
+          binding,
+          &mut location,
+          #offset,
+        );
+      },
+    });
+  }
+
+  Ok(quote! {
+    impl #impl_generics ::koyote::graphics::vertex::Vertex for #name #ty_generics #where_clause {
+      fn attributes(
+        binding: u32,
+        first_location: u32,
+      ) -> ::std::vec::Vec<::koyote::graphics::vertex::vk::VertexInputAttributeDescription> {
+        let mut attributes = ::std::vec::Vec::new();
+        let mut location = first_location;
+        #(#pushes)*
+        attributes
+      }
+    }
+  })
+}
@@ -0,0 +1,13 @@
+[[vk::binding(0, 0)]] RWStructuredBuffer<uint> values;
+
+[numthreads(64, 1, 1)]
+void compute_main(uint3 id : SV_DispatchThreadID)
+{
+  uint count, stride;
+  values.GetDimensions(count, stride);
+  // the last workgroup may run past the end of the buffer
+  if (id.x >= count) {
+    return;
+  }
+  values[id.x] *= 2;
+}
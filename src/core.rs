@@ -1,6 +1,8 @@
+pub mod config;
 pub mod event;
 pub mod flow;
 pub mod framework;
+pub mod pacing;
 pub mod runnable;
 pub mod time;
 pub mod error;
@@ -0,0 +1,204 @@
+use std::{env, fs, path::Path, str::FromStr};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::log::Level;
+
+/// Framework settings that can be supplied outside of code.
+///
+/// Layers are applied on top of the `FrameworkBuilder` values in this order, later layers winning:
+/// 1. config file (`.toml` or `.ron`)
+/// 2. environment variables (`KOYOTE_TITLE`, `KOYOTE_WIDTH`, `KOYOTE_HEIGHT`, `KOYOTE_CENTERED`,
+///    `KOYOTE_TICK_RATE`, `KOYOTE_LOG_LEVEL`)
+/// 3. command line flags (`--title`, `--width`, `--height`, `--centered`, `--no-centered`, `--tick-rate`, `--log-level`)
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FrameworkConfig {
+  pub title: Option<String>,
+  pub width: Option<u32>,
+  pub height: Option<u32>,
+  pub centered: Option<bool>,
+  pub tick_rate: Option<f64>,
+  pub log_level: Option<Level>,
+}
+
+impl FrameworkConfig {
+  const ENV_PREFIX: &'static str = "KOYOTE_";
+
+  /// Loads all layers in precedence order. A missing config file is not an error.
+  pub fn load(config_file: Option<&Path>) -> Result<Self> {
+    let file = match config_file {
+      Some(path) if path.exists() => Self::from_file(path)?,
+      _ => Self::default(),
+    };
+
+    Ok(
+      file
+        .merge(Self::from_env()?)
+        .merge(Self::from_args(env::args().skip(1))?)
+    )
+  }
+
+  pub fn from_file(path: &Path) -> Result<Self> {
+    let contents = fs::read_to_string(path)
+      .with_context(|| format!("Failed to read config file {path:?}"))?;
+
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("toml") => toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file {path:?}")),
+      Some("ron") => ron::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file {path:?}")),
+      _ => anyhow::bail!("Unsupported config file format {path:?}, expected .toml or .ron"),
+    }
+  }
+
+  pub fn from_env() -> Result<Self> {
+    let var = |name: &str| env::var(format!("{}{name}", Self::ENV_PREFIX)).ok();
+
+    Ok(Self {
+      title: var("TITLE"),
+      width: var("WIDTH").map(|v| parse("KOYOTE_WIDTH", &v)).transpose()?,
+      height: var("HEIGHT").map(|v| parse("KOYOTE_HEIGHT", &v)).transpose()?,
+      centered: var("CENTERED").map(|v| parse("KOYOTE_CENTERED", &v)).transpose()?,
+      tick_rate: var("TICK_RATE").map(|v| parse("KOYOTE_TICK_RATE", &v)).transpose()?,
+      log_level: var("LOG_LEVEL").map(|v| parse("KOYOTE_LOG_LEVEL", &v)).transpose()?,
+    })
+  }
+
+  /// Parses `--flag value` and `--flag=value` pairs. Unknown flags are left for the app to handle.
+  /// `--centered` may be given without a value, and `--no-centered` turns centering off.
+  pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self> {
+    let mut config = Self::default();
+    let mut args = args.into_iter().peekable();
+
+    while let Some(arg) = args.next() {
+      if arg == "--no-centered" {
+        config.centered = Some(false);
+        continue;
+      }
+
+      let (flag, inline_value) = match arg.split_once('=') {
+        Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+        None => (arg, None),
+      };
+
+      if !matches!(
+        flag.as_str(),
+        "--title" | "--width" | "--height" | "--centered" | "--tick-rate" | "--log-level"
+      ) {
+        continue;
+      }
+
+      let value = match inline_value {
+        Some(value) => value,
+        // a following bool belongs to `--centered`, anything else is the next argument
+        None if flag == "--centered" => args.next_if(|next| next.parse::<bool>().is_ok())
+          .unwrap_or_else(|| "true".to_owned()),
+        None => args.next().with_context(|| format!("Missing value for {flag}"))?,
+      };
+
+      match flag.as_str() {
+        "--title" => config.title = Some(value),
+        "--width" => config.width = Some(parse(&flag, &value)?),
+        "--height" => config.height = Some(parse(&flag, &value)?),
+        "--centered" => config.centered = Some(parse(&flag, &value)?),
+        "--tick-rate" => config.tick_rate = Some(parse(&flag, &value)?),
+        "--log-level" => config.log_level = Some(parse(&flag, &value)?),
+        _ => unreachable!(),
+      }
+    }
+
+    Ok(config)
+  }
+
+  /// Combines two configs, preferring values set in `other`.
+  pub fn merge(self, other: Self) -> Self {
+    Self {
+      title: other.title.or(self.title),
+      width: other.width.or(self.width),
+      height: other.height.or(self.height),
+      centered: other.centered.or(self.centered),
+      tick_rate: other.tick_rate.or(self.tick_rate),
+      log_level: other.log_level.or(self.log_level),
+    }
+  }
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T>
+where
+  T::Err: std::error::Error + Send + Sync + 'static,
+{
+  value.parse().with_context(|| format!("Invalid value for {name}: [{value}]"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|a| a.to_string()).collect()
+  }
+
+  #[test]
+  fn args_accept_separate_and_inline_values() {
+    let config = FrameworkConfig::from_args(args(&[
+      "--title", "Demo", "--width=800", "--height", "600", "--tick-rate=30", "--log-level", "debug",
+    ])).unwrap();
+
+    assert_eq!(config.title.as_deref(), Some("Demo"));
+    assert_eq!(config.width, Some(800));
+    assert_eq!(config.height, Some(600));
+    assert_eq!(config.tick_rate, Some(30.0));
+    assert_eq!(config.log_level, Some(Level::Debug));
+    assert_eq!(config.centered, None);
+  }
+
+  #[test]
+  fn centered_takes_an_optional_bool() {
+    let centered = |a: &[&str]| FrameworkConfig::from_args(args(a)).unwrap().centered;
+
+    assert_eq!(centered(&["--centered"]), Some(true));
+    assert_eq!(centered(&["--centered", "false"]), Some(false));
+    assert_eq!(centered(&["--centered", "true"]), Some(true));
+    assert_eq!(centered(&["--centered=false"]), Some(false));
+    assert_eq!(centered(&["--no-centered"]), Some(false));
+
+    // a following flag is not swallowed
+    let config = FrameworkConfig::from_args(args(&["--centered", "--width", "640"])).unwrap();
+    assert_eq!(config.centered, Some(true));
+    assert_eq!(config.width, Some(640));
+  }
+
+  #[test]
+  fn args_skip_unknown_flags_and_reject_bad_values() {
+    let config = FrameworkConfig::from_args(args(&["--fullscreen", "--width", "640", "extra"])).unwrap();
+    assert_eq!(config.width, Some(640));
+
+    assert!(FrameworkConfig::from_args(args(&["--width", "wide"])).is_err());
+    assert!(FrameworkConfig::from_args(args(&["--height"])).is_err());
+  }
+
+  #[test]
+  fn later_layers_win() {
+    let file = FrameworkConfig {
+      title: Some("file".into()),
+      width: Some(100),
+      height: Some(100),
+      centered: Some(true),
+      ..Default::default()
+    };
+    let env = FrameworkConfig {
+      width: Some(200),
+      height: Some(200),
+      ..Default::default()
+    };
+    let cli = FrameworkConfig::from_args(args(&["--height", "300", "--no-centered"])).unwrap();
+
+    let config = file.merge(env).merge(cli);
+    assert_eq!(config.title.as_deref(), Some("file"));
+    assert_eq!(config.width, Some(200));
+    assert_eq!(config.height, Some(300));
+    assert_eq!(config.centered, Some(false));
+    assert_eq!(config.tick_rate, None);
+  }
+}
@@ -1,13 +1,38 @@
+use std::path::PathBuf;
 use enumflags2::BitFlags;
 use crate::{
   input::modifier::Modifiers,
   prelude::{ButtonState, KeyCode, MouseCode},
 };
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum WindowEvent {
-  Moved,
-  Resized,
+  Created,
+  Moved { x: i32, y: i32 },
+  Resized { width: u32, height: u32 },
+  Focused(bool),
+  ScaleFactorChanged { scale_factor: f64, width: u32, height: u32 },
+  Occluded(bool),
+  FileDropped(PathBuf),
+  FileHovered(PathBuf),
+  FileHoverCancelled,
+  ThemeChanged(Theme),
+  CloseRequested,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Theme {
+  Light,
+  Dark,
+}
+
+impl From<winit::window::Theme> for Theme {
+  fn from(value: winit::window::Theme) -> Self {
+    match value {
+      winit::window::Theme::Light => Theme::Light,
+      winit::window::Theme::Dark => Theme::Dark,
+    }
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -17,4 +42,4 @@ pub enum InputEvent {
   Modifiers(BitFlags<Modifiers>),
   Cursor,
   Scroll,
-}
\ No newline at end of file
+}
@@ -1,6 +1,7 @@
+use std::path::PathBuf;
 use std::sync::OnceLock;
 use bevy_ecs::prelude::*;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 use winit::event_loop::{ControlFlow, EventLoop};
 
 use crate::{
@@ -13,13 +14,22 @@ use crate::{
   input::Input,
   log,
 };
+use crate::core::config::FrameworkConfig;
 use crate::core::flow::Flow;
+use crate::core::pacing::{FramePacer, LoopMode};
 use crate::graphics::GraphicsCreateInfo;
+use crate::graphics::device::{DeviceSelection, DeviceSelector};
+use crate::graphics::mesh::MeshHandle;
+use crate::graphics::monitor::MonitorSelection;
+use crate::graphics::placement::WindowPlacement;
+use crate::graphics::window::{WindowCreateInfo, WindowId};
 use crate::log::Level;
 
 pub struct Koyote {
   pub world: World,
   flow: Flow,
+  placement_file: Option<PathBuf>,
+  pacer: FramePacer,
 }
 
 static KOYOTE: OnceLock<Koyote> = OnceLock::new();
@@ -57,54 +67,104 @@ impl Koyote {
     info!("Kon-Koyo!");
 
     app.start(&mut self);
-    event_loop.run(move |event, _, control_flow| {
+    event_loop.run(move |event, window_target, control_flow| {
       if let Flow::Exit(code) = self.flow {
         *control_flow = ControlFlow::ExitWithCode(code);
       }
 
+      match self.graphics_mut().open_pending_windows(window_target) {
+        Ok(opened) => for window_id in opened {
+          self.pacer.notify_event();
+          app.window(window_id, WindowEvent::Created, &mut self);
+        },
+        Err(err) => error!("Failed to open window: {err:#}"),
+      }
+
       match event {
-        winit::event::Event::WindowEvent { window_id: _, event } => {
+        winit::event::Event::WindowEvent { window_id, event } => {
+          self.pacer.notify_event();
           match event {
             winit::event::WindowEvent::CloseRequested => {
-              match app.stop(&mut self) {
-                Flow::Exit(code) => *control_flow = ControlFlow::ExitWithCode(code),
-                Flow::Continue => *control_flow = ControlFlow::Poll,
+              app.window(window_id, WindowEvent::CloseRequested, &mut self);
+              if Some(window_id) == self.graphics().main_window_id() {
+                match app.stop(&mut self) {
+                  Flow::Exit(code) => *control_flow = ControlFlow::ExitWithCode(code),
+                  Flow::Continue => *control_flow = ControlFlow::Poll,
+                }
+              } else if let Err(err) = self.graphics_mut().close_window(window_id) {
+                error!("Failed to close window: {err:#}");
               }
             }
-            winit::event::WindowEvent::Resized(_) => {
-              app.window(WindowEvent::Resized, &mut self);
+            winit::event::WindowEvent::Resized(size) => {
+              self.pacer.set_minimized(window_id, size.width == 0 || size.height == 0);
+              self.graphics_mut().window_resized(window_id);
+              app.window(window_id, WindowEvent::Resized { width: size.width, height: size.height }, &mut self);
+            }
+            winit::event::WindowEvent::Moved(position) => {
+              self.graphics_mut().window_moved(window_id);
+              app.window(window_id, WindowEvent::Moved { x: position.x, y: position.y }, &mut self);
+            }
+            winit::event::WindowEvent::Focused(focused) => {
+              app.window(window_id, WindowEvent::Focused(focused), &mut self);
+            }
+            winit::event::WindowEvent::ScaleFactorChanged { scale_factor, new_inner_size } => {
+              app.window(window_id, WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                width: new_inner_size.width,
+                height: new_inner_size.height,
+              }, &mut self);
+            }
+            winit::event::WindowEvent::Occluded(occluded) => {
+              self.pacer.set_occluded(window_id, occluded);
+              app.window(window_id, WindowEvent::Occluded(occluded), &mut self);
+            }
+            winit::event::WindowEvent::DroppedFile(path) => {
+              app.window(window_id, WindowEvent::FileDropped(path), &mut self);
+            }
+            winit::event::WindowEvent::HoveredFile(path) => {
+              app.window(window_id, WindowEvent::FileHovered(path), &mut self);
+            }
+            winit::event::WindowEvent::HoveredFileCancelled => {
+              app.window(window_id, WindowEvent::FileHoverCancelled, &mut self);
             }
-            winit::event::WindowEvent::Moved(_) => {
-              app.window(WindowEvent::Moved, &mut self);
+            winit::event::WindowEvent::ThemeChanged(theme) => {
+              app.window(window_id, WindowEvent::ThemeChanged(theme.into()), &mut self);
             }
             winit::event::WindowEvent::KeyboardInput { device_id: _, input, is_synthetic: _ } => {
               if let Some(keycode) = input.virtual_keycode {
                 let state = self.world.resource_mut::<Input>().update_key_state(keycode, input.state);
-                app.input(InputEvent::Keyboard(keycode.into(), state), &mut self);
+                app.input(window_id, InputEvent::Keyboard(keycode.into(), state), &mut self);
               }
             }
             winit::event::WindowEvent::ModifiersChanged(mods) => {
               let mods = self.world.resource_mut::<Input>().update_modifiers_state(mods);
-              app.input(InputEvent::Modifiers(mods), &mut self);
+              app.input(window_id, InputEvent::Modifiers(mods), &mut self);
             }
             winit::event::WindowEvent::CursorMoved { device_id: _, position: _, .. } => {
-              app.input(InputEvent::Cursor, &mut self);
+              app.input(window_id, InputEvent::Cursor, &mut self);
             }
             winit::event::WindowEvent::MouseWheel { device_id: _, delta: _, phase: _, .. } => {
-              app.input(InputEvent::Scroll, &mut self);
+              app.input(window_id, InputEvent::Scroll, &mut self);
             }
             winit::event::WindowEvent::MouseInput { device_id: _, state, button, .. } => {
               let state = self.world.resource_mut::<Input>().update_mouse_button_state(button, state);
-              app.input(InputEvent::Mouse(button.into(), state), &mut self);
+              app.input(window_id, InputEvent::Mouse(button.into(), state), &mut self);
             }
             _ => {}
           }
         }
         winit::event::Event::MainEventsCleared => {
-          self.update(&mut app);
+          let throttled = self.pacer.throttled(self.graphics().windows().map(|w| w.id()));
+          if self.pacer.frame_due(throttled) {
+            // hidden windows keep updating, but there's no point drawing them
+            self.update(&mut app, !throttled);
+          }
+          if !matches!(*control_flow, ControlFlow::ExitWithCode(_)) {
+            *control_flow = self.pacer.control_flow(throttled);
+          }
         }
-        winit::event::Event::RedrawRequested(_) => {
-          self.graphics_mut().render_frame();
+        winit::event::Event::RedrawRequested(window_id) => {
+          self.render_frame(&mut app, window_id);
         }
         winit::event::Event::RedrawEventsCleared => {
           self.graphics_mut().reset_frame();
@@ -112,6 +172,7 @@ impl Koyote {
         winit::event::Event::LoopDestroyed => {
           info!("Otsu-Koyo!");
           app.shutdown(&mut self);
+          self.save_window_placement();
           trace!("Exiting Koyote Framework loop.");
         }
         _ => {}
@@ -120,15 +181,60 @@ impl Koyote {
   }
 
   // Note: Any errors will cause the entire frame to skip
-  fn update<App: 'static + Runnable>(&mut self, app: &mut App) {
+  fn update<App: 'static + Runnable>(&mut self, app: &mut App, redraw: bool) {
     self.world.resource_mut::<Time>().update();
+    if self.pacer.idles() {
+      self.world.resource_mut::<Time>().clamp_lag();
+    }
     while self.world.resource::<Time>().should_do_tick() {
       self.world.resource_mut::<Time>().tick();
       app.fixed_update(self);
     }
     app.update(self);
     app.late_update(self);
-    self.graphics().window().request_redraw();
+    if redraw {
+      self.graphics().request_redraw();
+    }
+  }
+
+  fn render_frame<App: 'static + Runnable>(&mut self, app: &mut App, window_id: WindowId) {
+    match self.graphics_mut().begin_frame(window_id) {
+      Ok(Some(frame)) => {
+        let mut meshes = self.world.query::<&MeshHandle>();
+        let graphics = self.world.resource::<Graphics>();
+        if let Err(err) = graphics.draw_meshes(&frame, meshes.iter(&self.world).map(|m| m.0.as_ref())) {
+          error!("Failed to draw meshes: {err:#}");
+        }
+        app.render(&frame, self);
+        if let Err(err) = self.graphics_mut().end_frame(frame) {
+          error!("Failed to present frame: {err:#}");
+        }
+      }
+      Ok(None) => {}
+      Err(err) => error!("Failed to begin frame: {err:#}"),
+    }
+  }
+
+  /// Asks for another frame. Only needed with `LoopMode::Reactive`, other modes redraw on their own.
+  pub fn request_redraw(&mut self) {
+    self.pacer.request_redraw();
+  }
+
+  pub fn loop_mode(&self) -> LoopMode {
+    self.pacer.mode()
+  }
+
+  pub fn set_loop_mode(&mut self, mode: LoopMode) {
+    self.pacer.set_mode(mode);
+  }
+
+  fn save_window_placement(&self) {
+    if let Some(path) = &self.placement_file {
+      match WindowPlacement::capture(self.graphics().window()).save(path) {
+        Ok(_) => trace!("Saved window placement to {path:?}"),
+        Err(err) => warn!("Failed to save window placement: {err:#}"),
+      }
+    }
   }
 
   pub fn time(&self) -> &Time {
@@ -149,16 +255,24 @@ impl Koyote {
 }
 
 pub struct FrameworkBuilder {
-  pub title: &'static str,
+  pub title: String,
   pub width: u32,
   pub height: u32,
   pub centered: bool,
+  pub monitor: Option<MonitorSelection>,
   pub tick_rate: f64,
+  pub loop_mode: LoopMode,
+  /// App name used for the window placement file. Placement isn't persisted when `None`.
+  pub persist_placement: Option<&'static str>,
+  /// Whether to layer a config file, environment variables and command line flags over these values.
+  pub external_config: bool,
+  pub config_file: Option<PathBuf>,
+  pub device: DeviceSelection,
 }
 
 impl FrameworkBuilder {
-  pub fn with_title(mut self, title: &'static str) -> Self {
-    self.title = title;
+  pub fn with_title(mut self, title: impl Into<String>) -> Self {
+    self.title = title.into();
     self
   }
 
@@ -172,29 +286,133 @@ impl FrameworkBuilder {
     self
   }
 
+  pub fn with_monitor(mut self, monitor: MonitorSelection) -> Self {
+    self.monitor = Some(monitor);
+    self
+  }
+
   pub fn with_tick_rate(mut self, tick_rate: f64) -> Self {
     self.tick_rate = tick_rate;
     self
   }
 
+  pub fn with_loop_mode(mut self, loop_mode: LoopMode) -> Self {
+    self.loop_mode = loop_mode;
+    self
+  }
+
+  /// Uses a specific physical device instead of the best ranked one.
+  pub fn with_device(mut self, device: DeviceSelector) -> Self {
+    self.device.device = Some(device);
+    self
+  }
+
+  /// Allows a CPU implementation such as lavapipe when no GPU is suitable.
+  pub fn with_cpu_fallback(mut self, cpu_fallback: bool) -> Self {
+    self.device.cpu_fallback = cpu_fallback;
+    self
+  }
+
+  /// Replaces the whole device selection policy, including required and optional features.
+  pub fn with_device_selection(mut self, device: DeviceSelection) -> Self {
+    self.device = device;
+    self
+  }
+
+  /// Saves the main window's placement on shutdown and restores it on the next launch.
+  pub fn with_persisted_placement(mut self, app_name: &'static str) -> Self {
+    self.persist_placement = Some(app_name);
+    self
+  }
+
+  /// Applies settings from the environment and command line when run. See `FrameworkConfig` for precedence.
+  pub fn with_external_config(mut self) -> Self {
+    self.external_config = true;
+    self
+  }
+
+  /// Like `with_external_config`, but also reads a `.toml` or `.ron` file if it exists.
+  pub fn with_config_file(mut self, path: impl Into<PathBuf>) -> Self {
+    self.external_config = true;
+    self.config_file = Some(path.into());
+    self
+  }
+
+  pub fn with_config(mut self, config: FrameworkConfig) -> Self {
+    if let Some(title) = config.title {
+      self.title = title;
+    }
+    if let Some(width) = config.width {
+      self.width = width;
+    }
+    if let Some(height) = config.height {
+      self.height = height;
+    }
+    if let Some(centered) = config.centered {
+      self.centered = centered;
+    }
+    if let Some(tick_rate) = config.tick_rate {
+      self.tick_rate = tick_rate;
+    }
+    if let Some(log_level) = config.log_level {
+      if !log::set_level(Some(log_level)) {
+        warn!("Logging was initialized outside of koyote, ignoring configured log level");
+      }
+    }
+    self
+  }
+
   pub fn log_init(self, framework_logging_level: Option<Level>) -> Self {
     log::init(framework_logging_level);
     self
   }
 
-  pub fn run<App: 'static + Runnable>(self) {
+  pub fn run<App: 'static + Runnable>(mut self) {
+    if self.external_config {
+      match FrameworkConfig::load(self.config_file.as_deref()) {
+        Ok(config) => self = self.with_config(config),
+        Err(err) => {
+          // nobody would see the error without logging
+          if log::is_initialized() {
+            error!("FATAL | CONFIG | {err:#}");
+          } else {
+            eprintln!("FATAL | CONFIG | {err:#}");
+          }
+          return;
+        }
+      }
+    }
+
     let mut world = World::new();
 
     world.insert_resource(Time::new(self.tick_rate, 1024));
     world.insert_resource(Input::default());
 
+    let placement_file = self.persist_placement.and_then(|app_name| {
+      WindowPlacement::file_path(app_name)
+        .map_err(|err| warn!("Window placement won't be persisted: {err:#}"))
+        .ok()
+    });
+    let placement = placement_file.as_ref()
+      .filter(|path| path.exists())
+      .and_then(|path| {
+        WindowPlacement::load(path)
+          .map_err(|err| warn!("Failed to restore window placement: {err:#}"))
+          .ok()
+      });
+
     let event_loop = EventLoop::new();
     let graphics = match Graphics::new(GraphicsCreateInfo {
       event_loop: &event_loop,
-      title: self.title,
-      width: self.width,
-      height: self.height,
-      centered: self.centered,
+      window: WindowCreateInfo {
+        title: self.title,
+        width: self.width,
+        height: self.height,
+        centered: self.centered,
+        monitor: self.monitor,
+        placement,
+      },
+      device: self.device,
     }) {
       Ok(value) => value,
       Err(err) => {
@@ -208,6 +426,8 @@ impl FrameworkBuilder {
     Koyote {
       world,
       flow: Default::default(),
+      placement_file,
+      pacer: FramePacer::new(self.loop_mode),
     }.run::<App>(event_loop);
   }
 }
@@ -215,11 +435,17 @@ impl FrameworkBuilder {
 impl Default for FrameworkBuilder {
   fn default() -> Self {
     Self {
-      title: "Koyote",
+      title: "Koyote".to_owned(),
       width: 800,
       height: 500,
       centered: false,
+      monitor: None,
       tick_rate: 128.,
+      loop_mode: Default::default(),
+      persist_placement: None,
+      external_config: false,
+      config_file: None,
+      device: Default::default(),
     }
   }
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use winit::event_loop::ControlFlow;
+
+use crate::graphics::window::WindowId;
+
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum LoopMode {
+  /// Updates and redraws as fast as possible.
+  #[default]
+  Continuous,
+  /// Updates and redraws at most this many times per second.
+  Capped(f64),
+  /// Sleeps until an event arrives or the app calls `Koyote::request_redraw`.
+  Reactive,
+}
+
+pub(crate) struct FramePacer {
+  mode: LoopMode,
+  next_frame: Instant,
+  pending_events: bool,
+  redraw_requested: bool,
+  minimized: HashSet<WindowId>,
+  occluded: HashSet<WindowId>,
+}
+
+impl FramePacer {
+  /// Frame rate used while every window is minimized or occluded.
+  const THROTTLED_FPS: f64 = 10.;
+  /// How long before a capped frame's deadline to stop sleeping and start spinning.
+  /// Sleeping is only accurate to within a millisecond or two on most platforms.
+  const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+  pub fn new(mode: LoopMode) -> Self {
+    Self {
+      mode,
+      next_frame: Instant::now(),
+      pending_events: true,
+      redraw_requested: false,
+      minimized: Default::default(),
+      occluded: Default::default(),
+    }
+  }
+
+  pub fn mode(&self) -> LoopMode {
+    self.mode
+  }
+
+  pub fn set_mode(&mut self, mode: LoopMode) {
+    self.mode = mode;
+    self.next_frame = Instant::now();
+  }
+
+  pub fn notify_event(&mut self) {
+    self.pending_events = true;
+  }
+
+  pub fn request_redraw(&mut self) {
+    self.redraw_requested = true;
+  }
+
+  pub fn set_minimized(&mut self, window_id: WindowId, minimized: bool) {
+    if minimized {
+      self.minimized.insert(window_id);
+    } else {
+      self.minimized.remove(&window_id);
+    }
+  }
+
+  pub fn set_occluded(&mut self, window_id: WindowId, occluded: bool) {
+    if occluded {
+      self.occluded.insert(window_id);
+    } else {
+      self.occluded.remove(&window_id);
+    }
+  }
+
+  /// Whether none of the given windows can currently be seen.
+  pub fn throttled(&self, mut windows: impl Iterator<Item = WindowId>) -> bool {
+    windows.all(|id| self.minimized.contains(&id) || self.occluded.contains(&id))
+  }
+
+  /// Whether the loop may go idle for long stretches, making the fixed update backlog meaningless.
+  pub fn idles(&self) -> bool {
+    matches!(self.mode, LoopMode::Reactive)
+  }
+
+  /// Decides whether a frame should run now, spinning out the last moments before a capped deadline.
+  pub fn frame_due(&mut self, throttled: bool) -> bool {
+    match self.effective_mode(throttled) {
+      LoopMode::Continuous => true,
+      LoopMode::Capped(fps) => {
+        let now = Instant::now();
+        if now + Self::SPIN_MARGIN < self.next_frame {
+          // woken early by an event
+          return false;
+        }
+
+        while Instant::now() < self.next_frame {
+          std::hint::spin_loop();
+        }
+
+        let frame_time = Duration::from_secs_f64(1. / fps.max(f64::EPSILON));
+        self.next_frame += frame_time;
+        if self.next_frame < now {
+          // fell behind, don't try to catch up with a burst of frames
+          self.next_frame = now + frame_time;
+        }
+        true
+      }
+      LoopMode::Reactive => {
+        let due = self.pending_events || self.redraw_requested;
+        self.pending_events = false;
+        self.redraw_requested = false;
+        due
+      }
+    }
+  }
+
+  pub fn control_flow(&self, throttled: bool) -> ControlFlow {
+    match self.effective_mode(throttled) {
+      LoopMode::Continuous => ControlFlow::Poll,
+      LoopMode::Capped(_) => ControlFlow::WaitUntil(
+        self.next_frame.checked_sub(Self::SPIN_MARGIN).unwrap_or(self.next_frame)
+      ),
+      LoopMode::Reactive => if self.redraw_requested {
+        ControlFlow::Poll
+      } else {
+        ControlFlow::Wait
+      },
+    }
+  }
+
+  fn effective_mode(&self, throttled: bool) -> LoopMode {
+    match self.mode {
+      LoopMode::Reactive => LoopMode::Reactive,
+      _ if throttled => LoopMode::Capped(Self::THROTTLED_FPS),
+      mode => mode,
+    }
+  }
+}
@@ -3,6 +3,7 @@ use crate::core::{
   framework::Koyote,
   flow::Flow,
 };
+use crate::graphics::{frame::Frame, window::WindowId};
 
 #[allow(unused)]
 pub trait Runnable {
@@ -16,15 +17,18 @@ pub trait Runnable {
 
   fn late_update(&mut self, koyote: &mut Koyote) {}
 
+  /// Records draws into `frame.command_buffer`. Called once per frame for each visible window.
+  fn render(&mut self, frame: &Frame, koyote: &mut Koyote) {}
+
   fn stop(&mut self, koyote: &mut Koyote) -> Flow {
     Default::default()
   }
 
   fn shutdown(&mut self, koyote: &mut Koyote) {}
 
-  fn window(&mut self, event: WindowEvent, koyote: &mut Koyote) {}
+  fn window(&mut self, window_id: WindowId, event: WindowEvent, koyote: &mut Koyote) {}
 
-  fn input(&mut self, event: InputEvent, koyote: &mut Koyote) {}
+  fn input(&mut self, window_id: WindowId, event: InputEvent, koyote: &mut Koyote) {}
 }
 
 // EXAMPLE
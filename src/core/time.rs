@@ -88,6 +88,11 @@ impl Time {
     self.step_count += 1;
   }
 
+  /// Drops any fixed update backlog beyond a single tick, e.g. after the loop sat idle.
+  pub(crate) fn clamp_lag(&mut self) {
+    self.lag_time = self.lag_time.min(self.tick_time);
+  }
+
   pub(crate) fn should_do_tick(&self) -> bool {
     if self.step_count >= self.bail_threshold {
       tracing::warn!("Struggling to catch up with tick rate.");
@@ -1,68 +1,232 @@
 mod debug;
 pub mod window;
+pub mod monitor;
+pub mod placement;
 pub mod context;
+pub mod device;
 pub mod shader;
+pub mod memory;
 pub mod buffer;
 pub mod image;
+pub mod texture;
+pub mod vertex;
+pub mod descriptor;
+pub mod graph;
 pub mod pipeline;
-mod swapchain;
+pub mod render_pass;
+pub mod mesh;
+pub mod swapchain;
+pub mod frame;
+pub mod target;
+pub mod readback;
+pub mod capture;
 
-use anyhow::{Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use anyhow::{Context, Result};
+use ash::vk;
 use bevy_ecs::prelude::Resource;
-use tracing::{trace};
-use winit::event_loop::EventLoop;
+use tracing::{error, trace, warn};
+use winit::event_loop::{EventLoop, EventLoopWindowTarget};
 use crate::graphics::pipeline::RenderPipeline;
 
-use self::{window::Window, context::RenderContext};
+use self::{
+  capture::{CaptureRequest, FrameCapture, SubmittedCapture},
+  frame::{Frame, FrameResources, FrameSync},
+  graph::RenderGraph,
+  image::transition_layout,
+  mesh::GpuMesh,
+  readback::{create_readback_buffer, record_image_copy},
+  render_pass::SwapchainFramebuffers,
+  monitor::Monitor,
+  target::RenderTarget,
+  window::{Window, WindowCreateInfo, WindowId},
+  context::RenderContext,
+  device::DeviceSelection,
+};
 
 #[allow(unused)]
 #[derive(Resource)]
 pub struct Graphics {
   pipeline: Option<RenderPipeline>,
-  window: Window,
+  windows: HashMap<WindowId, Window>,
+  main_window: Option<WindowId>,
+  pending_windows: Vec<WindowCreateInfo>,
+  frames: HashMap<WindowId, FrameResources>,
+  /// Created on first use by pipelines with a render pass.
+  framebuffers: Mutex<HashMap<WindowId, SwapchainFramebuffers>>,
+  capture_requests: Vec<CaptureRequest>,
+  target_captures: Vec<SubmittedCapture>,
+  clear_color: [f32; 4],
   context: RenderContext,
 }
 
 pub struct GraphicsCreateInfo<'e> {
   pub event_loop: &'e EventLoop<()>,
-  pub title: &'static str,
-  pub width: u32,
-  pub height: u32,
-  pub centered: bool,
+  pub window: WindowCreateInfo,
+  pub device: DeviceSelection,
 }
 
 impl Graphics {
   pub fn new(create_info: GraphicsCreateInfo) -> Result<Self> {
     trace!("Initializing Graphics...");
 
-    let mut window = Window::new(
-      create_info.event_loop,
-      create_info.title,
-      create_info.width,
-      create_info.height,
-    )?;
-    if create_info.centered {
-      window.center_on_monitor();
-    }
+    let mut window = Window::new(create_info.event_loop, &create_info.window)?;
 
-    let context = RenderContext::new(&mut window)?;
+    let context = RenderContext::new(&mut window, &create_info.device)?;
+    window.prepare_swapchain(&context)?;
     window.set_visible(true);
 
+    let main_window = window.id();
+
     trace!("Graphics ready!");
 
     Ok(Self {
       pipeline: None,
-      window,
+      windows: HashMap::from([(main_window, window)]),
+      main_window: Some(main_window),
+      pending_windows: Default::default(),
+      frames: Default::default(),
+      framebuffers: Default::default(),
+      capture_requests: Default::default(),
+      target_captures: Default::default(),
+      clear_color: [0.0, 0.0, 0.0, 1.0],
       context,
     })
   }
 
+  /// Creates graphics without any window, rendering only to `RenderTarget`s.
+  pub fn headless() -> Result<Self> {
+    Self::headless_with(&DeviceSelection::default())
+  }
+
+  /// Like `headless`, e.g. with CPU fallback enabled for machines without a GPU.
+  pub fn headless_with(device_selection: &DeviceSelection) -> Result<Self> {
+    trace!("Initializing headless Graphics...");
+
+    let context = RenderContext::headless(device_selection)?;
+
+    trace!("Graphics ready!");
+
+    Ok(Self {
+      pipeline: None,
+      windows: Default::default(),
+      main_window: None,
+      pending_windows: Default::default(),
+      frames: Default::default(),
+      framebuffers: Default::default(),
+      capture_requests: Default::default(),
+      target_captures: Default::default(),
+      clear_color: [0.0, 0.0, 0.0, 1.0],
+      context,
+    })
+  }
+
+  /// `None` for headless graphics.
+  pub fn main_window_id(&self) -> Option<WindowId> {
+    self.main_window
+  }
+
+  /// # Panics
+  /// If the graphics are headless.
   pub fn window(&self) -> &Window {
-    &self.window
+    self.main_window.and_then(|id| self.windows.get(&id)).expect("Graphics has no window")
   }
 
+  /// # Panics
+  /// If the graphics are headless.
   pub fn window_mut(&mut self) -> &mut Window {
-    &mut self.window
+    self.main_window.and_then(|id| self.windows.get_mut(&id)).expect("Graphics has no window")
+  }
+
+  pub fn window_by_id(&self, window_id: WindowId) -> Option<&Window> {
+    self.windows.get(&window_id)
+  }
+
+  pub fn window_by_id_mut(&mut self, window_id: WindowId) -> Option<&mut Window> {
+    self.windows.get_mut(&window_id)
+  }
+
+  pub fn windows(&self) -> impl Iterator<Item = &Window> {
+    self.windows.values()
+  }
+
+  pub fn monitors(&self) -> Vec<Monitor> {
+    self.windows.values().next().map(|w| w.available_monitors()).unwrap_or_default()
+  }
+
+  /// Queues a window to be opened at the start of the next frame.
+  /// `WindowEvent::Created` is sent to the app with the new window's id once it exists.
+  pub fn create_window(&mut self, create_info: WindowCreateInfo) {
+    self.pending_windows.push(create_info);
+  }
+
+  /// Closes a window opened with `create_window`. The main window can't be closed this way.
+  pub fn close_window(&mut self, window_id: WindowId) -> Result<()> {
+    if Some(window_id) == self.main_window {
+      anyhow::bail!("Cannot close the main window");
+    }
+
+    // the window's swapchain may still be in use by the gpu
+    self.context.wait_idle()?;
+    self.frames.remove(&window_id);
+    self.framebuffers.get_mut().unwrap().remove(&window_id);
+    self.windows.remove(&window_id).context("Window does not exist")?;
+
+    Ok(())
+  }
+
+  pub(crate) fn open_pending_windows(&mut self, window_target: &EventLoopWindowTarget<()>) -> Result<Vec<WindowId>> {
+    if self.context.is_headless() && !self.pending_windows.is_empty() {
+      anyhow::bail!("Headless graphics cannot open windows");
+    }
+
+    // one failing window doesn't keep the others from opening or being reported
+    let mut opened = vec![];
+    for create_info in std::mem::take(&mut self.pending_windows) {
+      match self.open_window(window_target, &create_info) {
+        Ok(window_id) => opened.push(window_id),
+        Err(err) => error!("Failed to open window [{}]: {err:#}", create_info.title),
+      }
+    }
+
+    Ok(opened)
+  }
+
+  fn open_window(&mut self, window_target: &EventLoopWindowTarget<()>, create_info: &WindowCreateInfo) -> Result<WindowId> {
+    let mut window = Window::new(window_target, create_info)?;
+    self.context.create_surface(&mut window)?;
+    window.prepare_swapchain(&self.context)?;
+    window.set_visible(true);
+
+    trace!("Opened window: [{}]", window.title());
+    let window_id = window.id();
+    self.windows.insert(window_id, window);
+    Ok(window_id)
+  }
+
+  /// Marks the window's swapchain for recreation before its next frame.
+  pub(crate) fn window_resized(&mut self, window_id: WindowId) {
+    let Some(window) = self.windows.get_mut(&window_id) else {
+      return;
+    };
+    window.track_restored_placement();
+    if let Some(swapchain) = window.swapchain_mut() {
+      swapchain.mark_out_of_date();
+    }
+  }
+
+  pub(crate) fn window_moved(&mut self, window_id: WindowId) {
+    if let Some(window) = self.windows.get_mut(&window_id) {
+      window.track_restored_placement();
+    }
+  }
+
+  pub(crate) fn request_redraw(&self) {
+    for window in self.windows.values() {
+      window.request_redraw();
+    }
   }
 
   // pub fn create_render_pass<RP: RenderPass + 'static>(&mut self, label: Option<&'static str>, shader: Arc<Shader>) -> RP {
@@ -70,6 +234,13 @@ impl Graphics {
   // }
 
   pub fn set_render_pipeline(&mut self, render_pipeline: RenderPipeline) {
+    // the old pipeline and its framebuffers may still be in use by the gpu
+    if let Err(err) = self.context.wait_idle() {
+      error!("{err:#}");
+    }
+    // everything submitted has finished, so pending captures can be handed out
+    self.resolve_target_captures();
+    self.framebuffers.get_mut().unwrap().clear();
     self.pipeline = Some(render_pipeline);
   }
 
@@ -80,18 +251,515 @@ impl Graphics {
   //   Ok(())
   // }
 
-  pub(crate) fn render_frame(&mut self) {}
+  pub fn clear_color(&self) -> [f32; 4] {
+    self.clear_color
+  }
+
+  pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+    self.clear_color = clear_color;
+  }
+
+  /// Waits for the window's next frame slot, acquires a swapchain image and starts recording.
+  /// Returns `None` when there is nothing to render to, e.g. while minimized or after a resize.
+  pub(crate) fn begin_frame(&mut self, window_id: WindowId) -> Result<Option<Frame>> {
+    self.resolve_target_captures();
+
+    let Some(window) = self.windows.get_mut(&window_id) else {
+      return Ok(None);
+    };
+
+    if !window.prepare_swapchain(&self.context)? {
+      return Ok(None);
+    }
+
+    if !self.frames.contains_key(&window_id) {
+      self.frames.insert(window_id, FrameResources::new(&self.context)?);
+    }
+    let sync = self.frames[&window_id].current();
+    let device = self.context.device();
+
+    unsafe {
+      device.wait_for_fences(&[sync.in_flight], true, u64::MAX)
+    }.context("Failed to wait for frame fence")?;
+    // the fence covers this slot's previous frame, so its captures are now readable
+    self.frames.get_mut(&window_id).unwrap().resolve_captures();
+    let sync = self.frames[&window_id].current();
+
+    let swapchain = window.swapchain_mut().context("Window has no swapchain")?;
+    let Some(image_index) = swapchain.acquire_next_image(sync.image_available)? else {
+      return Ok(None);
+    };
+
+    let frame = Frame {
+      window_id: Some(window_id),
+      command_buffer: sync.command_buffer,
+      image: swapchain.images()[image_index as usize],
+      image_view: swapchain.image_views()[image_index as usize],
+      image_index,
+      extent: swapchain.extent(),
+      format: swapchain.format().format,
+    };
+
+    // the image is acquired, so from here on the frame must be abandoned on failure
+    if let Err(err) = Self::begin_frame_commands(&device, sync) {
+      self.abandon_frame(window_id);
+      return Err(err);
+    }
+
+    self.record_clear(&device, &frame);
+
+    Ok(Some(frame))
+  }
+
+  fn begin_frame_commands(device: &ash::Device, sync: &FrameSync) -> Result<()> {
+    unsafe {
+      device.reset_command_buffer(sync.command_buffer, vk::CommandBufferResetFlags::empty())
+    }.context("Failed to reset frame command buffer")?;
+
+    let begin_info = vk::CommandBufferBeginInfo {
+      flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+      ..Default::default()
+    };
+    unsafe {
+      device.begin_command_buffer(sync.command_buffer, &begin_info)
+    }.context("Failed to begin frame command buffer")?;
+
+    // only reset once work is guaranteed to be submitted, or the next wait would deadlock
+    unsafe {
+      device.reset_fences(&[sync.in_flight])
+    }.context("Failed to reset frame fence")
+  }
+
+  /// Finishes recording the frame, submits it to the graphics queue and presents it.
+  /// A frame that fails before being submitted is abandoned, so its slot can still be waited on.
+  pub(crate) fn end_frame(&mut self, frame: Frame) -> Result<()> {
+    let window_id = frame.window_id.context("Offscreen frames are not presented")?;
+    let render_finished = match self.submit_frame(window_id, &frame) {
+      Ok(render_finished) => render_finished,
+      Err(err) => {
+        self.abandon_frame(window_id);
+        return Err(err);
+      }
+    };
+
+    self.frames.get_mut(&window_id).context("Frame has no resources")?.advance();
+    self.windows.get_mut(&window_id)
+      .and_then(|w| w.swapchain_mut())
+      .context("Window has no swapchain")?
+      .present(*self.context.present_queue(), frame.image_index, &[render_finished])
+  }
+
+  /// Returns the semaphore signaled once the frame has rendered.
+  fn submit_frame(&mut self, window_id: WindowId, frame: &Frame) -> Result<vk::Semaphore> {
+    let device = self.context.device();
+
+    let mut layout = vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL;
+    let mut captures = vec![];
+    let (requests, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.capture_requests)
+      .into_iter()
+      .partition(|r| r.window_id == Some(window_id));
+    self.capture_requests = pending;
+    for request in requests {
+      match create_readback_buffer(&self.context, frame.extent, frame.format) {
+        Ok(buffer) => {
+          record_image_copy(
+            &device,
+            frame.command_buffer,
+            frame.image,
+            layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            frame.extent,
+            &buffer,
+          );
+          layout = vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
+          captures.push(request.in_flight(buffer, frame.extent, frame.format));
+        }
+        Err(err) => request.fail(err),
+      }
+    }
+
+    transition_layout(
+      &device,
+      frame.command_buffer,
+      frame.image,
+      Self::color_range(),
+      layout,
+      vk::ImageLayout::PRESENT_SRC_KHR,
+    );
+
+    let frames = self.frames.get_mut(&window_id).context("Frame has no resources")?;
+    frames.current_mut().captures.extend(captures);
+    let sync = frames.current();
+
+    unsafe {
+      device.end_command_buffer(frame.command_buffer)
+    }.context("Failed to end frame command buffer")?;
+
+    let wait_stages = [vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+    let submit_info = vk::SubmitInfo {
+      wait_semaphore_count: 1,
+      p_wait_semaphores: &sync.image_available,
+      p_wait_dst_stage_mask: wait_stages.as_ptr(),
+      command_buffer_count: 1,
+      p_command_buffers: &frame.command_buffer,
+      signal_semaphore_count: 1,
+      p_signal_semaphores: &sync.render_finished,
+      ..Default::default()
+    };
+
+    unsafe {
+      device.queue_submit(*self.context.graphics_queue(), &[submit_info], sync.in_flight)
+    }.context("Failed to submit frame")?;
+
+    Ok(sync.render_finished)
+  }
+
+  /// Signals the slot's fence with an empty batch, which also consumes the acquire semaphore,
+  /// so the next `begin_frame` doesn't wait forever. The acquired image is released by recreating the swapchain.
+  /// The fence may still be signaled if the frame failed before resetting it.
+  fn abandon_frame(&mut self, window_id: WindowId) {
+    let Some(frames) = self.frames.get_mut(&window_id) else {
+      return;
+    };
+    for capture in frames.current_mut().captures.drain(..) {
+      capture.fail(anyhow::anyhow!("Frame was abandoned"));
+    }
+
+    let device = self.context.device();
+    let sync = frames.current();
+    let wait_stage = vk::PipelineStageFlags::ALL_COMMANDS;
+    let submit_info = vk::SubmitInfo {
+      wait_semaphore_count: 1,
+      p_wait_semaphores: &sync.image_available,
+      p_wait_dst_stage_mask: &wait_stage,
+      ..Default::default()
+    };
+    // nothing is pending on the slot, so a still signaled fence can be reset here
+    let submitted = unsafe {
+      match device.get_fence_status(sync.in_flight) {
+        Ok(true) => device.reset_fences(&[sync.in_flight]),
+        _ => Ok(()),
+      }.and_then(|_| device.queue_submit(*self.context.graphics_queue(), &[submit_info], sync.in_flight))
+    };
+    if let Err(err) = submitted {
+      error!("Failed to signal abandoned frame: {err}");
+      // nothing may still use the fence or semaphore once they're replaced
+      let recreated = unsafe { device.device_wait_idle() }
+        .context("Failed to wait for device idle")
+        .and_then(|_| frames.recreate_sync());
+      if let Err(err) = recreated {
+        error!("Failed to recreate frame synchronization: {err:#}");
+      }
+    }
+
+    if let Some(swapchain) = self.windows.get_mut(&window_id).and_then(|w| w.swapchain_mut()) {
+      swapchain.mark_out_of_date();
+    }
+  }
+
+  /// Draws `meshes` into the frame with the render pipeline. Does nothing without a pipeline.
+  ///
+  /// Works for window frames and, with a pipeline without a render pass, inside `render_offscreen`.
+  /// Meshes whose topology or vertex type don't match the pipeline are skipped, with a warning logged once per mesh.
+  pub fn draw_meshes<'m>(&self, frame: &Frame, meshes: impl IntoIterator<Item = &'m GpuMesh>) -> Result<()> {
+    let Some(pipeline) = &self.pipeline else {
+      return Ok(());
+    };
+    let topology = vk::PrimitiveTopology::from(pipeline.config().state.topology);
+    let vertex_layout = &pipeline.config().vertex_layout;
+    let meshes: Vec<_> = meshes
+      .into_iter()
+      .filter(|mesh| {
+        let matches = mesh.topology() == topology && mesh.vertex_layout() == vertex_layout;
+        if !matches && mesh.report_skipped() {
+          warn!(
+            "Skipping mesh with {:?} and {:?}, which don't match the render pipeline's {topology:?} and {vertex_layout:?}",
+            mesh.topology(),
+            mesh.vertex_layout(),
+          );
+        }
+        matches
+      })
+      .collect();
+
+    if meshes.is_empty() {
+      return Ok(());
+    }
+    self.record_meshes(pipeline, frame, &meshes)
+  }
+
+  fn record_meshes(&self, pipeline: &RenderPipeline, frame: &Frame, meshes: &[&GpuMesh]) -> Result<()> {
+    let formats = pipeline.attachment_formats()?;
+    if formats.color.as_slice() != [frame.format] || formats.depth.is_some() {
+      anyhow::bail!(
+        "Pipeline renders to {:?} color and {:?} depth, but frames only have one {:?} color attachment",
+        formats.color,
+        formats.depth,
+        frame.format,
+      );
+    }
+
+    let device = self.context.device();
+    match &pipeline.config().render_pass {
+      Some(render_pass) => {
+        let window_id = frame.window_id.context("Render passes can only draw into window frames")?;
+        let attachment = render_pass.attachments()[0];
+        if attachment.final_layout != vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL {
+          anyhow::bail!("Render pass must leave the frame in COLOR_ATTACHMENT_OPTIMAL, not {:?}", attachment.final_layout);
+        }
+
+        let swapchain = self.windows.get(&window_id)
+          .and_then(|w| w.swapchain())
+          .context("Window has no swapchain")?;
+        let mut framebuffers = self.framebuffers.lock().unwrap();
+        if framebuffers.get(&window_id).is_none_or(|f| f.is_stale(render_pass, swapchain)) {
+          framebuffers.insert(window_id, SwapchainFramebuffers::new(&self.context, render_pass, swapchain, &[])?);
+        }
+        let framebuffer = framebuffers[&window_id].get(frame.image_index).context("No framebuffer for swapchain image")?;
+
+        let clear_values = [vk::ClearValue { color: vk::ClearColorValue { float32: self.clear_color } }];
+        render_pass.begin(&device, frame.command_buffer, framebuffer, &clear_values);
+        pipeline.bind(&device, frame.command_buffer, frame.extent);
+        for mesh in meshes {
+          mesh.draw(&device, frame.command_buffer);
+        }
+        render_pass.end(&device, frame.command_buffer);
+      }
+      None => {
+        let color_attachment = vk::RenderingAttachmentInfo {
+          image_view: frame.image_view,
+          image_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+          // the frame was already cleared to `clear_color`
+          load_op: vk::AttachmentLoadOp::LOAD,
+          store_op: vk::AttachmentStoreOp::STORE,
+          ..Default::default()
+        };
+        let rendering_info = vk::RenderingInfo {
+          render_area: vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: frame.extent,
+          },
+          layer_count: 1,
+          color_attachment_count: 1,
+          p_color_attachments: &color_attachment,
+          ..Default::default()
+        };
+
+        self.context.cmd_begin_rendering(frame.command_buffer, &rendering_info);
+        pipeline.bind(&device, frame.command_buffer, frame.extent);
+        for mesh in meshes {
+          mesh.draw(&device, frame.command_buffer);
+        }
+        self.context.cmd_end_rendering(frame.command_buffer);
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Compiles `graph` and records it into the frame. Transient images live until the frame's gpu work is done.
+  pub fn execute_graph(&mut self, frame: &Frame, graph: RenderGraph<'_>) -> Result<()> {
+    let window_id = frame.window_id.context("Graphs can only be executed on window frames, offscreen frames must use `CompiledGraph::execute`")?;
+    let compiled = graph.compile(&self.context).context("Failed to compile render graph")?;
+    let resources = compiled.execute(&self.context.device(), frame.command_buffer);
+    self.frames.get_mut(&window_id)
+      .context("Frame has no resources")?
+      .current_mut()
+      .graph_resources
+      .push(resources);
+    Ok(())
+  }
+
+  /// Clears `target` and records `draw` into it, leaving the result ready for `RenderTarget::read_pixels`.
+  /// Blocks until the gpu has finished. Nothing is submitted if `draw` fails.
+  pub fn render_offscreen<F: FnOnce(&Frame) -> Result<()>>(&self, target: &mut RenderTarget, draw: F) -> Result<()> {
+    let device = self.context.device();
+    let command_buffer = self.context.begin_single_time_commands()?;
 
-  pub(crate) fn reset_frame(&mut self) {}
+    let frame = Frame {
+      window_id: None,
+      command_buffer,
+      image: target.image().image,
+      image_view: target.image_view(),
+      image_index: 0,
+      extent: target.extent(),
+      format: target.format(),
+    };
+
+    self.record_clear(&device, &frame);
+    if let Err(err) = draw(&frame) {
+      // never submitted, so it can be freed while still recording
+      unsafe {
+        device.free_command_buffers(*self.context.command_pool(), &[command_buffer]);
+      }
+      return Err(err);
+    }
+    transition_layout(
+      &device,
+      command_buffer,
+      frame.image,
+      Self::color_range(),
+      vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+      vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    );
+
+    self.context.end_single_time_commands(command_buffer)?;
+    target.set_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+
+    Ok(())
+  }
+
+  /// Captures the next frame presented to the window, i.e. the one rendered after this call.
+  /// Swapchain images belong to the presentation engine once presented, so earlier frames can't be read.
+  /// The pixels are converted to RGBA once the gpu is done with the frame, without stalling the frame loop.
+  pub fn capture_next_frame(&mut self, window_id: WindowId) -> FrameCapture {
+    let (request, capture) = CaptureRequest::new(Some(window_id), None);
+    self.capture_requests.push(request);
+    capture
+  }
+
+  /// Like `capture_next_frame`, additionally writing the pixels to a png file in the background.
+  pub fn capture_next_frame_to_png(&mut self, window_id: WindowId, path: impl Into<PathBuf>) -> FrameCapture {
+    let (request, capture) = CaptureRequest::new(Some(window_id), Some(path.into()));
+    self.capture_requests.push(request);
+    capture
+  }
+
+  /// Captures a render target's current contents without waiting for the copy.
+  /// Completes as frames are rendered, or on `poll_captures`. Use `RenderTarget::read_pixels` to block instead.
+  pub fn capture_target(&mut self, target: &RenderTarget) -> Result<FrameCapture> {
+    self.submit_target_capture(target, None)
+  }
+
+  /// Like `capture_target`, additionally writing the pixels to a png file in the background.
+  pub fn capture_target_to_png(&mut self, target: &RenderTarget, path: impl Into<PathBuf>) -> Result<FrameCapture> {
+    self.submit_target_capture(target, Some(path.into()))
+  }
+
+  /// Hands out every capture the gpu has finished, without blocking. Only needed without a frame loop.
+  pub fn poll_captures(&mut self) {
+    for frames in self.frames.values_mut() {
+      frames.resolve_captures();
+    }
+    self.resolve_target_captures();
+  }
+
+  fn submit_target_capture(&mut self, target: &RenderTarget, path: Option<PathBuf>) -> Result<FrameCapture> {
+    if target.layout() == vk::ImageLayout::UNDEFINED {
+      anyhow::bail!("Render target has not been rendered to");
+    }
+
+    let buffer = create_readback_buffer(&self.context, target.extent(), target.format())?;
+    let device = self.context.device();
+    let command_buffer = self.context.begin_single_time_commands()?;
+    record_image_copy(
+      &device,
+      command_buffer,
+      target.image().image,
+      target.layout(),
+      target.layout(),
+      target.extent(),
+      &buffer,
+    );
+    let fence = match self.context.submit_single_time_commands(command_buffer) {
+      Ok(fence) => fence,
+      Err(err) => unsafe {
+        device.free_command_buffers(*self.context.command_pool(), &[command_buffer]);
+        Err(err)?
+      }
+    };
+
+    let (request, capture) = CaptureRequest::new(None, path);
+    let in_flight = request.in_flight(buffer, target.extent(), target.format());
+    self.target_captures.push(SubmittedCapture::new(
+      device,
+      *self.context.command_pool(),
+      command_buffer,
+      fence,
+      in_flight,
+    ));
+    Ok(capture)
+  }
+
+  fn resolve_target_captures(&mut self) {
+    let (done, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.target_captures)
+      .into_iter()
+      .partition(SubmittedCapture::is_done);
+    self.target_captures = pending;
+    for capture in done {
+      capture.resolve();
+    }
+  }
+
+  /// Recreates swapchains invalidated while presenting, so the next frame starts with a valid one.
+  pub(crate) fn reset_frame(&mut self) {
+    self.poll_captures();
+
+    for window in self.windows.values_mut() {
+      if window.swapchain().is_some_and(|s| s.out_of_date()) {
+        if let Err(err) = window.prepare_swapchain(&self.context) {
+          error!("Failed to recreate swapchain: {err:#}");
+        }
+      }
+    }
+  }
+
+  /// Clears the frame's image to `clear_color`, leaving it in `COLOR_ATTACHMENT_OPTIMAL`.
+  fn record_clear(&self, device: &ash::Device, frame: &Frame) {
+    let range = Self::color_range();
+    transition_layout(
+      device,
+      frame.command_buffer,
+      frame.image,
+      range,
+      vk::ImageLayout::UNDEFINED,
+      vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+    unsafe {
+      device.cmd_clear_color_image(
+        frame.command_buffer,
+        frame.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &vk::ClearColorValue { float32: self.clear_color },
+        &[range],
+      );
+    }
+    transition_layout(
+      device,
+      frame.command_buffer,
+      frame.image,
+      range,
+      vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+      vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    );
+  }
+
+  fn color_range() -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: 1,
+      base_array_layer: 0,
+      layer_count: 1,
+    }
+  }
 
   pub fn context(&self) -> &RenderContext {
     &self.context
   }
+
+  /// Discards every cached pipeline, e.g. after a driver bug or to measure cold pipeline creation.
+  pub fn clear_pipeline_cache(&mut self) -> Result<()> {
+    self.context.clear_pipeline_cache()
+  }
 }
 
 impl Graphics {
   unsafe fn free(&mut self) {
     trace!("Cleaning up Graphics");
+    if let Err(err) = self.context.wait_idle() {
+      error!("{err:#}");
+    }
   }
 }
 
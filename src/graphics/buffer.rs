@@ -3,11 +3,14 @@ use ash::{self, vk};
 use anyhow::{Context, Result};
 use crate::graphics::context::RenderContext;
 use crate::graphics::image::Image;
+use crate::graphics::memory::Allocation;
+
+pub mod typed;
 
 pub struct Buffer {
   device: Arc<ash::Device>,
   pub buffer: vk::Buffer,
-  pub memory: vk::DeviceMemory,
+  allocation: Allocation,
   pub size: vk::DeviceSize,
 }
 
@@ -29,19 +32,7 @@ impl Buffer {
       context.device().create_buffer(&buffer_create_info, None)
     }.context("Failed to create buffer")?;
 
-    let memory_reqs = unsafe {
-      context.device().get_buffer_memory_requirements(buffer)
-    };
-
-    let memory_create_info = vk::MemoryAllocateInfo {
-      allocation_size: memory_reqs.size,
-      memory_type_index: context.find_memory_type(memory_reqs.memory_type_bits, properties).heap_index,
-      ..Default::default()
-    };
-
-    let memory = match unsafe {
-      context.device().allocate_memory(&memory_create_info, None)
-    }.context("Failed to allocate buffer memory") {
+    let allocation = match context.allocator().allocate_for_buffer(buffer, properties) {
       Ok(value) => value,
       Err(err) => unsafe {
         context.device().destroy_buffer(buffer, None);
@@ -52,59 +43,89 @@ impl Buffer {
     Ok(Self {
       device: context.device(),
       buffer,
-      memory,
+      allocation,
       size,
     })
   }
 
+  /// Host visible buffer holding `data`, to be copied into device local memory.
+  pub fn new_staging(context: &RenderContext, data: &[u8]) -> Result<Self> {
+    let buffer = Self::new(
+      context,
+      data.len() as vk::DeviceSize,
+      vk::BufferUsageFlags::TRANSFER_SRC,
+      vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    buffer.write(0, data)?;
+    Ok(buffer)
+  }
+
+  /// Writes `data` at byte `offset`. Only valid for host visible buffers, which stay mapped for their whole lifetime.
+  pub fn write(&self, offset: vk::DeviceSize, data: &[u8]) -> Result<()> {
+    self.allocation.write(offset, data).context("Failed to write buffer")
+  }
+
+  pub fn device(&self) -> &ash::Device {
+    &self.device
+  }
+
+  pub fn allocation(&self) -> &Allocation {
+    &self.allocation
+  }
+
   unsafe fn free(&mut self) {
+    // the allocation is returned to its pool once the field drops
     self.device.destroy_buffer(self.buffer, None);
-    self.device.free_memory(self.memory, None);
   }
 
-  pub fn copy_to_buffer(&self, context: &RenderContext, dst: &Buffer) {
-    context.issue_single_time_commands(|command_buffer| {
-      let copy_region = vk::BufferCopy {
-        size: self.size,
-        ..Default::default()
-      };
+  pub fn copy_to_buffer(&self, context: &RenderContext, dst: &Buffer) -> Result<()> {
+    self.copy_range_to_buffer(context, dst, vk::BufferCopy {
+      size: self.size.min(dst.size),
+      ..Default::default()
+    })
+  }
 
-      unsafe {
-        self.device.cmd_copy_buffer(command_buffer, self.buffer, dst.buffer, &[copy_region]);
-      }
-    });
+  /// Blocks until the copy has finished.
+  pub fn copy_range_to_buffer(&self, context: &RenderContext, dst: &Buffer, region: vk::BufferCopy) -> Result<()> {
+    let command_buffer = context.begin_single_time_commands()?;
+    unsafe {
+      self.device.cmd_copy_buffer(command_buffer, self.buffer, dst.buffer, &[region]);
+    }
+    context.end_single_time_commands(command_buffer)
   }
 
-  pub fn copy_to_image(
-    &self,
-    context: &RenderContext,
-    image: &Image,
-  ) {
-    context.issue_single_time_commands(|command_buffer| {
-      let copy_region = vk::BufferImageCopy {
-        image_subresource: vk::ImageSubresourceLayers {
-          aspect_mask: vk::ImageAspectFlags::COLOR,
-          layer_count: image.layer_count,
-          ..Default::default()
-        },
-        image_extent: vk::Extent3D {
-          width: image.extent.width,
-          height: image.extent.height,
-          depth: 1,
-        },
+  /// Blocks until the copy has finished. The image must be in `TRANSFER_DST_OPTIMAL`.
+  pub fn copy_to_image(&self, context: &RenderContext, image: &Image) -> Result<()> {
+    let command_buffer = context.begin_single_time_commands()?;
+    self.record_copy_to_image(command_buffer, image);
+    context.end_single_time_commands(command_buffer)
+  }
+
+  /// Records a copy into every layer of `image`'s first mip level. The image must be in `TRANSFER_DST_OPTIMAL`.
+  pub fn record_copy_to_image(&self, command_buffer: vk::CommandBuffer, image: &Image) {
+    let copy_region = vk::BufferImageCopy {
+      image_subresource: vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        layer_count: image.layer_count,
         ..Default::default()
-      };
-
-      unsafe {
-        self.device.cmd_copy_buffer_to_image(
-          command_buffer,
-          self.buffer,
-          image.image,
-          vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-          &[copy_region],
-        );
-      }
-    });
+      },
+      image_extent: vk::Extent3D {
+        width: image.extent.width,
+        height: image.extent.height,
+        depth: 1,
+      },
+      ..Default::default()
+    };
+
+    unsafe {
+      self.device.cmd_copy_buffer_to_image(
+        command_buffer,
+        self.buffer,
+        image.image,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[copy_region],
+      );
+    }
   }
 }
 
@@ -0,0 +1,256 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+use anyhow::{Context, Result};
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::context::RenderContext;
+
+/// Where a typed buffer's memory lives, deciding how updates reach the gpu.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum BufferMode {
+  /// Device local memory, filled through a staging buffer. Best for data that rarely changes.
+  #[default]
+  Static,
+  /// Host visible memory that stays mapped, so updates are plain memory writes.
+  /// Best for data that changes every frame.
+  Mapped,
+}
+
+/// A buffer holding a contiguous array of `T`.
+///
+/// Updates to a `Static` buffer wait for the gpu to go idle, while `Mapped` updates are written immediately.
+/// Either way, the caller must make sure no frame in flight still reads the updated range.
+pub struct TypedBuffer<T: Pod> {
+  buffer: Buffer,
+  len: usize,
+  mode: BufferMode,
+  _marker: PhantomData<T>,
+}
+
+impl<T: Pod> TypedBuffer<T> {
+  pub fn new(context: &RenderContext, data: &[T], usage: vk::BufferUsageFlags, mode: BufferMode) -> Result<Self> {
+    if data.is_empty() {
+      anyhow::bail!("Cannot create an empty buffer");
+    }
+
+    let bytes: &[u8] = bytemuck::cast_slice(data);
+    let size = bytes.len() as vk::DeviceSize;
+
+    let buffer = match mode {
+      BufferMode::Static => {
+        let buffer = Buffer::new(
+          context,
+          size,
+          usage | vk::BufferUsageFlags::TRANSFER_DST,
+          vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        Buffer::new_staging(context, bytes)?
+          .copy_to_buffer(context, &buffer)
+          .context("Failed to upload buffer data")?;
+        buffer
+      }
+      BufferMode::Mapped => {
+        let buffer = Buffer::new(
+          context,
+          size,
+          usage,
+          vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        buffer.write(0, bytes)?;
+        buffer
+      }
+    };
+
+    Ok(Self {
+      buffer,
+      len: data.len(),
+      mode,
+      _marker: PhantomData,
+    })
+  }
+
+  /// Overwrites the elements starting at index `offset` with `data`.
+  pub fn update(&self, context: &RenderContext, offset: usize, data: &[T]) -> Result<()> {
+    let end = offset.checked_add(data.len())
+      .with_context(|| format!("Update of {} elements at {offset} overflows", data.len()))?;
+    if end > self.len {
+      anyhow::bail!("Update of {offset}..{end} is out of bounds of a buffer with {} elements", self.len);
+    }
+    if data.is_empty() {
+      return Ok(());
+    }
+
+    let bytes: &[u8] = bytemuck::cast_slice(data);
+    let byte_offset = (offset * std::mem::size_of::<T>()) as vk::DeviceSize;
+
+    match self.mode {
+      BufferMode::Static => Buffer::new_staging(context, bytes)?.copy_range_to_buffer(context, &self.buffer, vk::BufferCopy {
+        src_offset: 0,
+        dst_offset: byte_offset,
+        size: bytes.len() as vk::DeviceSize,
+      }),
+      BufferMode::Mapped => self.buffer.write(byte_offset, bytes),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  pub fn mode(&self) -> BufferMode {
+    self.mode
+  }
+
+  pub fn buffer(&self) -> &Buffer {
+    &self.buffer
+  }
+
+  pub fn handle(&self) -> vk::Buffer {
+    self.buffer.buffer
+  }
+
+  /// Drops the element type, e.g. to keep buffers of different vertex types side by side.
+  pub fn into_buffer(self) -> Buffer {
+    self.buffer
+  }
+}
+
+pub struct VertexBuffer<T: Pod>(TypedBuffer<T>);
+
+impl<T: Pod> VertexBuffer<T> {
+  pub fn new(context: &RenderContext, vertices: &[T]) -> Result<Self> {
+    Self::with_mode(context, vertices, BufferMode::Static)
+  }
+
+  pub fn with_mode(context: &RenderContext, vertices: &[T], mode: BufferMode) -> Result<Self> {
+    TypedBuffer::new(context, vertices, vk::BufferUsageFlags::VERTEX_BUFFER, mode).map(Self)
+  }
+}
+
+impl<T: Pod> Deref for VertexBuffer<T> {
+  type Target = TypedBuffer<T>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+mod sealed {
+  pub trait Sealed {}
+  impl Sealed for u16 {}
+  impl Sealed for u32 {}
+}
+
+/// Integer types usable as indices, `u16` or `u32`.
+pub trait Index: Pod + sealed::Sealed {
+  const INDEX_TYPE: vk::IndexType;
+}
+
+impl Index for u16 {
+  const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT16;
+}
+
+impl Index for u32 {
+  const INDEX_TYPE: vk::IndexType = vk::IndexType::UINT32;
+}
+
+pub struct IndexBuffer<I: Index>(TypedBuffer<I>);
+
+impl<I: Index> IndexBuffer<I> {
+  pub fn new(context: &RenderContext, indices: &[I]) -> Result<Self> {
+    Self::with_mode(context, indices, BufferMode::Static)
+  }
+
+  pub fn with_mode(context: &RenderContext, indices: &[I], mode: BufferMode) -> Result<Self> {
+    TypedBuffer::new(context, indices, vk::BufferUsageFlags::INDEX_BUFFER, mode).map(Self)
+  }
+
+  pub fn index_type(&self) -> vk::IndexType {
+    I::INDEX_TYPE
+  }
+}
+
+impl<I: Index> Deref for IndexBuffer<I> {
+  type Target = TypedBuffer<I>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+/// A single `T` exposed to shaders as a uniform block. Mapped by default, since uniforms usually change every frame.
+pub struct UniformBuffer<T: Pod>(TypedBuffer<T>);
+
+impl<T: Pod> UniformBuffer<T> {
+  pub fn new(context: &RenderContext, value: &T) -> Result<Self> {
+    Self::with_mode(context, value, BufferMode::Mapped)
+  }
+
+  pub fn with_mode(context: &RenderContext, value: &T, mode: BufferMode) -> Result<Self> {
+    TypedBuffer::new(context, std::slice::from_ref(value), vk::BufferUsageFlags::UNIFORM_BUFFER, mode).map(Self)
+  }
+
+  pub fn set(&self, context: &RenderContext, value: &T) -> Result<()> {
+    self.0.update(context, 0, std::slice::from_ref(value))
+  }
+}
+
+impl<T: Pod> Deref for UniformBuffer<T> {
+  type Target = TypedBuffer<T>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
+
+/// An array of `T` that shaders can read and write. Can also be copied out of for readback.
+pub struct StorageBuffer<T: Pod>(TypedBuffer<T>);
+
+impl<T: Pod> StorageBuffer<T> {
+  pub fn new(context: &RenderContext, data: &[T]) -> Result<Self> {
+    Self::with_mode(context, data, BufferMode::Static)
+  }
+
+  pub fn with_mode(context: &RenderContext, data: &[T], mode: BufferMode) -> Result<Self> {
+    let usage = vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC;
+    TypedBuffer::new(context, data, usage, mode).map(Self)
+  }
+
+  /// Copies the buffer's contents back to the host. Blocks until the copy is done.
+  /// The gpu must have finished writing to it, and the writes must be made available to transfers or the host.
+  pub fn read(&self, context: &RenderContext) -> Result<Vec<T>> {
+    let size = self.buffer().size;
+    let bytes = match self.mode() {
+      BufferMode::Static => {
+        let readback = Buffer::new(
+          context,
+          size,
+          vk::BufferUsageFlags::TRANSFER_DST,
+          vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        self.buffer().copy_to_buffer(context, &readback).context("Failed to copy storage buffer")?;
+        readback.allocation().read(0, size)?
+      }
+      BufferMode::Mapped => self.buffer().allocation().read(0, size)?,
+    };
+
+    // the bytes aren't necessarily aligned for `T`
+    let mut data = vec![T::zeroed(); self.len()];
+    bytemuck::cast_slice_mut(&mut data).copy_from_slice(&bytes);
+    Ok(data)
+  }
+}
+
+impl<T: Pod> Deref for StorageBuffer<T> {
+  type Target = TypedBuffer<T>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.0
+  }
+}
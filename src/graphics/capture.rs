@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use anyhow::{Context, Result};
+use ash::vk;
+use tracing::{error, info};
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::readback::{read_buffer, RgbaImage};
+use crate::graphics::window::WindowId;
+
+/// Pixels of a frame or render target that will be available once the gpu has finished with it.
+///
+/// Captures are handed out as frames keep being rendered. Without a frame loop, e.g. with headless graphics,
+/// `Graphics::poll_captures` has to be called for them to complete.
+pub struct FrameCapture {
+  receiver: mpsc::Receiver<Result<RgbaImage>>,
+}
+
+impl FrameCapture {
+  /// Returns the captured pixels once ready, or `None` while the frame is still in flight.
+  pub fn try_take(&self) -> Option<Result<RgbaImage>> {
+    match self.receiver.try_recv() {
+      Ok(result) => Some(result),
+      Err(mpsc::TryRecvError::Empty) => None,
+      Err(mpsc::TryRecvError::Disconnected) => Some(Err(anyhow::anyhow!("Frame capture was cancelled"))),
+    }
+  }
+
+  /// Blocks until the capture is ready. Must not be called from the thread running the frame loop or
+  /// polling captures, since the capture would never complete.
+  pub fn wait(self) -> Result<RgbaImage> {
+    self.receiver.recv().context("Frame capture was cancelled")?
+  }
+}
+
+/// A capture requested for a window's next presented frame, or for a render target when `window_id` is `None`.
+pub(crate) struct CaptureRequest {
+  pub window_id: Option<WindowId>,
+  pub path: Option<PathBuf>,
+  sender: mpsc::Sender<Result<RgbaImage>>,
+}
+
+impl CaptureRequest {
+  pub fn new(window_id: Option<WindowId>, path: Option<PathBuf>) -> (Self, FrameCapture) {
+    let (sender, receiver) = mpsc::channel();
+    (Self { window_id, path, sender }, FrameCapture { receiver })
+  }
+
+  pub fn fail(self, error: anyhow::Error) {
+    error!("Frame capture failed: {error:#}");
+    let _ = self.sender.send(Err(error));
+  }
+
+  pub fn in_flight(self, buffer: Buffer, extent: vk::Extent2D, format: vk::Format) -> InFlightCapture {
+    InFlightCapture {
+      request: self,
+      buffer,
+      extent,
+      format,
+    }
+  }
+}
+
+/// A capture whose copy has been recorded into a frame that hasn't finished on the gpu yet.
+pub(crate) struct InFlightCapture {
+  request: CaptureRequest,
+  buffer: Buffer,
+  extent: vk::Extent2D,
+  format: vk::Format,
+}
+
+impl InFlightCapture {
+  pub fn fail(self, error: anyhow::Error) {
+    self.request.fail(error);
+  }
+
+  /// Reads back the copied pixels. Only valid once the frame's fence has signaled.
+  pub fn resolve(self) {
+    let result = read_buffer(&self.buffer)
+      .and_then(|data| RgbaImage::from_raw(self.extent.width, self.extent.height, self.format, data));
+
+    if let (Ok(image), Some(path)) = (&result, self.request.path) {
+      // encoding is slow enough to hitch a frame, so keep it off the loop
+      let image = image.clone();
+      std::thread::spawn(move || match image.save_png(&path) {
+        Ok(_) => info!("Saved frame capture to {path:?}"),
+        Err(err) => error!("Failed to save frame capture: {err:#}"),
+      });
+    }
+
+    // nobody may be waiting for the pixels if the capture only went to a file
+    let _ = self.request.sender.send(result);
+  }
+}
+
+/// A capture of a render target copied by its own submission, resolved once `fence` signals.
+pub(crate) struct SubmittedCapture {
+  device: Arc<ash::Device>,
+  command_pool: vk::CommandPool,
+  command_buffer: vk::CommandBuffer,
+  fence: vk::Fence,
+  capture: Option<InFlightCapture>,
+}
+
+impl SubmittedCapture {
+  pub fn new(
+    device: Arc<ash::Device>,
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    capture: InFlightCapture,
+  ) -> Self {
+    Self {
+      device,
+      command_pool,
+      command_buffer,
+      fence,
+      capture: Some(capture),
+    }
+  }
+
+  pub fn is_done(&self) -> bool {
+    matches!(unsafe { self.device.get_fence_status(self.fence) }, Ok(true))
+  }
+
+  /// Only valid once `is_done`.
+  pub fn resolve(mut self) {
+    if let Some(capture) = self.capture.take() {
+      capture.resolve();
+    }
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_fence(self.fence, None);
+    self.device.free_command_buffers(self.command_pool, &[self.command_buffer]);
+  }
+}
+
+impl Drop for SubmittedCapture {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
@@ -4,52 +4,123 @@ use std::sync::Arc;
 
 use anyhow::{Context, Error, Result};
 use ash::{extensions::*, vk};
+use bytemuck::Pod;
 use raw_window_handle::HasRawDisplayHandle;
 use tracing::{debug, error, trace};
 
+use crate::graphics::buffer::typed::StorageBuffer;
 use crate::graphics::debug::DebugMessenger;
+use crate::graphics::descriptor::DescriptorSet;
+use crate::graphics::device::{DeviceCandidate, DeviceFeature, DeviceFeatures, DeviceSelection};
+use crate::graphics::memory::Allocator;
+use crate::graphics::pipeline::cache::PipelineCache;
+use crate::graphics::pipeline::compute::ComputePipeline;
+use crate::graphics::shader;
 use crate::graphics::shader::stage::Stage;
+use crate::graphics::texture::sampler::{Sampler, SamplerCache, SamplerDesc};
 use crate::graphics::window::Window;
 
 #[allow(unused)]
 pub struct RenderContext {
+  entry: ash::Entry,
   instance: ash::Instance,
   debug: Option<DebugMessenger>,
   physical_device: vk::PhysicalDevice,
+  queue_family_indices: QueueFamilyIndices,
+  instance_extensions: HashSet<&'static ffi::CStr>,
+  enabled_extensions: HashSet<String>,
+  enabled_features: HashSet<DeviceFeature>,
+  properties: vk::PhysicalDeviceProperties,
+  headless: bool,
+  /// Loaded when dynamic rendering comes from `VK_KHR_dynamic_rendering` rather than Vulkan 1.3.
+  dynamic_rendering: Option<khr::DynamicRendering>,
 
   device: Arc<ash::Device>,
+  allocator: Arc<Allocator>,
+  samplers: SamplerCache,
+  pipeline_cache: PipelineCache,
   command_pool: vk::CommandPool,
   graphics_queue: vk::Queue,
   present_queue: vk::Queue,
-
-  pub physical_device_properties: vk::PhysicalDeviceProperties,
 }
 
 static ENABLE_VALIDATION_LAYERS: OnceLock<bool> = OnceLock::new();
 static VALIDATION_LAYERS: OnceLock<HashSet<ffi::CString>> = OnceLock::new();
-static INSTANCE_EXTENSIONS: OnceLock<HashSet<&ffi::CStr>> = OnceLock::new();
-static DEVICE_EXTENSIONS: OnceLock<HashSet<&ffi::CStr>> = OnceLock::new();
 
 impl RenderContext {
-  pub fn new(window: &mut Window) -> Result<Self> {
+  const SWAPCHAIN_EXTENSION: &'static str = "VK_KHR_swapchain";
+
+  pub fn new(window: &mut Window, device_selection: &DeviceSelection) -> Result<Self> {
+    Self::create(Some(window), device_selection)
+  }
+
+  /// Creates a context without a window or surface, for offscreen rendering.
+  /// Present queue operations are unavailable, and the present queue aliases the graphics queue.
+  pub fn headless(device_selection: &DeviceSelection) -> Result<Self> {
+    Self::create(None, device_selection)
+  }
+
+  fn create(mut window: Option<&mut Window>, device_selection: &DeviceSelection) -> Result<Self> {
+    let mut device_selection = device_selection.clone().with_env_overrides()?;
+    if window.is_some() {
+      device_selection = device_selection.with_required_extension(Self::SWAPCHAIN_EXTENSION);
+    }
     let entry = ash::Entry::linked();
-    let instance = Self::create_instance(&entry, window)?;
+    Self::check_layers(&entry)?;
+    let instance_extensions = Self::check_extensions(window.as_deref())?;
+    let instance = Self::create_instance(&entry, window.as_deref(), &instance_extensions)?;
     let debug = Self::create_debug_messenger(&entry, &instance);
-    window.create_surface(&entry, &instance)?;
-    let physical_device = Self::pick_physical_device(window, &instance)?;
-    let device = Self::create_logical_device(window, &instance, physical_device)?;
-    let command_pool = Self::create_command_pool(window, &instance, &device, physical_device)?;
+    if let Some(window) = window.as_deref_mut() {
+      window.create_surface(&entry, &instance)?;
+    }
+    let window = window.as_deref();
+    let headless = window.is_none();
+    let candidate = Self::pick_physical_device(window, &instance, &device_selection)?;
+    let physical_device = candidate.handle;
+    let properties = candidate.properties;
+    let queue_family_indices = Self::find_queue_families(window, &instance, physical_device)?;
+    let enabled_features = device_selection.enabled_features(&candidate);
+    let enabled_extensions = device_selection.enabled_extensions(&candidate);
+    debug!("Enabled device features: {enabled_features:?}");
+    debug!("Enabled device extensions: {enabled_extensions:?}");
+    let device = Self::create_logical_device(
+      &instance,
+      &candidate,
+      queue_family_indices,
+      &enabled_extensions,
+      &enabled_features,
+    )?;
+    let dynamic_rendering = enabled_extensions.contains(DeviceFeatures::DYNAMIC_RENDERING_EXTENSION)
+      .then(|| khr::DynamicRendering::new(&instance, &device));
+    let allocator = Arc::new(Allocator::new(&instance, physical_device, device.clone()));
+    let max_anisotropy = enabled_features.contains(&DeviceFeature::SamplerAnisotropy)
+      .then_some(properties.limits.max_sampler_anisotropy);
+    let samplers = SamplerCache::new(device.clone(), max_anisotropy);
+    let pipeline_cache = PipelineCache::new(device.clone(), &properties, shader::cache_dir()?.join("pipeline_cache.bin"))?;
+    let command_pool = Self::create_command_pool(&device, queue_family_indices)?;
+    let graphics_queue = unsafe { device.get_device_queue(queue_family_indices.graphics_family, 0) };
+    let present_queue = unsafe { device.get_device_queue(queue_family_indices.present_family, 0) };
     Stage::set_static_entry_points()?;
 
     Ok(Self {
+      entry,
       instance,
       debug,
       physical_device,
+      queue_family_indices,
+      instance_extensions,
+      enabled_extensions,
+      enabled_features,
+      properties,
+      headless,
+      dynamic_rendering,
       device,
+      allocator,
+      samplers,
+      pipeline_cache,
       command_pool,
-      graphics_queue: Default::default(),
-      present_queue: Default::default(),
-      physical_device_properties: Default::default(),
+      graphics_queue,
+      present_queue,
     })
   }
 
@@ -57,6 +128,37 @@ impl RenderContext {
     *ENABLE_VALIDATION_LAYERS.get().unwrap()
   }
 
+  pub(crate) fn create_surface(&self, window: &mut Window) -> Result<()> {
+    window.create_surface(&self.entry, &self.instance)?;
+
+    let present_support = unsafe {
+      window.surface_loader().get_physical_device_surface_support(
+        self.physical_device,
+        self.queue_family_indices.present_family,
+        *window.surface(),
+      )
+    }.context("Failed to query surface support")?;
+
+    if !present_support {
+      anyhow::bail!("Chosen physical device cannot present to window surface");
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn instance(&self) -> &ash::Instance {
+    &self.instance
+  }
+
+  pub(crate) fn physical_device(&self) -> vk::PhysicalDevice {
+    self.physical_device
+  }
+
+  /// Graphics and present queue family indices, in that order.
+  pub(crate) fn queue_families(&self) -> (u32, u32) {
+    (self.queue_family_indices.graphics_family, self.queue_family_indices.present_family)
+  }
+
   pub fn command_pool(&self) -> &vk::CommandPool {
     &self.command_pool
   }
@@ -65,6 +167,32 @@ impl RenderContext {
     self.device.clone()
   }
 
+  pub fn allocator(&self) -> &Arc<Allocator> {
+    &self.allocator
+  }
+
+  /// The shared sampler for `desc`, created on first use.
+  pub fn sampler(&self, desc: SamplerDesc) -> Result<Arc<Sampler>> {
+    self.samplers.get(desc)
+  }
+
+  pub fn sampler_cache(&self) -> &SamplerCache {
+    &self.samplers
+  }
+
+  pub fn pipeline_cache(&self) -> &PipelineCache {
+    &self.pipeline_cache
+  }
+
+  /// Discards every cached pipeline, in memory and on disk.
+  pub fn clear_pipeline_cache(&mut self) -> Result<()> {
+    self.pipeline_cache.clear()
+  }
+
+  pub fn format_properties(&self, format: vk::Format) -> vk::FormatProperties {
+    unsafe { self.instance.get_physical_device_format_properties(self.physical_device, format) }
+  }
+
   pub fn graphics_queue(&self) -> &vk::Queue {
     &self.graphics_queue
   }
@@ -73,25 +201,84 @@ impl RenderContext {
     &self.present_queue
   }
 
-  pub fn find_memory_type(
-    &self,
-    type_filter: u32,
-    properties: vk::MemoryPropertyFlags,
-  ) -> vk::MemoryType {
-    let props = unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) };
+  pub fn wait_idle(&self) -> Result<()> {
+    unsafe {
+      self.device.device_wait_idle()
+    }.context("Failed to wait for device idle")
+  }
+
+  /// Whether `feature` was required, or optional and supported by the chosen device.
+  pub fn feature_enabled(&self, feature: DeviceFeature) -> bool {
+    self.enabled_features.contains(&feature)
+  }
+
+  pub fn enabled_features(&self) -> &HashSet<DeviceFeature> {
+    &self.enabled_features
+  }
+
+  /// Whether pipelines can render without a `vk::RenderPass`.
+  pub fn dynamic_rendering_enabled(&self) -> bool {
+    self.feature_enabled(DeviceFeature::DynamicRendering)
+  }
+
+  /// Starts dynamic rendering through Vulkan 1.3 or `VK_KHR_dynamic_rendering`, whichever the device uses.
+  /// Requires `DeviceFeature::DynamicRendering`.
+  pub fn cmd_begin_rendering(&self, command_buffer: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+    unsafe {
+      match &self.dynamic_rendering {
+        Some(loader) => loader.cmd_begin_rendering(command_buffer, rendering_info),
+        None => self.device.cmd_begin_rendering(command_buffer, rendering_info),
+      }
+    }
+  }
 
-    for mem_type in props.memory_types {
-      if (type_filter & (1 << mem_type.heap_index)) != 0 && mem_type.property_flags.contains(properties) {
-        return mem_type;
+  pub fn cmd_end_rendering(&self, command_buffer: vk::CommandBuffer) {
+    unsafe {
+      match &self.dynamic_rendering {
+        Some(loader) => loader.cmd_end_rendering(command_buffer),
+        None => self.device.cmd_end_rendering(command_buffer),
       }
     }
-    // for i in 0..props.memory_type_count as usize {
-    //   if (type_filter & (1 << i)) != 0 && props.memory_types[i].property_flags.contains(properties) {
-    //     return props.memory_types[i];
-    //   }
-    // }
-    error!("Failed to find supported memory type.");
-    vk::MemoryType::default()
+  }
+
+  pub fn extension_enabled(&self, name: &str) -> bool {
+    self.enabled_extensions.contains(name)
+  }
+
+  pub fn enabled_extensions(&self) -> &HashSet<String> {
+    &self.enabled_extensions
+  }
+
+  pub fn properties(&self) -> &vk::PhysicalDeviceProperties {
+    &self.properties
+  }
+
+  pub fn limits(&self) -> &vk::PhysicalDeviceLimits {
+    &self.properties.limits
+  }
+
+  pub fn device_name(&self) -> String {
+    unsafe { ffi::CStr::from_ptr(self.properties.device_name.as_ptr()) }
+      .to_string_lossy()
+      .into_owned()
+  }
+
+  /// The Vulkan version the device supports, e.g. `vk::API_VERSION_1_3`. Compare with `vk::api_version_*`.
+  pub fn api_version(&self) -> u32 {
+    self.properties.api_version
+  }
+
+  pub fn is_headless(&self) -> bool {
+    self.headless
+  }
+
+  /// Index of the first memory type allowed by `type_filter` that has all of `properties`.
+  pub fn find_memory_type_index(
+    &self,
+    type_filter: u32,
+    properties: vk::MemoryPropertyFlags,
+  ) -> Result<u32> {
+    self.allocator.find_memory_type_index(type_filter, properties)
   }
 
   pub fn begin_single_time_commands(&self) -> Result<vk::CommandBuffer> {
@@ -144,6 +331,66 @@ impl RenderContext {
     Ok(())
   }
 
+  /// Ends and submits `command_buffer` without waiting for it. The returned fence signals once the gpu is done,
+  /// after which both the fence and the command buffer, which came from `command_pool`, must be freed by the caller.
+  pub(crate) fn submit_single_time_commands(&self, command_buffer: vk::CommandBuffer) -> Result<vk::Fence> {
+    unsafe {
+      self.device.end_command_buffer(command_buffer)
+    }.context("Failed to end command buffer")?;
+
+    let fence = unsafe {
+      self.device.create_fence(&vk::FenceCreateInfo::default(), None)
+    }.context("Failed to create fence")?;
+
+    let submit_info = vk::SubmitInfo {
+      command_buffer_count: 1,
+      p_command_buffers: &command_buffer,
+      ..Default::default()
+    };
+    if let Err(err) = unsafe { self.device.queue_submit(self.graphics_queue, &[submit_info], fence) } {
+      unsafe { self.device.destroy_fence(fence, None) };
+      return Err(err).context("Failed to submit graphics queue");
+    }
+
+    Ok(fence)
+  }
+
+  /// Dispatches `workgroups` workgroups of `pipeline` with `descriptor_sets` bound from set 0,
+  /// then reads `output` back once the gpu is done. Blocks until then.
+  pub fn run_compute<T: Pod>(
+    &self,
+    pipeline: &ComputePipeline,
+    descriptor_sets: &[&DescriptorSet],
+    workgroups: [u32; 3],
+    output: &StorageBuffer<T>,
+  ) -> Result<Vec<T>> {
+    let command_buffer = self.begin_single_time_commands()?;
+    pipeline.bind(&self.device, command_buffer);
+    pipeline.bind_descriptor_sets(&self.device, command_buffer, descriptor_sets);
+    pipeline.dispatch(&self.device, command_buffer, workgroups);
+
+    // shader writes must be visible to the readback copy, or to the host for mapped buffers
+    let barrier = vk::MemoryBarrier {
+      src_access_mask: vk::AccessFlags::SHADER_WRITE,
+      dst_access_mask: vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::HOST_READ,
+      ..Default::default()
+    };
+    unsafe {
+      self.device.cmd_pipeline_barrier(
+        command_buffer,
+        vk::PipelineStageFlags::COMPUTE_SHADER,
+        vk::PipelineStageFlags::TRANSFER | vk::PipelineStageFlags::HOST,
+        vk::DependencyFlags::empty(),
+        &[barrier],
+        &[],
+        &[],
+      );
+    }
+    self.end_single_time_commands(command_buffer).context("Failed to run compute job")?;
+
+    output.read(self)
+  }
+
   pub fn issue_single_time_commands<F: FnOnce(vk::CommandBuffer)>(&self, commands: F) {
     match self.begin_single_time_commands() {
       Ok(command_buffer) => {
@@ -169,6 +416,9 @@ impl Drop for RenderContext {
 impl RenderContext {
   // PRIVATE
   unsafe fn free(&mut self) {
+    self.samplers.clear();
+    self.pipeline_cache.free();
+    self.allocator.destroy();
     self.device.destroy_command_pool(self.command_pool, None);
     self.device.destroy_device(None);
     if let Some(db) = self.debug.as_mut() {
@@ -177,21 +427,25 @@ impl RenderContext {
     self.instance.destroy_instance(None);
   }
 
-  fn create_instance(entry: &ash::Entry, window: &Window) -> Result<ash::Instance> {
+  fn create_instance(
+    entry: &ash::Entry,
+    window: Option<&Window>,
+    instance_extensions: &HashSet<&'static ffi::CStr>,
+  ) -> Result<ash::Instance> {
+    // the names must outlive `create_instance`, which reads them through `app_info`
+    let engine_name = ffi::CString::new("Koyote")?;
+    let application_name = ffi::CString::new(window.map_or("Koyote".to_owned(), |w| w.title()))?;
     let app_info = vk::ApplicationInfo {
-      p_engine_name: ffi::CString::new("Koyote")?.as_ptr(),
+      p_engine_name: engine_name.as_ptr(),
       engine_version: vk::make_api_version(1, 0, 1, 0),
-      p_application_name: ffi::CString::new(window.title())?.as_ptr(),
+      p_application_name: application_name.as_ptr(),
       application_version: vk::make_api_version(1, 0, 0, 0),
       api_version: vk::API_VERSION_1_3,
       ..Default::default()
     };
 
-    Self::check_layers(entry)?;
-    Self::check_extensions(window)?;
-
     let enabled_layers = Self::enabled_layers();
-    let enabled_instance_extensions = Self::enabled_instance_extensions_c_chars();
+    let enabled_instance_extensions = Self::c_chars(instance_extensions);
 
     let create_info = vk::InstanceCreateInfo {
       enabled_extension_count: enabled_instance_extensions.len() as u32,
@@ -208,6 +462,11 @@ impl RenderContext {
   }
 
   fn check_layers(entry: &ash::Entry) -> Result<()> {
+    // layers are process wide, so later contexts reuse the first one's result
+    if VALIDATION_LAYERS.get().is_some() {
+      return Ok(());
+    }
+
     match VALIDATION_LAYERS.set({
       let mut v: HashSet<ffi::CString> = Default::default();
       let mut layers_enabled = cfg!(debug_assertions);
@@ -256,60 +515,28 @@ impl RenderContext {
       .collect()
   }
 
-  fn check_extensions(window: &Window) -> Result<()> {
-    if INSTANCE_EXTENSIONS.set({
-      let mut v: HashSet<&ffi::CStr> = Default::default();
-      if Self::validation_layers_enabled() {
-        v.insert(ext::DebugUtils::name());
-      }
+  /// Instance extensions to enable. Surface extensions are only needed with a window.
+  fn check_extensions(
+    window: Option<&Window>,
+  ) -> Result<HashSet<&'static ffi::CStr>> {
+    let mut instance_extensions: HashSet<&'static ffi::CStr> = Default::default();
+    if Self::validation_layers_enabled() {
+      instance_extensions.insert(ext::DebugUtils::name());
+    }
 
+    if let Some(window) = window {
       for ext_name in ash_window::enumerate_required_extensions(
         window.winit().raw_display_handle()
       )? {
-        v.insert(unsafe { ffi::CStr::from_ptr(*ext_name) });
+        instance_extensions.insert(unsafe { ffi::CStr::from_ptr(*ext_name) });
       }
-
-      v
-    }).is_err() {
-      anyhow::bail!("Failed to set instance extensions HashSet");
-    }
-
-    if DEVICE_EXTENSIONS.set({
-      let mut v: HashSet<&ffi::CStr> = Default::default();
-
-      v.extend([
-        khr::Swapchain::name(),
-
-        // ffi::CString::new("VK_KHR_driver_properties").unwrap().as_c_str()
-      ]);
-
-      v
-    }).is_err() {
-      anyhow::bail!("Failed to set instance extensions HashSet");
     }
 
-    Ok(())
-  }
-
-  fn enabled_instance_extensions() -> &'static HashSet<&'static ffi::CStr> {
-    INSTANCE_EXTENSIONS.get()
-      .expect("Failed to get INSTANCE_EXTENSIONS variable")
+    Ok(instance_extensions)
   }
 
-  fn enabled_instance_extensions_c_chars() -> Vec<*const ffi::c_char> {
-    Self::enabled_instance_extensions()
-      .iter()
-      .map(|e| e.as_ptr())
-      .collect()
-  }
-
-  fn enabled_device_extensions() -> &'static HashSet<&'static ffi::CStr> {
-    DEVICE_EXTENSIONS.get()
-      .expect("Failed to get DEVICE_EXTENSIONS variable")
-  }
-
-  fn enabled_device_extensions_c_chars() -> Vec<*const ffi::c_char> {
-    Self::enabled_device_extensions()
+  fn c_chars(extensions: &HashSet<&'static ffi::CStr>) -> Vec<*const ffi::c_char> {
+    extensions
       .iter()
       .map(|e| e.as_ptr())
       .collect()
@@ -329,44 +556,38 @@ impl RenderContext {
     }
   }
 
-  fn pick_physical_device(window: &Window, instance: &ash::Instance) -> Result<vk::PhysicalDevice> {
+  fn pick_physical_device(
+    window: Option<&Window>,
+    instance: &ash::Instance,
+    device_selection: &DeviceSelection,
+  ) -> Result<DeviceCandidate> {
     let physical_devices = unsafe {
       instance.enumerate_physical_devices()
     }.context("Failed to enumerate physical devices")?;
     debug!("Physical device count: {}", physical_devices.len());
 
-    let physical_device = physical_devices
-      .iter()
-      .filter(|p| Self::device_suitable(window, instance, **p))
-      .min_by_key(|p| unsafe {
-        // lower score for preferred device types
-        match instance.get_physical_device_properties(**p).device_type {
-          vk::PhysicalDeviceType::DISCRETE_GPU => 0,
-          vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
-          vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
-          vk::PhysicalDeviceType::CPU => 3,
-          vk::PhysicalDeviceType::OTHER => 4,
-          _ => 5,
-        }
-      }).context("Failed to find valid physical device")?;
-
-    let props = unsafe {
-      instance.get_physical_device_properties(*physical_device)
-    };
-    let device_name = unsafe {
-      ffi::CStr::from_ptr(props.device_name.as_ptr()).to_str().unwrap()
-    };
-    debug!("Chosen device: [{}]", device_name);
+    let candidates = physical_devices.into_iter()
+      .enumerate()
+      .map(|(index, physical_device)| {
+        let candidate = DeviceCandidate::new(instance, index, physical_device)?;
+        debug!("Checking if suitable: [{}] ({:?})", candidate.name, candidate.device_type);
+        let suitable = Self::device_suitable(window, instance, physical_device);
+        Ok((candidate, suitable))
+      })
+      .collect::<Result<_>>()?;
+    let candidate = device_selection.select(candidates)?;
+    debug!("Chosen device: [{}] ({:?})", candidate.name, candidate.device_type);
 
-    Ok(*physical_device)
+    Ok(candidate)
   }
 
   fn create_logical_device(
-    window: &Window,
     instance: &ash::Instance,
-    physical_device: vk::PhysicalDevice,
+    candidate: &DeviceCandidate,
+    indices: QueueFamilyIndices,
+    enabled_extensions: &HashSet<String>,
+    enabled_features: &HashSet<DeviceFeature>,
   ) -> Result<Arc<ash::Device>> {
-    let indices = Self::find_queue_families(window, instance, physical_device)?;
     let mut queue_create_infos: Vec<vk::DeviceQueueCreateInfo> = vec![];
     let unique_queue_families: HashSet<u32> = HashSet::from([
       indices.graphics_family,
@@ -384,37 +605,57 @@ impl RenderContext {
       queue_create_infos.push(queue_create_info);
     }
 
-    let device_features = vk::PhysicalDeviceFeatures {
-      sampler_anisotropy: vk::TRUE,
+    let mut device_features = DeviceFeatures::default();
+    for feature in enabled_features {
+      feature.enable(&mut device_features);
+    }
+
+    // older devices must not see the feature structs of versions they don't implement
+    let api_version = candidate.properties.api_version;
+    let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures {
+      dynamic_rendering: device_features.vulkan_13.dynamic_rendering,
       ..Default::default()
     };
+    if api_version >= vk::API_VERSION_1_3 {
+      device_features.vulkan_12.p_next = &mut device_features.vulkan_13 as *mut _ as *mut ffi::c_void;
+    } else if candidate.features.dynamic_rendering_extension && dynamic_rendering.dynamic_rendering == vk::TRUE {
+      device_features.vulkan_12.p_next = &mut dynamic_rendering as *mut _ as *mut ffi::c_void;
+    }
+    let p_next = if api_version >= vk::API_VERSION_1_2 {
+      &device_features.vulkan_12 as *const _ as *const ffi::c_void
+    } else {
+      std::ptr::null()
+    };
 
-    let enabled_device_extensions = Self::enabled_device_extensions_c_chars();
+    let extension_names = enabled_extensions.iter()
+      .map(|name| ffi::CString::new(name.as_str()))
+      .collect::<Result<Vec<_>, _>>()
+      .context("Device extension name contains a nul byte")?;
+    let enabled_device_extensions: Vec<*const ffi::c_char> = extension_names.iter()
+      .map(|name| name.as_ptr())
+      .collect();
 
     let create_info = vk::DeviceCreateInfo {
+      p_next,
       queue_create_info_count: queue_create_infos.len() as u32,
       p_queue_create_infos: queue_create_infos.as_ptr(),
-      p_enabled_features: &device_features,
+      p_enabled_features: &device_features.core,
       enabled_extension_count: enabled_device_extensions.len() as u32,
       pp_enabled_extension_names: enabled_device_extensions.as_ptr(),
       ..Default::default()
     };
 
     let device = unsafe {
-      instance.create_device(physical_device, &create_info, None)
+      instance.create_device(candidate.handle, &create_info, None)
     }.context("Failed to create logical graphics device")?;
 
     Ok(Arc::new(device))
   }
 
   fn create_command_pool(
-    window: &Window,
-    instance: &ash::Instance,
     device: &ash::Device,
-    physical_device: vk::PhysicalDevice,
+    indices: QueueFamilyIndices,
   ) -> Result<vk::CommandPool> {
-    let indices = Self::find_queue_families(window, instance, physical_device)?;
-
     let create_info = vk::CommandPoolCreateInfo {
       queue_family_index: indices.graphics_family,
       flags: vk::CommandPoolCreateFlags::TRANSIENT | vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
@@ -426,66 +667,27 @@ impl RenderContext {
     }.context("Failed to create command pool")
   }
 
-  fn device_extensions_supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<bool> {
-    let available_extensions = unsafe {
-      instance.enumerate_device_extension_properties(physical_device)
-    }.context("Failed to enumerate device extension properties")?;
-
-    let mut requested_extensions: HashSet<ffi::CString> = Default::default();
-    for str in Self::enabled_device_extensions() {
-      requested_extensions.insert(ffi::CString::from(*str));
-    }
-    for ext in available_extensions {
-      requested_extensions.remove(unsafe { ffi::CStr::from_ptr(ext.extension_name.as_ptr()) });
-    }
-
-    Ok(requested_extensions.is_empty())
-  }
-
+  /// Fails with the reason the device can't be used. Features and extensions are checked by `DeviceSelection`.
   fn device_suitable(
-    window: &Window,
+    window: Option<&Window>,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
-  ) -> bool {
-    let indices = Self::find_queue_families(window, instance, physical_device);
-    let props = unsafe {
-      instance.get_physical_device_properties(physical_device)
-    };
-    let device_name = unsafe {
-      String::from(ffi::CStr::from_ptr(props.device_name.as_ptr()).to_str().unwrap())
-    };
-
-    debug!("Checking if suitable: [{}]", device_name);
-    // debug!("Checking if suitable: [{}]", unsafe { std::str::from_utf8_unchecked(std::mem::transmute(&props.device_name as &[i8])) });
-
-    let extensions_supported = match Self::device_extensions_supported(instance, physical_device) {
-      Ok(value) => value,
-      Err(e) => {
-        error!("{e}");
-        false
+  ) -> Result<()> {
+    Self::find_queue_families(window, instance, physical_device)?;
+
+    // nothing to present to without a window
+    if let Some(window) = window {
+      let swapchain_support = window.swapchain_support(physical_device)?;
+      if swapchain_support.formats.is_empty() || swapchain_support.present_modes.is_empty() {
+        anyhow::bail!("no surface formats or present modes for the window");
       }
-    };
-
-    let swapchain_adequate = if extensions_supported {
-      let swapchain_support = match window.swapchain_support(physical_device) {
-        Ok(value) => value,
-        Err(e) => {
-          error!("{e}");
-          return false;
-        }
-      };
-      !swapchain_support.formats.is_empty() && !swapchain_support.present_modes.is_empty()
-    } else {
-      false
-    };
-
-    let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+    }
 
-    indices.is_ok() && extensions_supported && swapchain_adequate && supported_features.sampler_anisotropy == vk::TRUE
+    Ok(())
   }
 
   fn find_queue_families(
-    window: &Window,
+    window: Option<&Window>,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
   ) -> Result<QueueFamilyIndices> {
@@ -500,13 +702,17 @@ impl RenderContext {
         graphics_family = Some(i as u32);
       }
 
-      let present_support = unsafe {
-        window.surface_loader().get_physical_device_surface_support(
-          physical_device,
-          i as u32,
-          *window.surface(),
-        )
-      }?;
+      // headless contexts "present" on the graphics queue, which is never actually used for presenting
+      let present_support = match window {
+        Some(window) => unsafe {
+          window.surface_loader().get_physical_device_surface_support(
+            physical_device,
+            i as u32,
+            *window.surface(),
+          )
+        }?,
+        None => family.queue_flags.contains(vk::QueueFlags::GRAPHICS),
+      };
 
       if family.queue_count > 0 && present_support {
         present_family = Some(i as u32);
@@ -556,7 +762,7 @@ impl RenderContext {
 //   }
 // }
 
-#[derive(Default)]
+#[derive(Default, Copy, Clone)]
 struct QueueFamilyIndices {
   pub graphics_family: u32,
   pub present_family: u32,
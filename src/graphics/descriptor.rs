@@ -0,0 +1,403 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+use bytemuck::Pod;
+use tracing::trace;
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::buffer::typed::StorageBuffer;
+use crate::graphics::context::RenderContext;
+use crate::graphics::pipeline::layout::PipelineLayout;
+use crate::graphics::texture::Texture;
+
+/// One binding of a descriptor set layout.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DescriptorBinding {
+  pub binding: u32,
+  pub descriptor_type: vk::DescriptorType,
+  pub count: u32,
+  pub stages: vk::ShaderStageFlags,
+}
+
+pub struct DescriptorSetLayout {
+  device: Arc<ash::Device>,
+  layout: vk::DescriptorSetLayout,
+  bindings: Arc<[DescriptorBinding]>,
+}
+
+impl DescriptorSetLayout {
+  pub fn builder() -> DescriptorSetLayoutBuilder {
+    DescriptorSetLayoutBuilder::default()
+  }
+
+  pub fn handle(&self) -> vk::DescriptorSetLayout {
+    self.layout
+  }
+
+  pub fn bindings(&self) -> &[DescriptorBinding] {
+    &self.bindings
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_descriptor_set_layout(self.layout, None);
+  }
+}
+
+impl Drop for DescriptorSetLayout {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+#[derive(Default, Clone)]
+pub struct DescriptorSetLayoutBuilder {
+  bindings: Vec<DescriptorBinding>,
+}
+
+impl DescriptorSetLayoutBuilder {
+  pub fn with_binding(
+    mut self,
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    count: u32,
+    stages: vk::ShaderStageFlags,
+  ) -> Self {
+    self.bindings.push(DescriptorBinding {
+      binding,
+      descriptor_type,
+      count,
+      stages,
+    });
+    self
+  }
+
+  pub fn with_uniform_buffer(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+    self.with_binding(binding, vk::DescriptorType::UNIFORM_BUFFER, 1, stages)
+  }
+
+  pub fn with_storage_buffer(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+    self.with_binding(binding, vk::DescriptorType::STORAGE_BUFFER, 1, stages)
+  }
+
+  pub fn with_sampled_image(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+    self.with_binding(binding, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, stages)
+  }
+
+  pub fn with_storage_image(self, binding: u32, stages: vk::ShaderStageFlags) -> Self {
+    self.with_binding(binding, vk::DescriptorType::STORAGE_IMAGE, 1, stages)
+  }
+
+  pub fn build(self, context: &RenderContext) -> Result<DescriptorSetLayout> {
+    let mut seen = std::collections::HashSet::new();
+    if let Some(binding) = self.bindings.iter().find(|b| !seen.insert(b.binding)) {
+      anyhow::bail!("Descriptor binding {} is declared more than once", binding.binding);
+    }
+
+    let vk_bindings: Vec<vk::DescriptorSetLayoutBinding> = self.bindings.iter()
+      .map(|b| vk::DescriptorSetLayoutBinding {
+        binding: b.binding,
+        descriptor_type: b.descriptor_type,
+        descriptor_count: b.count,
+        stage_flags: b.stages,
+        ..Default::default()
+      })
+      .collect();
+
+    let create_info = vk::DescriptorSetLayoutCreateInfo {
+      binding_count: vk_bindings.len() as u32,
+      p_bindings: vk_bindings.as_ptr(),
+      ..Default::default()
+    };
+
+    let layout = unsafe {
+      context.device().create_descriptor_set_layout(&create_info, None)
+    }.context("Failed to create descriptor set layout")?;
+
+    Ok(DescriptorSetLayout {
+      device: context.device(),
+      layout,
+      bindings: self.bindings.into(),
+    })
+  }
+}
+
+/// A set allocated from a `DescriptorAllocator`. Must be returned with `DescriptorAllocator::free`
+/// or released by resetting the allocator.
+pub struct DescriptorSet {
+  set: vk::DescriptorSet,
+  pool: vk::DescriptorPool,
+  bindings: Arc<[DescriptorBinding]>,
+}
+
+impl DescriptorSet {
+  pub fn handle(&self) -> vk::DescriptorSet {
+    self.set
+  }
+
+  pub fn writer(&self) -> DescriptorWriter<'_> {
+    DescriptorWriter {
+      set: self,
+      buffer_writes: vec![],
+      image_writes: vec![],
+    }
+  }
+
+  /// Binds the set at index `set_index` of a graphics pipeline's layout.
+  pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, layout: &PipelineLayout, set_index: u32) {
+    self.bind_to(device, command_buffer, vk::PipelineBindPoint::GRAPHICS, layout, set_index);
+  }
+
+  pub fn bind_to(
+    &self,
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    bind_point: vk::PipelineBindPoint,
+    layout: &PipelineLayout,
+    set_index: u32,
+  ) {
+    unsafe {
+      device.cmd_bind_descriptor_sets(command_buffer, bind_point, layout.handle(), set_index, &[self.set], &[]);
+    }
+  }
+}
+
+/// Hands out descriptor sets from a list of pools, adding a larger pool whenever all of them run out.
+pub struct DescriptorAllocator {
+  device: Arc<ash::Device>,
+  pools: Vec<vk::DescriptorPool>,
+  sets_per_pool: u32,
+}
+
+impl DescriptorAllocator {
+  const INITIAL_SETS_PER_POOL: u32 = 64;
+  const MAX_SETS_PER_POOL: u32 = 4096;
+
+  pub fn new(context: &RenderContext) -> Self {
+    Self {
+      device: context.device(),
+      pools: vec![],
+      sets_per_pool: Self::INITIAL_SETS_PER_POOL,
+    }
+  }
+
+  /// Tries the newest pool first, then older ones which may have room from freed sets, before adding a pool.
+  pub fn allocate(&mut self, layout: &DescriptorSetLayout) -> Result<DescriptorSet> {
+    for &pool in self.pools.iter().rev() {
+      match self.allocate_from(pool, layout) {
+        Ok(set) => return Ok(set),
+        Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {}
+        Err(err) => return Err(err).context("Failed to allocate descriptor set"),
+      }
+    }
+
+    let pool = self.create_pool(layout)?;
+    match self.allocate_from(pool, layout) {
+      Ok(set) => {
+        self.pools.push(pool);
+        self.sets_per_pool = (self.sets_per_pool * 2).min(Self::MAX_SETS_PER_POOL);
+        Ok(set)
+      }
+      Err(err) => {
+        unsafe { self.device.destroy_descriptor_pool(pool, None) };
+        Err(err).context("Failed to allocate descriptor set from a fresh pool")
+      }
+    }
+  }
+
+  pub fn free(&mut self, set: DescriptorSet) -> Result<()> {
+    unsafe {
+      self.device.free_descriptor_sets(set.pool, &[set.set])
+    }.context("Failed to free descriptor set")
+  }
+
+  /// Returns every set to its pool at once. Sets allocated before must not be used afterwards.
+  pub fn reset(&mut self) -> Result<()> {
+    for &pool in &self.pools {
+      unsafe {
+        self.device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+      }.context("Failed to reset descriptor pool")?;
+    }
+    Ok(())
+  }
+
+  fn allocate_from(&self, pool: vk::DescriptorPool, layout: &DescriptorSetLayout) -> Result<DescriptorSet, vk::Result> {
+    let allocate_info = vk::DescriptorSetAllocateInfo {
+      descriptor_pool: pool,
+      descriptor_set_count: 1,
+      p_set_layouts: &layout.layout,
+      ..Default::default()
+    };
+
+    let sets = unsafe { self.device.allocate_descriptor_sets(&allocate_info)? };
+
+    Ok(DescriptorSet {
+      set: sets[0],
+      pool,
+      bindings: layout.bindings.clone(),
+    })
+  }
+
+  /// Sized for `sets_per_pool` typical sets, and always for at least one `layout`.
+  /// Covers every descriptor type reflection can produce. Not added to `pools` until it has served `layout`.
+  fn create_pool(&self, layout: &DescriptorSetLayout) -> Result<vk::DescriptorPool> {
+    let sets = self.sets_per_pool;
+    let pool_sizes = [
+      (vk::DescriptorType::UNIFORM_BUFFER, 2),
+      (vk::DescriptorType::STORAGE_BUFFER, 2),
+      (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4),
+      (vk::DescriptorType::STORAGE_IMAGE, 1),
+      (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1),
+      (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1),
+      (vk::DescriptorType::SAMPLED_IMAGE, 1),
+      (vk::DescriptorType::SAMPLER, 1),
+      (vk::DescriptorType::INPUT_ATTACHMENT, 1),
+      (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 1),
+      (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 1),
+    ].map(|(ty, per_set)| {
+      let required: u32 = layout.bindings.iter()
+        .filter(|b| b.descriptor_type == ty)
+        .map(|b| b.count)
+        .sum();
+      vk::DescriptorPoolSize {
+        ty,
+        descriptor_count: (per_set * sets).max(required),
+      }
+    });
+
+    let create_info = vk::DescriptorPoolCreateInfo {
+      flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+      max_sets: sets,
+      pool_size_count: pool_sizes.len() as u32,
+      p_pool_sizes: pool_sizes.as_ptr(),
+      ..Default::default()
+    };
+
+    let pool = unsafe {
+      self.device.create_descriptor_pool(&create_info, None)
+    }.context("Failed to create descriptor pool")?;
+
+    trace!("Created descriptor pool for {sets} sets");
+    Ok(pool)
+  }
+
+  unsafe fn free_pools(&mut self) {
+    for pool in self.pools.drain(..) {
+      self.device.destroy_descriptor_pool(pool, None);
+    }
+  }
+}
+
+impl Drop for DescriptorAllocator {
+  fn drop(&mut self) {
+    unsafe {
+      self.free_pools();
+    }
+  }
+}
+
+/// Batches writes to a descriptor set, checking each against the set's layout.
+pub struct DescriptorWriter<'s> {
+  set: &'s DescriptorSet,
+  buffer_writes: Vec<(DescriptorBinding, vk::DescriptorBufferInfo)>,
+  image_writes: Vec<(DescriptorBinding, vk::DescriptorImageInfo)>,
+}
+
+impl<'s> DescriptorWriter<'s> {
+  /// Binds the whole of `buffer` to a uniform or storage buffer binding.
+  pub fn buffer(self, binding: u32, buffer: &Buffer) -> Result<Self> {
+    self.buffer_range(binding, buffer, 0, vk::WHOLE_SIZE)
+  }
+
+  pub fn buffer_range(mut self, binding: u32, buffer: &Buffer, offset: vk::DeviceSize, range: vk::DeviceSize) -> Result<Self> {
+    let layout_binding = self.binding(binding, &[
+      vk::DescriptorType::UNIFORM_BUFFER,
+      vk::DescriptorType::STORAGE_BUFFER,
+      vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+      vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+    ])?;
+    self.buffer_writes.push((layout_binding, vk::DescriptorBufferInfo {
+      buffer: buffer.buffer,
+      offset,
+      range,
+    }));
+    Ok(self)
+  }
+
+  /// Binds the whole of a storage buffer.
+  pub fn storage_buffer<T: Pod>(self, binding: u32, buffer: &StorageBuffer<T>) -> Result<Self> {
+    self.buffer(binding, buffer.buffer())
+  }
+
+  /// Binds an image view together with its sampler. The image must be in `SHADER_READ_ONLY_OPTIMAL` when used.
+  pub fn sampled_image(self, binding: u32, image_view: vk::ImageView, sampler: vk::Sampler) -> Result<Self> {
+    self.image(binding, image_view, sampler, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+  }
+
+  /// Binds a texture's view and sampler.
+  pub fn texture(self, binding: u32, texture: &Texture) -> Result<Self> {
+    self.sampled_image(binding, texture.view(), texture.sampler().handle())
+  }
+
+  /// Binds a storage image, which must be in `GENERAL` layout when used.
+  pub fn storage_image(self, binding: u32, image_view: vk::ImageView) -> Result<Self> {
+    self.image(binding, image_view, vk::Sampler::null(), vk::ImageLayout::GENERAL)
+  }
+
+  pub fn image(mut self, binding: u32, image_view: vk::ImageView, sampler: vk::Sampler, image_layout: vk::ImageLayout) -> Result<Self> {
+    let layout_binding = self.binding(binding, &[
+      vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+      vk::DescriptorType::SAMPLED_IMAGE,
+      vk::DescriptorType::STORAGE_IMAGE,
+    ])?;
+    if layout_binding.descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER && sampler == vk::Sampler::null() {
+      anyhow::bail!("Descriptor binding {binding} needs a sampler");
+    }
+    self.image_writes.push((layout_binding, vk::DescriptorImageInfo {
+      sampler,
+      image_view,
+      image_layout,
+    }));
+    Ok(self)
+  }
+
+  /// Applies all writes. The set must not be in use by a command buffer that is still executing.
+  pub fn update(self, context: &RenderContext) {
+    // the infos are collected first so the pointers in the writes stay valid
+    let writes: Vec<vk::WriteDescriptorSet> = self.buffer_writes.iter()
+      .map(|(binding, info)| vk::WriteDescriptorSet {
+        dst_set: self.set.set,
+        dst_binding: binding.binding,
+        descriptor_count: 1,
+        descriptor_type: binding.descriptor_type,
+        p_buffer_info: info,
+        ..Default::default()
+      })
+      .chain(self.image_writes.iter().map(|(binding, info)| vk::WriteDescriptorSet {
+        dst_set: self.set.set,
+        dst_binding: binding.binding,
+        descriptor_count: 1,
+        descriptor_type: binding.descriptor_type,
+        p_image_info: info,
+        ..Default::default()
+      }))
+      .collect();
+
+    unsafe {
+      context.device().update_descriptor_sets(&writes, &[]);
+    }
+  }
+
+  fn binding(&self, binding: u32, allowed: &[vk::DescriptorType]) -> Result<DescriptorBinding> {
+    let layout_binding = self.set.bindings.iter()
+      .find(|b| b.binding == binding)
+      .with_context(|| format!("Descriptor set has no binding {binding}"))?;
+
+    if !allowed.contains(&layout_binding.descriptor_type) {
+      anyhow::bail!("Descriptor binding {binding} is {:?}, which can't be written this way", layout_binding.descriptor_type);
+    }
+
+    Ok(*layout_binding)
+  }
+}
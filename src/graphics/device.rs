@@ -0,0 +1,510 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::{env, ffi};
+use std::str::FromStr;
+use anyhow::{Context, Result};
+use ash::vk;
+use strum::{Display, EnumIter};
+use tracing::info;
+
+/// A device feature that can be required or requested. Features from Vulkan 1.2 and 1.3
+/// are only supported by devices implementing that version.
+#[derive(EnumIter, Display, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceFeature {
+  SamplerAnisotropy,
+  /// Line and point polygon modes, e.g. for wireframes.
+  FillModeNonSolid,
+  WideLines,
+  GeometryShader,
+  TessellationShader,
+  MultiDrawIndirect,
+  SampleRateShading,
+  DepthClamp,
+  IndependentBlend,
+  ShaderFloat64,
+  ShaderInt64,
+  /// Runtime sized, partially bound and non-uniformly indexed descriptor arrays, e.g. for bindless textures.
+  DescriptorIndexing,
+  TimelineSemaphore,
+  BufferDeviceAddress,
+  /// Rendering without render pass objects. Pipelines without a render pass need it.
+  /// Core in Vulkan 1.3, and available on 1.2 devices through `VK_KHR_dynamic_rendering`.
+  DynamicRendering,
+  Synchronization2,
+}
+
+impl DeviceFeature {
+  pub(crate) fn supported_by(self, features: &DeviceFeatures) -> bool {
+    let mut features = features.clone();
+    self.fields(&mut features).into_iter().all(|field| *field == vk::TRUE)
+  }
+
+  pub(crate) fn enable(self, features: &mut DeviceFeatures) {
+    for field in self.fields(features) {
+      *field = vk::TRUE;
+    }
+  }
+
+  fn fields(self, features: &mut DeviceFeatures) -> Vec<&mut vk::Bool32> {
+    let DeviceFeatures { core, vulkan_12, vulkan_13, .. } = features;
+    match self {
+      DeviceFeature::SamplerAnisotropy => vec![&mut core.sampler_anisotropy],
+      DeviceFeature::FillModeNonSolid => vec![&mut core.fill_mode_non_solid],
+      DeviceFeature::WideLines => vec![&mut core.wide_lines],
+      DeviceFeature::GeometryShader => vec![&mut core.geometry_shader],
+      DeviceFeature::TessellationShader => vec![&mut core.tessellation_shader],
+      DeviceFeature::MultiDrawIndirect => vec![&mut core.multi_draw_indirect],
+      DeviceFeature::SampleRateShading => vec![&mut core.sample_rate_shading],
+      DeviceFeature::DepthClamp => vec![&mut core.depth_clamp],
+      DeviceFeature::IndependentBlend => vec![&mut core.independent_blend],
+      DeviceFeature::ShaderFloat64 => vec![&mut core.shader_float64],
+      DeviceFeature::ShaderInt64 => vec![&mut core.shader_int64],
+      DeviceFeature::DescriptorIndexing => vec![
+        &mut vulkan_12.descriptor_indexing,
+        &mut vulkan_12.runtime_descriptor_array,
+        &mut vulkan_12.descriptor_binding_partially_bound,
+        &mut vulkan_12.descriptor_binding_variable_descriptor_count,
+        &mut vulkan_12.shader_sampled_image_array_non_uniform_indexing,
+      ],
+      DeviceFeature::TimelineSemaphore => vec![&mut vulkan_12.timeline_semaphore],
+      DeviceFeature::BufferDeviceAddress => vec![&mut vulkan_12.buffer_device_address],
+      DeviceFeature::DynamicRendering => vec![&mut vulkan_13.dynamic_rendering],
+      DeviceFeature::Synchronization2 => vec![&mut vulkan_13.synchronization2],
+    }
+  }
+}
+
+/// The feature structs `DeviceFeature`s live in. `p_next` is always null outside of queries and device creation.
+#[derive(Clone, Default)]
+pub(crate) struct DeviceFeatures {
+  pub core: vk::PhysicalDeviceFeatures,
+  pub vulkan_12: vk::PhysicalDeviceVulkan12Features,
+  /// On 1.2 devices only `dynamic_rendering` can be supported, through the extension.
+  pub vulkan_13: vk::PhysicalDeviceVulkan13Features,
+  /// Whether dynamic rendering comes from `VK_KHR_dynamic_rendering` instead of Vulkan 1.3.
+  pub dynamic_rendering_extension: bool,
+}
+
+impl DeviceFeatures {
+  pub const DYNAMIC_RENDERING_EXTENSION: &'static str = "VK_KHR_dynamic_rendering";
+
+  /// Leaves features of versions newer than `api_version` unsupported, as older devices must not see their structs.
+  /// The exception is dynamic rendering, which 1.2 devices may support through `extensions`.
+  pub fn query(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    api_version: u32,
+    extensions: &HashSet<String>,
+  ) -> Self {
+    let mut features = Self::default();
+    if api_version < vk::API_VERSION_1_2 {
+      features.core = unsafe { instance.get_physical_device_features(physical_device) };
+      return features;
+    }
+
+    let mut features2 = vk::PhysicalDeviceFeatures2 {
+      p_next: &mut features.vulkan_12 as *mut _ as *mut ffi::c_void,
+      ..Default::default()
+    };
+    let mut dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+    features.dynamic_rendering_extension = api_version < vk::API_VERSION_1_3
+      && extensions.contains(Self::DYNAMIC_RENDERING_EXTENSION);
+    if api_version >= vk::API_VERSION_1_3 {
+      features.vulkan_12.p_next = &mut features.vulkan_13 as *mut _ as *mut ffi::c_void;
+    } else if features.dynamic_rendering_extension {
+      features.vulkan_12.p_next = &mut dynamic_rendering as *mut _ as *mut ffi::c_void;
+    }
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features2) };
+
+    if features.dynamic_rendering_extension {
+      features.vulkan_13.dynamic_rendering = dynamic_rendering.dynamic_rendering;
+    }
+    features.core = features2.features;
+    features.vulkan_12.p_next = std::ptr::null_mut();
+    features.vulkan_13.p_next = std::ptr::null_mut();
+    features
+  }
+}
+
+/// Picks a physical device by its enumeration index, or by a case insensitive part of its name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceSelector {
+  Index(usize),
+  Name(String),
+}
+
+impl FromStr for DeviceSelector {
+  type Err = Infallible;
+
+  /// Numbers select by index, anything else by name.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s.parse() {
+      Ok(index) => DeviceSelector::Index(index),
+      Err(_) => DeviceSelector::Name(s.to_owned()),
+    })
+  }
+}
+
+/// How the physical device is chosen, and which of its features and extensions are enabled.
+///
+/// Suitable devices are ranked discrete over integrated over virtual GPUs, then by how many optional features and extensions they support.
+/// CPU implementations such as lavapipe are only used when selected explicitly or when `cpu_fallback` is set
+/// and no GPU is suitable, e.g. on headless CI machines.
+///
+/// `KOYOTE_DEVICE` (an index or part of a name) and `KOYOTE_CPU_FALLBACK` (`true`/`false`) override these values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceSelection {
+  /// Skips ranking and uses this device, failing if it isn't suitable.
+  pub device: Option<DeviceSelector>,
+  /// Devices missing any of these are rejected.
+  pub required_features: HashSet<DeviceFeature>,
+  /// Enabled when supported.
+  pub optional_features: HashSet<DeviceFeature>,
+  /// Device extension names, e.g. `VK_KHR_ray_query`. Devices missing any of these are rejected.
+  pub required_extensions: HashSet<String>,
+  /// Enabled when supported.
+  pub optional_extensions: HashSet<String>,
+  pub cpu_fallback: bool,
+}
+
+impl DeviceSelection {
+  const DEVICE_ENV: &'static str = "KOYOTE_DEVICE";
+  const CPU_FALLBACK_ENV: &'static str = "KOYOTE_CPU_FALLBACK";
+
+  pub fn with_device(mut self, device: DeviceSelector) -> Self {
+    self.device = Some(device);
+    self
+  }
+
+  pub fn with_required_feature(mut self, feature: DeviceFeature) -> Self {
+    self.optional_features.remove(&feature);
+    self.required_features.insert(feature);
+    self
+  }
+
+  pub fn with_optional_feature(mut self, feature: DeviceFeature) -> Self {
+    if !self.required_features.contains(&feature) {
+      self.optional_features.insert(feature);
+    }
+    self
+  }
+
+  pub fn with_required_extension(mut self, name: impl Into<String>) -> Self {
+    let name = name.into();
+    self.optional_extensions.remove(&name);
+    self.required_extensions.insert(name);
+    self
+  }
+
+  pub fn with_optional_extension(mut self, name: impl Into<String>) -> Self {
+    let name = name.into();
+    if !self.required_extensions.contains(&name) {
+      self.optional_extensions.insert(name);
+    }
+    self
+  }
+
+  pub fn with_cpu_fallback(mut self, cpu_fallback: bool) -> Self {
+    self.cpu_fallback = cpu_fallback;
+    self
+  }
+
+  /// Applies `KOYOTE_DEVICE` and `KOYOTE_CPU_FALLBACK` if they're set.
+  pub fn with_env_overrides(mut self) -> Result<Self> {
+    if let Ok(device) = env::var(Self::DEVICE_ENV) {
+      self.device = Some(device.parse()?);
+    }
+    if let Ok(cpu_fallback) = env::var(Self::CPU_FALLBACK_ENV) {
+      self.cpu_fallback = cpu_fallback.parse()
+        .with_context(|| format!("Invalid value for {}: [{cpu_fallback}]", Self::CPU_FALLBACK_ENV))?;
+    }
+    Ok(self)
+  }
+
+  /// The features to create `candidate`'s device with.
+  pub(crate) fn enabled_features(&self, candidate: &DeviceCandidate) -> HashSet<DeviceFeature> {
+    self.required_features.iter()
+      .chain(self.optional_features.iter().filter(|f| f.supported_by(&candidate.features)))
+      .copied()
+      .collect()
+  }
+
+  /// The extensions to create `candidate`'s device with.
+  pub(crate) fn enabled_extensions(&self, candidate: &DeviceCandidate) -> HashSet<String> {
+    self.required_extensions.iter()
+      .chain(self.optional_extensions.iter().filter(|e| candidate.extensions.contains(*e)))
+      .cloned()
+      .chain(
+        (candidate.features.dynamic_rendering_extension
+          && self.enabled_features(candidate).contains(&DeviceFeature::DynamicRendering))
+          .then(|| DeviceFeatures::DYNAMIC_RENDERING_EXTENSION.to_string())
+      )
+      .collect()
+  }
+
+  /// Higher is better. Fails with the reason a device can't be used.
+  fn score(&self, candidate: &DeviceCandidate) -> Result<u32, String> {
+    let missing: Vec<_> = self.required_features.iter()
+      .filter(|f| !f.supported_by(&candidate.features))
+      .map(ToString::to_string)
+      .collect();
+    if !missing.is_empty() {
+      return Err(format!("missing required features {}", missing.join(", ")));
+    }
+    let mut missing: Vec<_> = self.required_extensions.iter()
+      .filter(|e| !candidate.extensions.contains(*e))
+      .map(String::as_str)
+      .collect();
+    if !missing.is_empty() {
+      missing.sort_unstable();
+      return Err(format!("missing required extensions {}", missing.join(", ")));
+    }
+
+    let type_score = match candidate.device_type {
+      vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+      vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+      vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+      vk::PhysicalDeviceType::CPU => 1,
+      _ => 0,
+    };
+    let optional_score = self.optional_features.iter()
+      .filter(|f| f.supported_by(&candidate.features))
+      .count() as u32
+      + self.optional_extensions.iter()
+      .filter(|e| candidate.extensions.contains(*e))
+      .count() as u32;
+    Ok(type_score * 100 + optional_score)
+  }
+
+  /// Picks a device among `candidates`, which carry the outcome of the context's own suitability checks.
+  /// Rejected devices are logged with their reason.
+  pub(crate) fn select(&self, candidates: Vec<(DeviceCandidate, Result<()>)>) -> Result<DeviceCandidate> {
+    let scored: Vec<(DeviceCandidate, Result<u32, String>)> = candidates.into_iter()
+      .map(|(candidate, suitable)| {
+        let score = match suitable {
+          Ok(()) => self.score(&candidate),
+          Err(err) => Err(format!("{err:#}")),
+        };
+        (candidate, score)
+      })
+      .collect();
+
+    if let Some(selector) = &self.device {
+      let (candidate, score) = scored.into_iter()
+        .find(|(candidate, _)| candidate.matches(selector))
+        .with_context(|| format!("No physical device matches {selector:?}"))?;
+      return match score {
+        Ok(_) => Ok(candidate),
+        Err(reason) => Err(anyhow::anyhow!("Selected device [{}] can't be used: {reason}", candidate.name)),
+      };
+    }
+
+    let mut best_gpu: Option<(DeviceCandidate, u32)> = None;
+    let mut best_cpu: Option<(DeviceCandidate, u32)> = None;
+    for (candidate, score) in scored {
+      let score = match score {
+        Ok(score) => score,
+        Err(reason) => {
+          info!("Rejected device [{}]: {reason}", candidate.name);
+          continue;
+        }
+      };
+      let best = if candidate.device_type == vk::PhysicalDeviceType::CPU { &mut best_cpu } else { &mut best_gpu };
+      if best.as_ref().is_none_or(|(_, best_score)| score > *best_score) {
+        *best = Some((candidate, score));
+      }
+    }
+
+    match (best_gpu, best_cpu) {
+      (Some((gpu, _)), _) => Ok(gpu),
+      (None, Some((cpu, _))) if self.cpu_fallback => {
+        info!("No suitable GPU, falling back to CPU device [{}]", cpu.name);
+        Ok(cpu)
+      }
+      (None, Some((cpu, _))) => Err(anyhow::anyhow!(
+        "Failed to find a suitable GPU. CPU device [{}] is available, enable CPU fallback or set {}=true to use it",
+        cpu.name,
+        Self::CPU_FALLBACK_ENV,
+      )),
+      (None, None) => Err(anyhow::anyhow!("Failed to find a suitable physical device")),
+    }
+  }
+}
+
+impl Default for DeviceSelection {
+  fn default() -> Self {
+    Self {
+      device: None,
+      required_features: HashSet::from([DeviceFeature::SamplerAnisotropy]),
+      optional_features: HashSet::from([DeviceFeature::FillModeNonSolid, DeviceFeature::DynamicRendering]),
+      required_extensions: Default::default(),
+      optional_extensions: Default::default(),
+      cpu_fallback: false,
+    }
+  }
+}
+
+/// A physical device being considered by `DeviceSelection`.
+#[derive(Clone)]
+pub(crate) struct DeviceCandidate {
+  pub handle: vk::PhysicalDevice,
+  pub index: usize,
+  pub name: String,
+  pub device_type: vk::PhysicalDeviceType,
+  pub properties: vk::PhysicalDeviceProperties,
+  pub features: DeviceFeatures,
+  pub extensions: HashSet<String>,
+}
+
+impl DeviceCandidate {
+  pub fn new(instance: &ash::Instance, index: usize, handle: vk::PhysicalDevice) -> Result<Self> {
+    let properties = unsafe { instance.get_physical_device_properties(handle) };
+    let name = unsafe { ffi::CStr::from_ptr(properties.device_name.as_ptr()) }
+      .to_string_lossy()
+      .into_owned();
+    let extensions = unsafe {
+      instance.enumerate_device_extension_properties(handle)
+    }.context("Failed to enumerate device extension properties")?
+      .iter()
+      .map(|e| unsafe { ffi::CStr::from_ptr(e.extension_name.as_ptr()) }.to_string_lossy().into_owned())
+      .collect();
+
+    Ok(Self {
+      handle,
+      index,
+      name,
+      device_type: properties.device_type,
+      properties,
+      features: DeviceFeatures::query(instance, handle, properties.api_version, &extensions),
+      extensions,
+    })
+  }
+
+  fn matches(&self, selector: &DeviceSelector) -> bool {
+    match selector {
+      DeviceSelector::Index(index) => self.index == *index,
+      DeviceSelector::Name(name) => self.name.to_lowercase().contains(&name.to_lowercase()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn candidate(index: usize, name: &str, device_type: vk::PhysicalDeviceType, features: &[DeviceFeature]) -> DeviceCandidate {
+    let mut device_features = DeviceFeatures::default();
+    for feature in features {
+      feature.enable(&mut device_features);
+    }
+    DeviceCandidate {
+      handle: vk::PhysicalDevice::null(),
+      index,
+      name: name.to_owned(),
+      device_type,
+      properties: Default::default(),
+      features: device_features,
+      extensions: HashSet::new(),
+    }
+  }
+
+  fn gpu(index: usize, device_type: vk::PhysicalDeviceType) -> DeviceCandidate {
+    candidate(index, &format!("GPU {index}"), device_type, &[DeviceFeature::SamplerAnisotropy])
+  }
+
+  fn select(selection: &DeviceSelection, candidates: Vec<DeviceCandidate>) -> Result<usize> {
+    let candidates = candidates.into_iter().map(|c| (c, Ok(()))).collect();
+    selection.select(candidates).map(|c| c.index)
+  }
+
+  #[test]
+  fn ranks_discrete_over_integrated_over_virtual() -> Result<()> {
+    let selection = DeviceSelection::default();
+    let candidates = vec![
+      gpu(0, vk::PhysicalDeviceType::VIRTUAL_GPU),
+      gpu(1, vk::PhysicalDeviceType::INTEGRATED_GPU),
+      gpu(2, vk::PhysicalDeviceType::DISCRETE_GPU),
+    ];
+    assert_eq!(select(&selection, candidates.clone())?, 2);
+    assert_eq!(select(&selection, candidates[..2].to_vec())?, 1);
+    Ok(())
+  }
+
+  #[test]
+  fn optional_features_and_extensions_break_ties() -> Result<()> {
+    let selection = DeviceSelection::default().with_optional_extension("VK_EXT_memory_budget");
+    let plain = gpu(0, vk::PhysicalDeviceType::DISCRETE_GPU);
+    let mut with_extension = gpu(1, vk::PhysicalDeviceType::DISCRETE_GPU);
+    with_extension.extensions.insert("VK_EXT_memory_budget".to_owned());
+    let with_feature = candidate(2, "GPU 2", vk::PhysicalDeviceType::DISCRETE_GPU, &[
+      DeviceFeature::SamplerAnisotropy,
+      DeviceFeature::FillModeNonSolid,
+    ]);
+
+    assert_eq!(select(&selection, vec![plain.clone(), with_extension])?, 1);
+    assert_eq!(select(&selection, vec![plain, with_feature])?, 2);
+    Ok(())
+  }
+
+  #[test]
+  fn rejects_devices_missing_requirements() -> Result<()> {
+    let discrete = candidate(0, "GPU 0", vk::PhysicalDeviceType::DISCRETE_GPU, &[]);
+    let integrated = gpu(1, vk::PhysicalDeviceType::INTEGRATED_GPU);
+    assert_eq!(select(&DeviceSelection::default(), vec![discrete.clone(), integrated.clone()])?, 1);
+
+    let selection = DeviceSelection::default().with_required_extension("VK_KHR_ray_query");
+    let mut ray_query = gpu(2, vk::PhysicalDeviceType::INTEGRATED_GPU);
+    ray_query.extensions.insert("VK_KHR_ray_query".to_owned());
+    assert_eq!(select(&selection, vec![gpu(3, vk::PhysicalDeviceType::DISCRETE_GPU), ray_query])?, 2);
+    assert!(select(&selection, vec![discrete, integrated]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn rejects_devices_failing_context_checks() -> Result<()> {
+    let candidates = vec![
+      (gpu(0, vk::PhysicalDeviceType::DISCRETE_GPU), Err(anyhow::anyhow!("no present queue"))),
+      (gpu(1, vk::PhysicalDeviceType::INTEGRATED_GPU), Ok(())),
+    ];
+    assert_eq!(DeviceSelection::default().select(candidates)?.index, 1);
+    Ok(())
+  }
+
+  #[test]
+  fn selectors_pick_by_index_or_name() -> Result<()> {
+    let candidates = vec![
+      candidate(0, "NVIDIA GeForce", vk::PhysicalDeviceType::DISCRETE_GPU, &[DeviceFeature::SamplerAnisotropy]),
+      candidate(1, "Intel UHD", vk::PhysicalDeviceType::INTEGRATED_GPU, &[DeviceFeature::SamplerAnisotropy]),
+      candidate(2, "llvmpipe", vk::PhysicalDeviceType::CPU, &[]),
+    ];
+
+    let by_index = DeviceSelection::default().with_device(DeviceSelector::Index(1));
+    assert_eq!(select(&by_index, candidates.clone())?, 1);
+    let by_name = DeviceSelection::default().with_device("intel".parse()?);
+    assert_eq!(select(&by_name, candidates.clone())?, 1);
+
+    let missing = DeviceSelection::default().with_device(DeviceSelector::Index(5));
+    assert!(select(&missing, candidates.clone()).is_err());
+    // selected devices still have to meet the requirements
+    let unsuitable = DeviceSelection::default().with_device(DeviceSelector::Name("llvmpipe".to_owned()));
+    assert!(select(&unsuitable, candidates).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn parses_selectors() {
+    assert_eq!("2".parse(), Ok(DeviceSelector::Index(2)));
+    assert_eq!("RTX 4090".parse(), Ok(DeviceSelector::Name("RTX 4090".to_owned())));
+  }
+
+  #[test]
+  fn cpu_devices_need_fallback() -> Result<()> {
+    let cpu = gpu(0, vk::PhysicalDeviceType::CPU);
+    assert!(select(&DeviceSelection::default(), vec![cpu.clone()]).is_err());
+
+    let fallback = DeviceSelection::default().with_cpu_fallback(true);
+    assert_eq!(select(&fallback, vec![cpu.clone()])?, 0);
+    assert_eq!(select(&fallback, vec![cpu, gpu(1, vk::PhysicalDeviceType::VIRTUAL_GPU)])?, 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::{self, vk};
+
+use crate::graphics::capture::InFlightCapture;
+use crate::graphics::context::RenderContext;
+use crate::graphics::graph::GraphResources;
+use crate::graphics::swapchain::Swapchain;
+use crate::graphics::window::WindowId;
+
+/// The frame currently being recorded for a window, handed to `Runnable::render`.
+///
+/// During the hook the image is in `COLOR_ATTACHMENT_OPTIMAL` and has already been cleared.
+/// Commands recorded into `command_buffer` are submitted and presented once the hook returns.
+#[derive(Debug, Copy, Clone)]
+pub struct Frame {
+  /// `None` when rendering to an offscreen `RenderTarget`.
+  pub window_id: Option<WindowId>,
+  pub command_buffer: vk::CommandBuffer,
+  pub image: vk::Image,
+  pub image_view: vk::ImageView,
+  pub image_index: u32,
+  pub extent: vk::Extent2D,
+  pub format: vk::Format,
+}
+
+/// Per window command buffers and synchronization for `Swapchain::MAX_FRAMES_IN_FLIGHT` frames.
+pub(crate) struct FrameResources {
+  device: Arc<ash::Device>,
+  command_pool: vk::CommandPool,
+  frames: Vec<FrameSync>,
+  current: usize,
+}
+
+pub(crate) struct FrameSync {
+  pub command_buffer: vk::CommandBuffer,
+  pub image_available: vk::Semaphore,
+  pub render_finished: vk::Semaphore,
+  pub in_flight: vk::Fence,
+  /// Captures recorded into this frame, readable once `in_flight` signals.
+  pub captures: Vec<InFlightCapture>,
+  /// Transient images of graphs executed in this frame, dropped once `in_flight` signals.
+  pub graph_resources: Vec<GraphResources>,
+}
+
+impl FrameResources {
+  pub fn new(context: &RenderContext) -> Result<Self> {
+    let device = context.device();
+    let allocate_info = vk::CommandBufferAllocateInfo {
+      level: vk::CommandBufferLevel::PRIMARY,
+      command_pool: *context.command_pool(),
+      command_buffer_count: Swapchain::MAX_FRAMES_IN_FLIGHT,
+      ..Default::default()
+    };
+
+    let command_buffers = unsafe {
+      device.allocate_command_buffers(&allocate_info)
+    }.context("Failed to allocate frame command buffers")?;
+
+    let mut resources = Self {
+      device,
+      command_pool: *context.command_pool(),
+      frames: Vec::with_capacity(command_buffers.len()),
+      current: 0,
+    };
+
+    for command_buffer in command_buffers {
+      // anything created so far is cleaned up by drop on failure
+      resources.frames.push(FrameSync {
+        command_buffer,
+        image_available: vk::Semaphore::null(),
+        render_finished: vk::Semaphore::null(),
+        in_flight: vk::Fence::null(),
+        captures: vec![],
+        graph_resources: vec![],
+      });
+      let frame = resources.frames.last_mut().unwrap();
+      frame.image_available = Self::create_semaphore(&resources.device)?;
+      frame.render_finished = Self::create_semaphore(&resources.device)?;
+      frame.in_flight = Self::create_fence(&resources.device)?;
+    }
+
+    Ok(resources)
+  }
+
+  pub fn current(&self) -> &FrameSync {
+    &self.frames[self.current]
+  }
+
+  pub fn current_mut(&mut self) -> &mut FrameSync {
+    &mut self.frames[self.current]
+  }
+
+  /// Hands out finished captures of every frame whose gpu work has completed, without blocking.
+  /// Also releases the frame's graph resources.
+  pub fn resolve_captures(&mut self) {
+    for frame in &mut self.frames {
+      if frame.captures.is_empty() && frame.graph_resources.is_empty() {
+        continue;
+      }
+      if let Ok(true) = unsafe { self.device.get_fence_status(frame.in_flight) } {
+        for capture in frame.captures.drain(..) {
+          capture.resolve();
+        }
+        frame.graph_resources.clear();
+      }
+    }
+  }
+
+  /// Replaces the current slot's fence with a signaled one and its acquire semaphore with an unsignaled one,
+  /// for when no submission could consume them. Neither may be in use by pending gpu work.
+  pub fn recreate_sync(&mut self) -> Result<()> {
+    let fence = Self::create_fence(&self.device)?;
+    let image_available = match Self::create_semaphore(&self.device) {
+      Ok(semaphore) => semaphore,
+      Err(err) => unsafe {
+        self.device.destroy_fence(fence, None);
+        Err(err)?
+      }
+    };
+
+    let frame = &mut self.frames[self.current];
+    unsafe {
+      self.device.destroy_fence(frame.in_flight, None);
+      self.device.destroy_semaphore(frame.image_available, None);
+    }
+    frame.in_flight = fence;
+    frame.image_available = image_available;
+    Ok(())
+  }
+
+  pub fn advance(&mut self) {
+    self.current = (self.current + 1) % self.frames.len();
+  }
+
+  /// Signaled so the first wait on each frame doesn't block forever.
+  fn create_fence(device: &ash::Device) -> Result<vk::Fence> {
+    unsafe {
+      device.create_fence(&vk::FenceCreateInfo {
+        flags: vk::FenceCreateFlags::SIGNALED,
+        ..Default::default()
+      }, None)
+    }.context("Failed to create frame fence")
+  }
+
+  fn create_semaphore(device: &ash::Device) -> Result<vk::Semaphore> {
+    unsafe {
+      device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+    }.context("Failed to create frame semaphore")
+  }
+}
+
+impl FrameResources {
+  unsafe fn free(&mut self) {
+    for frame in self.frames.drain(..) {
+      self.device.destroy_semaphore(frame.image_available, None);
+      self.device.destroy_semaphore(frame.render_finished, None);
+      self.device.destroy_fence(frame.in_flight, None);
+      self.device.free_command_buffers(self.command_pool, &[frame.command_buffer]);
+    }
+  }
+}
+
+impl Drop for FrameResources {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
@@ -0,0 +1,1125 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::context::RenderContext;
+use crate::graphics::frame::Frame;
+use crate::graphics::image::Image;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResourceHandle {
+  Image(ImageHandle),
+  Buffer(BufferHandle),
+}
+
+impl From<ImageHandle> for ResourceHandle {
+  fn from(value: ImageHandle) -> Self {
+    Self::Image(value)
+  }
+}
+
+impl From<BufferHandle> for ResourceHandle {
+  fn from(value: BufferHandle) -> Self {
+    Self::Buffer(value)
+  }
+}
+
+/// How a pass uses an image. Whether it reads or writes is given separately.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageUsage {
+  ColorAttachment,
+  DepthAttachment,
+  /// Read through a sampler in the given shader stages. Can't be written.
+  Sampled(vk::ShaderStageFlags),
+  Storage(vk::ShaderStageFlags),
+  Transfer,
+}
+
+/// How a pass uses a buffer. Whether it reads or writes is given separately.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BufferUsage {
+  Vertex,
+  Index,
+  Indirect,
+  Uniform(vk::ShaderStageFlags),
+  Storage(vk::ShaderStageFlags),
+  Transfer,
+}
+
+/// The synchronization scope of one access.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Access {
+  stages: vk::PipelineStageFlags,
+  access: vk::AccessFlags,
+  layout: vk::ImageLayout,
+  write: bool,
+}
+
+impl ImageUsage {
+  fn access(self, write: bool) -> Result<Access> {
+    let (stages, access, layout) = match (self, write) {
+      (Self::ColorAttachment, false) => (
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags::COLOR_ATTACHMENT_READ,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+      ),
+      (Self::ColorAttachment, true) => (
+        vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+      ),
+      (Self::DepthAttachment, false) => (
+        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+        vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+      ),
+      (Self::DepthAttachment, true) => (
+        vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+      ),
+      (Self::Sampled(stages), false) => (
+        shader_stages(stages),
+        vk::AccessFlags::SHADER_READ,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ),
+      (Self::Sampled(_), true) => anyhow::bail!("Sampled images can't be written"),
+      (Self::Storage(stages), write) => (
+        shader_stages(stages),
+        if write { vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE } else { vk::AccessFlags::SHADER_READ },
+        vk::ImageLayout::GENERAL,
+      ),
+      (Self::Transfer, false) => (
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_READ,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+      ),
+      (Self::Transfer, true) => (
+        vk::PipelineStageFlags::TRANSFER,
+        vk::AccessFlags::TRANSFER_WRITE,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+      ),
+    };
+    Ok(Access { stages, access, layout, write })
+  }
+
+  fn image_usage(self, write: bool) -> vk::ImageUsageFlags {
+    match (self, write) {
+      (Self::ColorAttachment, _) => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+      (Self::DepthAttachment, _) => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+      (Self::Sampled(_), _) => vk::ImageUsageFlags::SAMPLED,
+      (Self::Storage(_), _) => vk::ImageUsageFlags::STORAGE,
+      (Self::Transfer, false) => vk::ImageUsageFlags::TRANSFER_SRC,
+      (Self::Transfer, true) => vk::ImageUsageFlags::TRANSFER_DST,
+    }
+  }
+}
+
+impl BufferUsage {
+  fn access(self, write: bool) -> Result<Access> {
+    let (stages, access) = match (self, write) {
+      (Self::Vertex, false) => (vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ),
+      (Self::Index, false) => (vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::INDEX_READ),
+      (Self::Indirect, false) => (vk::PipelineStageFlags::DRAW_INDIRECT, vk::AccessFlags::INDIRECT_COMMAND_READ),
+      (Self::Uniform(stages), false) => (shader_stages(stages), vk::AccessFlags::UNIFORM_READ),
+      (Self::Vertex | Self::Index | Self::Indirect | Self::Uniform(_), true) => {
+        anyhow::bail!("{self:?} buffers can't be written")
+      }
+      (Self::Storage(stages), false) => (shader_stages(stages), vk::AccessFlags::SHADER_READ),
+      (Self::Storage(stages), true) => (shader_stages(stages), vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE),
+      (Self::Transfer, false) => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+      (Self::Transfer, true) => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+    };
+    Ok(Access { stages, access, layout: vk::ImageLayout::UNDEFINED, write })
+  }
+}
+
+fn shader_stages(stages: vk::ShaderStageFlags) -> vk::PipelineStageFlags {
+  let mut flags = vk::PipelineStageFlags::empty();
+  for (shader, pipeline) in [
+    (vk::ShaderStageFlags::VERTEX, vk::PipelineStageFlags::VERTEX_SHADER),
+    (vk::ShaderStageFlags::GEOMETRY, vk::PipelineStageFlags::GEOMETRY_SHADER),
+    (vk::ShaderStageFlags::FRAGMENT, vk::PipelineStageFlags::FRAGMENT_SHADER),
+    (vk::ShaderStageFlags::COMPUTE, vk::PipelineStageFlags::COMPUTE_SHADER),
+  ] {
+    if stages.contains(shader) {
+      flags |= pipeline;
+    }
+  }
+  if flags.is_empty() { vk::PipelineStageFlags::ALL_COMMANDS } else { flags }
+}
+
+fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+  match format {
+    vk::Format::D16_UNORM | vk::Format::D32_SFLOAT | vk::Format::X8_D24_UNORM_PACK32 => vk::ImageAspectFlags::DEPTH,
+    vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT => {
+      vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    }
+    vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+    _ => vk::ImageAspectFlags::COLOR,
+  }
+}
+
+/// An image created outside the graph, e.g. a swapchain image.
+#[derive(Debug, Copy, Clone)]
+pub struct ImportedImage {
+  pub image: vk::Image,
+  pub view: vk::ImageView,
+  pub format: vk::Format,
+  pub extent: vk::Extent2D,
+  /// The layout the image is in when the graph starts.
+  pub initial_layout: vk::ImageLayout,
+  /// The layout to leave the image in, or whatever the last pass needed when `None`.
+  pub final_layout: Option<vk::ImageLayout>,
+}
+
+/// An image the graph creates for the passes using it, and drops once the gpu is done.
+#[derive(Debug, Copy, Clone)]
+pub struct TransientImage {
+  pub format: vk::Format,
+  pub extent: vk::Extent2D,
+  pub samples: vk::SampleCountFlags,
+}
+
+impl TransientImage {
+  pub fn new(format: vk::Format, extent: vk::Extent2D) -> Self {
+    Self {
+      format,
+      extent,
+      samples: vk::SampleCountFlags::TYPE_1,
+    }
+  }
+
+  pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+    self.samples = samples;
+    self
+  }
+}
+
+enum ImageSource {
+  Imported(ImportedImage),
+  Transient(TransientImage),
+}
+
+struct ImageResource {
+  name: String,
+  source: ImageSource,
+  output: bool,
+}
+
+impl ImageResource {
+  fn format(&self) -> vk::Format {
+    match &self.source {
+      ImageSource::Imported(imported) => imported.format,
+      ImageSource::Transient(transient) => transient.format,
+    }
+  }
+}
+
+struct BufferResource {
+  name: String,
+  buffer: vk::Buffer,
+  output: bool,
+}
+
+type RecordFn<'g> = Box<dyn FnOnce(&PassContext<'_>) + 'g>;
+
+struct Pass<'g> {
+  name: String,
+  images: Vec<(ImageHandle, ImageUsage, bool)>,
+  buffers: Vec<(BufferHandle, BufferUsage, bool)>,
+  side_effects: bool,
+  record: RecordFn<'g>,
+}
+
+/// Passes recorded into a frame, declaring the images and buffers they read and write.
+///
+/// Passes run in declaration order, and each read sees the latest write declared before it.
+/// Compiling inserts the barriers and layout transitions between passes, creates transient images, and culls passes
+/// that contribute nothing to an output: an imported resource marked with `mark_output`, or a pass with side effects.
+#[derive(Default)]
+pub struct RenderGraph<'g> {
+  images: Vec<ImageResource>,
+  buffers: Vec<BufferResource>,
+  passes: Vec<Pass<'g>>,
+}
+
+impl<'g> RenderGraph<'g> {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn import_image(&mut self, name: impl Into<String>, image: ImportedImage) -> ImageHandle {
+    self.images.push(ImageResource {
+      name: name.into(),
+      source: ImageSource::Imported(image),
+      output: false,
+    });
+    ImageHandle(self.images.len() - 1)
+  }
+
+  /// Imports the frame's image as an output, leaving it in `COLOR_ATTACHMENT_OPTIMAL` like `Graphics` expects.
+  pub fn import_frame(&mut self, frame: &Frame) -> ImageHandle {
+    let handle = self.import_image("frame", ImportedImage {
+      image: frame.image,
+      view: frame.image_view,
+      format: frame.format,
+      extent: frame.extent,
+      initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+      final_layout: Some(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    });
+    self.mark_output(handle);
+    handle
+  }
+
+  pub fn create_image(&mut self, name: impl Into<String>, image: TransientImage) -> ImageHandle {
+    self.images.push(ImageResource {
+      name: name.into(),
+      source: ImageSource::Transient(image),
+      output: false,
+    });
+    ImageHandle(self.images.len() - 1)
+  }
+
+  /// The buffer must outlive the gpu work of the compiled graph.
+  pub fn import_buffer(&mut self, name: impl Into<String>, buffer: &Buffer) -> BufferHandle {
+    self.buffers.push(BufferResource {
+      name: name.into(),
+      buffer: buffer.buffer,
+      output: false,
+    });
+    BufferHandle(self.buffers.len() - 1)
+  }
+
+  /// Keeps the passes writing the resource from being culled. Only imported resources outlive the graph.
+  pub fn mark_output(&mut self, handle: impl Into<ResourceHandle>) {
+    match handle.into() {
+      ResourceHandle::Image(ImageHandle(i)) => self.images[i].output = true,
+      ResourceHandle::Buffer(BufferHandle(i)) => self.buffers[i].output = true,
+    }
+  }
+
+  pub fn add_pass<'a>(&'a mut self, name: impl Into<String>) -> PassBuilder<'a, 'g> {
+    PassBuilder {
+      graph: self,
+      name: name.into(),
+      images: vec![],
+      buffers: vec![],
+      side_effects: false,
+    }
+  }
+
+  pub fn compile(self, context: &RenderContext) -> Result<CompiledGraph<'g>> {
+    let Plan { order, mut barriers, mut layouts, usages, final_barrier } = self.plan()?;
+
+    let mut transients = vec![];
+    let mut images = Vec::with_capacity(self.images.len());
+    for (i, image) in self.images.iter().enumerate() {
+      let aspect_mask = aspect_mask(image.format());
+      let resolved = match &image.source {
+        ImageSource::Imported(imported) => GraphImage {
+          image: imported.image,
+          view: imported.view,
+          format: imported.format,
+          extent: imported.extent,
+          aspect_mask,
+        },
+        // unused transients are culled along with their passes
+        ImageSource::Transient(_) if usages[i].is_empty() => GraphImage {
+          image: vk::Image::null(),
+          view: vk::ImageView::null(),
+          format: image.format(),
+          extent: vk::Extent2D::default(),
+          aspect_mask,
+        },
+        ImageSource::Transient(transient) => {
+          let created = TransientResource::new(context, transient, usages[i], aspect_mask)
+            .with_context(|| format!("Failed to create transient image `{}`", image.name))?;
+          let resolved = GraphImage {
+            image: created.image.image,
+            view: created.view,
+            format: transient.format,
+            extent: transient.extent,
+            aspect_mask,
+          };
+          transients.push(created);
+          resolved
+        }
+      };
+      images.push(resolved);
+    }
+
+    let info = GraphInfo {
+      images: self.images.iter().map(|i| (i.name.clone(), matches!(i.source, ImageSource::Transient(_)), i.output)).collect(),
+      buffers: self.buffers.iter().map(|b| (b.name.clone(), b.output)).collect(),
+      passes: self.passes.iter().enumerate()
+        .map(|(p, pass)| PassInfo {
+          name: pass.name.clone(),
+          order: order.iter().position(|o| *o == p),
+          images: pass.images.clone(),
+          buffers: pass.buffers.clone(),
+          barrier_count: barriers[p].images.len() + barriers[p].buffers.len(),
+        })
+        .collect(),
+    };
+
+    let mut passes: Vec<Option<Pass<'g>>> = self.passes.into_iter().map(Some).collect();
+    let compiled_passes = order.iter()
+      .map(|p| CompiledPass {
+        record: passes[*p].take().unwrap().record,
+        barrier: std::mem::take(&mut barriers[*p]),
+        layouts: std::mem::take(&mut layouts[*p]),
+      })
+      .collect();
+
+    Ok(CompiledGraph {
+      images,
+      buffers: self.buffers.iter().map(|b| b.buffer).collect(),
+      passes: compiled_passes,
+      final_barrier,
+      resources: GraphResources { transients },
+      info,
+    })
+  }
+
+  /// Orders the passes, culls the dead ones and places the barriers between them, without creating anything.
+  fn plan(&self) -> Result<Plan> {
+    let pass_count = self.passes.len();
+
+    // resolve every access first, so invalid ones fail before anything is created
+    let mut image_accesses = Vec::with_capacity(pass_count);
+    let mut buffer_accesses = Vec::with_capacity(pass_count);
+    for pass in &self.passes {
+      let images = merge_accesses(pass.images.iter().map(|(h, usage, write)| (h.0, usage.access(*write))))
+        .with_context(|| format!("Invalid image access in pass `{}`", pass.name))?;
+      let buffers = merge_accesses(pass.buffers.iter().map(|(h, usage, write)| (h.0, usage.access(*write))))
+        .with_context(|| format!("Invalid buffer access in pass `{}`", pass.name))?;
+      image_accesses.push(images);
+      buffer_accesses.push(buffers);
+    }
+
+    // dependencies in declaration order. `producers` only holds the ones passing data along, which decide culling
+    let mut dependencies: Vec<Vec<usize>> = vec![vec![]; pass_count];
+    let mut producers: Vec<Vec<usize>> = vec![vec![]; pass_count];
+    let mut image_hazards = vec![Hazards::default(); self.images.len()];
+    let mut buffer_hazards = vec![Hazards::default(); self.buffers.len()];
+    for pass in 0..pass_count {
+      for (resource, access) in &image_accesses[pass] {
+        let image = &self.images[*resource];
+        if !access.write && image_hazards[*resource].last_writer.is_none() && matches!(image.source, ImageSource::Transient(_)) {
+          anyhow::bail!("Pass `{}` reads `{}` before any pass writes it", self.passes[pass].name, image.name);
+        }
+        image_hazards[*resource].record(pass, access.write, &mut dependencies, &mut producers);
+      }
+      for (resource, access) in &buffer_accesses[pass] {
+        buffer_hazards[*resource].record(pass, access.write, &mut dependencies, &mut producers);
+      }
+    }
+
+    let mut alive = vec![false; pass_count];
+    let mut queue: VecDeque<usize> = (0..pass_count)
+      .filter(|p| {
+        self.passes[*p].side_effects
+          || image_accesses[*p].iter().any(|(i, a)| a.write && self.images[*i].output)
+          || buffer_accesses[*p].iter().any(|(b, a)| a.write && self.buffers[*b].output)
+      })
+      .collect();
+    while let Some(pass) = queue.pop_front() {
+      if !std::mem::replace(&mut alive[pass], true) {
+        queue.extend(producers[pass].iter().copied());
+      }
+    }
+
+    // topological order over the living passes, preferring declaration order among ready ones
+    let mut remaining: Vec<usize> = (0..pass_count)
+      .map(|p| dependencies[p].iter().filter(|d| alive[**d]).count())
+      .collect();
+    let mut order = Vec::with_capacity(pass_count);
+    let mut ready: Vec<usize> = (0..pass_count).filter(|p| alive[*p] && remaining[*p] == 0).collect();
+    while let Some(pass) = ready.iter().copied().min() {
+      ready.retain(|p| *p != pass);
+      order.push(pass);
+      for next in (0..pass_count).filter(|n| alive[*n] && dependencies[*n].contains(&pass)) {
+        remaining[next] -= 1;
+        if remaining[next] == 0 {
+          ready.push(next);
+        }
+      }
+    }
+
+    // walk the passes in order, recording the barriers each one needs
+    let mut image_states: Vec<State> = self.images.iter()
+      .map(|image| match &image.source {
+        ImageSource::Imported(imported) => State::imported(imported.initial_layout),
+        ImageSource::Transient(_) => State::transient(),
+      })
+      .collect();
+    let mut buffer_states = vec![State::imported(vk::ImageLayout::UNDEFINED); self.buffers.len()];
+    let mut usages = vec![vk::ImageUsageFlags::empty(); self.images.len()];
+    let mut barriers = vec![Barrier::default(); pass_count];
+    let mut layouts: Vec<Vec<(usize, vk::ImageLayout)>> = vec![vec![]; pass_count];
+    for pass in order.iter().copied() {
+      for (resource, access) in &image_accesses[pass] {
+        if let Some((old_layout, src_stages, src_access)) = image_states[*resource].transition(*access) {
+          barriers[pass].add(src_stages, access.stages);
+          barriers[pass].images.push(ImageBarrier {
+            image: *resource,
+            old_layout,
+            new_layout: access.layout,
+            src_access,
+            dst_access: access.access,
+          });
+        }
+        layouts[pass].push((*resource, access.layout));
+      }
+      for (image, usage, write) in &self.passes[pass].images {
+        usages[image.0] |= usage.image_usage(*write);
+      }
+      for (resource, access) in &buffer_accesses[pass] {
+        if let Some((_, src_stages, src_access)) = buffer_states[*resource].transition(*access) {
+          barriers[pass].add(src_stages, access.stages);
+          barriers[pass].buffers.push(BufferBarrier {
+            buffer: *resource,
+            src_access,
+            dst_access: access.access,
+          });
+        }
+      }
+    }
+
+    let mut final_barrier = Barrier::default();
+    for (i, image) in self.images.iter().enumerate() {
+      let ImageSource::Imported(ImportedImage { final_layout: Some(final_layout), .. }) = image.source else {
+        continue;
+      };
+      let access = Access {
+        stages: vk::PipelineStageFlags::ALL_COMMANDS,
+        access: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        layout: final_layout,
+        write: false,
+      };
+      if image_states[i].layout != final_layout {
+        let (old_layout, src_stages, src_access) = image_states[i].transition(access).unwrap();
+        final_barrier.add(src_stages, access.stages);
+        final_barrier.images.push(ImageBarrier {
+          image: i,
+          old_layout,
+          new_layout: final_layout,
+          src_access,
+          dst_access: access.access,
+        });
+      }
+    }
+
+    Ok(Plan {
+      order,
+      barriers,
+      layouts,
+      usages,
+      final_barrier,
+    })
+  }
+}
+
+struct Plan {
+  /// Living passes in the order they are recorded.
+  order: Vec<usize>,
+  barriers: Vec<Barrier>,
+  layouts: Vec<Vec<(usize, vk::ImageLayout)>>,
+  /// Usage flags of every image across the living passes, empty when unused.
+  usages: Vec<vk::ImageUsageFlags>,
+  final_barrier: Barrier,
+}
+
+/// Combines the accesses of a pass to the same resource, which must agree on the layout.
+fn merge_accesses(accesses: impl Iterator<Item = (usize, Result<Access>)>) -> Result<Vec<(usize, Access)>> {
+  let mut merged: Vec<(usize, Access)> = vec![];
+  for (resource, access) in accesses {
+    let access = access?;
+    match merged.iter_mut().find(|(r, _)| *r == resource) {
+      Some((_, existing)) => {
+        if existing.layout != access.layout {
+          anyhow::bail!("Resource is used in both {:?} and {:?}", existing.layout, access.layout);
+        }
+        existing.stages |= access.stages;
+        existing.access |= access.access;
+        existing.write |= access.write;
+      }
+      None => merged.push((resource, access)),
+    }
+  }
+  Ok(merged)
+}
+
+#[derive(Default, Clone)]
+struct Hazards {
+  last_writer: Option<usize>,
+  readers: Vec<usize>,
+}
+
+impl Hazards {
+  /// Adds each dependency once, even when passes share several resources, as the topological sort relies on it.
+  fn record(&mut self, pass: usize, write: bool, dependencies: &mut [Vec<usize>], producers: &mut [Vec<usize>]) {
+    if let Some(writer) = self.last_writer {
+      push_unique(&mut dependencies[pass], writer);
+      push_unique(&mut producers[pass], writer);
+    }
+    if write {
+      for reader in self.readers.drain(..).filter(|r| *r != pass) {
+        push_unique(&mut dependencies[pass], reader);
+      }
+      self.last_writer = Some(pass);
+    } else {
+      self.readers.push(pass);
+    }
+  }
+}
+
+fn push_unique(passes: &mut Vec<usize>, pass: usize) {
+  if !passes.contains(&pass) {
+    passes.push(pass);
+  }
+}
+
+#[derive(Copy, Clone)]
+struct State {
+  layout: vk::ImageLayout,
+  stages: vk::PipelineStageFlags,
+  access: vk::AccessFlags,
+  written: bool,
+}
+
+impl State {
+  /// Anything may have happened to the resource before the graph.
+  fn imported(layout: vk::ImageLayout) -> Self {
+    Self {
+      layout,
+      stages: vk::PipelineStageFlags::ALL_COMMANDS,
+      access: vk::AccessFlags::MEMORY_WRITE,
+      written: true,
+    }
+  }
+
+  fn transient() -> Self {
+    Self {
+      layout: vk::ImageLayout::UNDEFINED,
+      stages: vk::PipelineStageFlags::TOP_OF_PIPE,
+      access: vk::AccessFlags::empty(),
+      written: false,
+    }
+  }
+
+  /// Moves to `next`, returning the old layout and the source scope if a barrier is needed.
+  /// Consecutive reads in the same layout share one scope, so a later write waits for all of them.
+  fn transition(&mut self, next: Access) -> Option<(vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags)> {
+    let layout_changes = self.layout != next.layout && next.layout != vk::ImageLayout::UNDEFINED;
+    if !layout_changes && !self.written && !next.write {
+      self.stages |= next.stages;
+      return None;
+    }
+
+    let barrier = (
+      self.layout,
+      self.stages,
+      if self.written { self.access } else { vk::AccessFlags::empty() },
+    );
+    *self = Self {
+      layout: if next.layout == vk::ImageLayout::UNDEFINED { self.layout } else { next.layout },
+      stages: next.stages,
+      access: next.access,
+      written: next.write,
+    };
+    Some(barrier)
+  }
+}
+
+#[derive(Default, Clone)]
+struct Barrier {
+  src_stages: vk::PipelineStageFlags,
+  dst_stages: vk::PipelineStageFlags,
+  images: Vec<ImageBarrier>,
+  buffers: Vec<BufferBarrier>,
+}
+
+impl Barrier {
+  fn add(&mut self, src_stages: vk::PipelineStageFlags, dst_stages: vk::PipelineStageFlags) {
+    self.src_stages |= src_stages;
+    self.dst_stages |= dst_stages;
+  }
+
+  fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, images: &[GraphImage], buffers: &[vk::Buffer]) {
+    if self.images.is_empty() && self.buffers.is_empty() {
+      return;
+    }
+
+    let image_barriers: Vec<_> = self.images.iter()
+      .map(|b| vk::ImageMemoryBarrier {
+        src_access_mask: b.src_access,
+        dst_access_mask: b.dst_access,
+        old_layout: b.old_layout,
+        new_layout: b.new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image: images[b.image].image,
+        subresource_range: vk::ImageSubresourceRange {
+          aspect_mask: images[b.image].aspect_mask,
+          base_mip_level: 0,
+          level_count: vk::REMAINING_MIP_LEVELS,
+          base_array_layer: 0,
+          layer_count: vk::REMAINING_ARRAY_LAYERS,
+        },
+        ..Default::default()
+      })
+      .collect();
+    let buffer_barriers: Vec<_> = self.buffers.iter()
+      .map(|b| vk::BufferMemoryBarrier {
+        src_access_mask: b.src_access,
+        dst_access_mask: b.dst_access,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        buffer: buffers[b.buffer],
+        offset: 0,
+        size: vk::WHOLE_SIZE,
+        ..Default::default()
+      })
+      .collect();
+
+    unsafe {
+      device.cmd_pipeline_barrier(
+        command_buffer,
+        self.src_stages,
+        self.dst_stages,
+        vk::DependencyFlags::empty(),
+        &[],
+        &buffer_barriers,
+        &image_barriers,
+      );
+    }
+  }
+}
+
+#[derive(Clone)]
+struct ImageBarrier {
+  image: usize,
+  old_layout: vk::ImageLayout,
+  new_layout: vk::ImageLayout,
+  src_access: vk::AccessFlags,
+  dst_access: vk::AccessFlags,
+}
+
+#[derive(Clone)]
+struct BufferBarrier {
+  buffer: usize,
+  src_access: vk::AccessFlags,
+  dst_access: vk::AccessFlags,
+}
+
+pub struct PassBuilder<'a, 'g> {
+  graph: &'a mut RenderGraph<'g>,
+  name: String,
+  images: Vec<(ImageHandle, ImageUsage, bool)>,
+  buffers: Vec<(BufferHandle, BufferUsage, bool)>,
+  side_effects: bool,
+}
+
+impl<'a, 'g> PassBuilder<'a, 'g> {
+  pub fn with_read_image(mut self, image: ImageHandle, usage: ImageUsage) -> Self {
+    self.images.push((image, usage, false));
+    self
+  }
+
+  pub fn with_write_image(mut self, image: ImageHandle, usage: ImageUsage) -> Self {
+    self.images.push((image, usage, true));
+    self
+  }
+
+  pub fn with_read_buffer(mut self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+    self.buffers.push((buffer, usage, false));
+    self
+  }
+
+  pub fn with_write_buffer(mut self, buffer: BufferHandle, usage: BufferUsage) -> Self {
+    self.buffers.push((buffer, usage, true));
+    self
+  }
+
+  /// Never culls the pass, e.g. when it writes to something the graph doesn't know about.
+  pub fn with_side_effects(mut self) -> Self {
+    self.side_effects = true;
+    self
+  }
+
+  /// Adds the pass to the graph, recording its commands with `record` when the graph executes.
+  pub fn record(self, record: impl FnOnce(&PassContext<'_>) + 'g) {
+    self.graph.passes.push(Pass {
+      name: self.name,
+      images: self.images,
+      buffers: self.buffers,
+      side_effects: self.side_effects,
+      record: Box::new(record),
+    });
+  }
+}
+
+/// A graph image resolved to its vulkan objects.
+#[derive(Debug, Copy, Clone)]
+pub struct GraphImage {
+  pub image: vk::Image,
+  pub view: vk::ImageView,
+  pub format: vk::Format,
+  pub extent: vk::Extent2D,
+  pub aspect_mask: vk::ImageAspectFlags,
+}
+
+/// Handed to a pass while it records.
+pub struct PassContext<'a> {
+  pub device: &'a ash::Device,
+  pub command_buffer: vk::CommandBuffer,
+  images: &'a [GraphImage],
+  buffers: &'a [vk::Buffer],
+  layouts: &'a [(usize, vk::ImageLayout)],
+}
+
+impl PassContext<'_> {
+  pub fn image(&self, image: ImageHandle) -> &GraphImage {
+    &self.images[image.0]
+  }
+
+  pub fn buffer(&self, buffer: BufferHandle) -> vk::Buffer {
+    self.buffers[buffer.0]
+  }
+
+  /// The layout the image is in during this pass. `UNDEFINED` if the pass didn't declare it.
+  pub fn layout(&self, image: ImageHandle) -> vk::ImageLayout {
+    self.layouts.iter()
+      .find(|(i, _)| *i == image.0)
+      .map_or(vk::ImageLayout::UNDEFINED, |(_, layout)| *layout)
+  }
+
+  /// An attachment for `RenderContext::cmd_begin_rendering`, in the layout the graph transitioned the image to.
+  pub fn attachment(&self, image: ImageHandle, load_op: vk::AttachmentLoadOp, clear_value: vk::ClearValue) -> vk::RenderingAttachmentInfo {
+    vk::RenderingAttachmentInfo {
+      image_view: self.image(image).view,
+      image_layout: self.layout(image),
+      load_op,
+      store_op: vk::AttachmentStoreOp::STORE,
+      clear_value,
+      ..Default::default()
+    }
+  }
+}
+
+struct CompiledPass<'g> {
+  record: RecordFn<'g>,
+  barrier: Barrier,
+  layouts: Vec<(usize, vk::ImageLayout)>,
+}
+
+struct PassInfo {
+  name: String,
+  /// `None` when culled.
+  order: Option<usize>,
+  images: Vec<(ImageHandle, ImageUsage, bool)>,
+  buffers: Vec<(BufferHandle, BufferUsage, bool)>,
+  barrier_count: usize,
+}
+
+struct GraphInfo {
+  /// (name, transient, output)
+  images: Vec<(String, bool, bool)>,
+  /// (name, output)
+  buffers: Vec<(String, bool)>,
+  passes: Vec<PassInfo>,
+}
+
+/// Transient images owned by an executed graph. Must be kept alive until the gpu has finished the graph's commands.
+#[derive(Default)]
+pub struct GraphResources {
+  #[allow(unused)]
+  transients: Vec<TransientResource>,
+}
+
+struct TransientResource {
+  device: Arc<ash::Device>,
+  image: Image,
+  view: vk::ImageView,
+}
+
+impl TransientResource {
+  fn new(context: &RenderContext, transient: &TransientImage, usage: vk::ImageUsageFlags, aspect_mask: vk::ImageAspectFlags) -> Result<Self> {
+    let image_info = vk::ImageCreateInfo {
+      image_type: vk::ImageType::TYPE_2D,
+      format: transient.format,
+      extent: vk::Extent3D {
+        width: transient.extent.width,
+        height: transient.extent.height,
+        depth: 1,
+      },
+      mip_levels: 1,
+      array_layers: 1,
+      samples: transient.samples,
+      tiling: vk::ImageTiling::OPTIMAL,
+      usage,
+      sharing_mode: vk::SharingMode::EXCLUSIVE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      ..Default::default()
+    };
+    let image = Image::new(context, image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+    let view_info = vk::ImageViewCreateInfo {
+      image: image.image,
+      view_type: vk::ImageViewType::TYPE_2D,
+      format: transient.format,
+      subresource_range: vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+      },
+      ..Default::default()
+    };
+    let view = unsafe {
+      context.device().create_image_view(&view_info, None)
+    }.context("Failed to create transient image view")?;
+
+    Ok(Self {
+      device: context.device(),
+      image,
+      view,
+    })
+  }
+}
+
+impl Drop for TransientResource {
+  fn drop(&mut self) {
+    unsafe {
+      self.device.destroy_image_view(self.view, None);
+    }
+  }
+}
+
+/// A graph with its passes ordered, barriers placed and transient images created, ready to record.
+pub struct CompiledGraph<'g> {
+  images: Vec<GraphImage>,
+  buffers: Vec<vk::Buffer>,
+  passes: Vec<CompiledPass<'g>>,
+  final_barrier: Barrier,
+  resources: GraphResources,
+  info: GraphInfo,
+}
+
+impl<'g> CompiledGraph<'g> {
+  /// How many passes survived culling.
+  pub fn pass_count(&self) -> usize {
+    self.passes.len()
+  }
+
+  /// Records every pass with its barriers into `command_buffer`.
+  pub fn execute(self, device: &ash::Device, command_buffer: vk::CommandBuffer) -> GraphResources {
+    for pass in self.passes {
+      pass.barrier.record(device, command_buffer, &self.images, &self.buffers);
+      (pass.record)(&PassContext {
+        device,
+        command_buffer,
+        images: &self.images,
+        buffers: &self.buffers,
+        layouts: &pass.layouts,
+      });
+    }
+    self.final_barrier.record(device, command_buffer, &self.images, &self.buffers);
+    self.resources
+  }
+
+  /// The graph in Graphviz DOT format. Culled passes are dashed, transient images dotted, outputs doubled.
+  pub fn to_dot(&self) -> String {
+    let mut dot = String::from("digraph render_graph {\n  rankdir=LR;\n");
+
+    for (i, (name, transient, output)) in self.info.images.iter().enumerate() {
+      let style = if *transient { ", style=dotted" } else { "" };
+      let peripheries = if *output { ", peripheries=2" } else { "" };
+      let _ = writeln!(dot, "  image{i} [label=\"{}\", shape=ellipse{style}{peripheries}];", escape(name));
+    }
+    for (i, (name, output)) in self.info.buffers.iter().enumerate() {
+      let peripheries = if *output { ", peripheries=2" } else { "" };
+      let _ = writeln!(dot, "  buffer{i} [label=\"{}\", shape=cylinder{peripheries}];", escape(name));
+    }
+
+    for (p, pass) in self.info.passes.iter().enumerate() {
+      let label = match pass.order {
+        Some(order) => format!("#{order} {}\\n{} barriers", escape(&pass.name), pass.barrier_count),
+        None => format!("{}\\nculled", escape(&pass.name)),
+      };
+      let style = if pass.order.is_some() { "" } else { ", style=dashed" };
+      let _ = writeln!(dot, "  pass{p} [label=\"{label}\", shape=box{style}];");
+
+      let edges = pass.images.iter().map(|(h, usage, write)| (format!("image{}", h.0), format!("{usage:?}"), *write))
+        .chain(pass.buffers.iter().map(|(h, usage, write)| (format!("buffer{}", h.0), format!("{usage:?}"), *write)));
+      for (resource, usage, write) in edges {
+        let label = escape(&usage);
+        if write {
+          let _ = writeln!(dot, "  pass{p} -> {resource} [label=\"{label}\"];");
+        } else {
+          let _ = writeln!(dot, "  {resource} -> pass{p} [label=\"{label}\"];");
+        }
+      }
+    }
+
+    dot.push_str("}\n");
+    dot
+  }
+}
+
+fn escape(text: &str) -> String {
+  text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAGMENT: ImageUsage = ImageUsage::Sampled(vk::ShaderStageFlags::FRAGMENT);
+
+  fn imported(final_layout: Option<vk::ImageLayout>) -> ImportedImage {
+    ImportedImage {
+      image: vk::Image::null(),
+      view: vk::ImageView::null(),
+      format: vk::Format::B8G8R8A8_SRGB,
+      extent: vk::Extent2D { width: 4, height: 4 },
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      final_layout,
+    }
+  }
+
+  fn transient() -> TransientImage {
+    TransientImage::new(vk::Format::R16G16B16A16_SFLOAT, vk::Extent2D { width: 4, height: 4 })
+  }
+
+  fn output(graph: &mut RenderGraph<'_>, name: &str) -> ImageHandle {
+    let handle = graph.import_image(name, imported(None));
+    graph.mark_output(handle);
+    handle
+  }
+
+  fn layouts(barrier: &Barrier) -> Vec<(usize, vk::ImageLayout, vk::ImageLayout)> {
+    barrier.images.iter().map(|b| (b.image, b.old_layout, b.new_layout)).collect()
+  }
+
+  #[test]
+  fn orders_living_passes_in_declaration_order() -> Result<()> {
+    let mut graph = RenderGraph::new();
+    let color = graph.create_image("color", transient());
+    let first = output(&mut graph, "first");
+    let second = output(&mut graph, "second");
+    graph.add_pass("draw").with_write_image(color, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("second").with_write_image(second, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("first").with_read_image(color, FRAGMENT).with_write_image(first, ImageUsage::ColorAttachment).record(|_| {});
+
+    assert_eq!(graph.plan()?.order, [0, 1, 2]);
+    Ok(())
+  }
+
+  #[test]
+  fn culls_passes_contributing_to_no_output() -> Result<()> {
+    let mut graph = RenderGraph::new();
+    let unused = graph.create_image("unused", transient());
+    let frame = output(&mut graph, "frame");
+    graph.add_pass("unused").with_write_image(unused, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("draw").with_write_image(frame, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("upload").with_side_effects().record(|_| {});
+
+    let plan = graph.plan()?;
+    assert_eq!(plan.order, [1, 2]);
+    assert!(plan.usages[unused.0].is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn keeps_passes_reading_several_resources_of_one_producer() -> Result<()> {
+    let mut graph = RenderGraph::new();
+    let albedo = graph.create_image("albedo", transient());
+    let normal = graph.create_image("normal", transient());
+    let frame = output(&mut graph, "frame");
+    graph.add_pass("gbuffer")
+      .with_write_image(albedo, ImageUsage::ColorAttachment)
+      .with_write_image(normal, ImageUsage::ColorAttachment)
+      .record(|_| {});
+    graph.add_pass("lighting")
+      .with_read_image(albedo, FRAGMENT)
+      .with_read_image(normal, FRAGMENT)
+      .with_write_image(frame, ImageUsage::ColorAttachment)
+      .record(|_| {});
+
+    assert_eq!(graph.plan()?.order, [0, 1]);
+    Ok(())
+  }
+
+  #[test]
+  fn places_barriers_between_passes() -> Result<()> {
+    let mut graph = RenderGraph::new();
+    let color = graph.create_image("color", transient());
+    let frame = graph.import_image("frame", imported(Some(vk::ImageLayout::PRESENT_SRC_KHR)));
+    graph.mark_output(frame);
+    graph.add_pass("draw").with_write_image(color, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("blit").with_read_image(color, FRAGMENT).with_write_image(frame, ImageUsage::ColorAttachment).record(|_| {});
+
+    let plan = graph.plan()?;
+    assert_eq!(layouts(&plan.barriers[0]), [
+      (color.0, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    ]);
+    // a fresh transient has nothing to wait for
+    assert_eq!(plan.barriers[0].images[0].src_access, vk::AccessFlags::empty());
+
+    let blit = &plan.barriers[1];
+    assert_eq!(layouts(blit), [
+      (color.0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      (frame.0, vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    ]);
+    assert!(blit.images[0].src_access.contains(vk::AccessFlags::COLOR_ATTACHMENT_WRITE));
+    assert!(blit.src_stages.contains(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT));
+    assert!(blit.dst_stages.contains(vk::PipelineStageFlags::FRAGMENT_SHADER));
+    assert_eq!(plan.layouts[1], [
+      (color.0, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+      (frame.0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+    ]);
+
+    assert_eq!(layouts(&plan.final_barrier), [
+      (frame.0, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR),
+    ]);
+    Ok(())
+  }
+
+  #[test]
+  fn consecutive_reads_share_a_barrier() -> Result<()> {
+    let mut graph = RenderGraph::new();
+    let color = graph.create_image("color", transient());
+    let first = output(&mut graph, "first");
+    let second = output(&mut graph, "second");
+    graph.add_pass("draw").with_write_image(color, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("first").with_read_image(color, FRAGMENT).with_write_image(first, ImageUsage::ColorAttachment).record(|_| {});
+    graph.add_pass("second").with_read_image(color, FRAGMENT).with_write_image(second, ImageUsage::ColorAttachment).record(|_| {});
+
+    let plan = graph.plan()?;
+    assert!(plan.barriers[2].images.iter().all(|b| b.image != color.0));
+    Ok(())
+  }
+
+  #[test]
+  fn rejects_reading_unwritten_transients() {
+    let mut graph = RenderGraph::new();
+    let color = graph.create_image("color", transient());
+    let frame = output(&mut graph, "frame");
+    graph.add_pass("blit").with_read_image(color, FRAGMENT).with_write_image(frame, ImageUsage::ColorAttachment).record(|_| {});
+
+    assert!(graph.plan().is_err());
+  }
+}
@@ -2,11 +2,14 @@ use std::sync::Arc;
 use ash::{self, vk};
 use anyhow::{Context, Result};
 use crate::graphics::context::RenderContext;
+use crate::graphics::memory::Allocation;
+
+pub mod storage;
 
 pub struct Image {
   device: Arc<ash::Device>,
   pub image: vk::Image,
-  pub memory: vk::DeviceMemory,
+  allocation: Allocation,
   pub extent: vk::Extent3D,
   pub layer_count: u32,
 }
@@ -21,18 +24,7 @@ impl Image {
       context.device().create_image(&image_info, None)
     }.context("Failed to create image")?;
 
-    let memory_reqs = unsafe {
-      context.device().get_image_memory_requirements(image)
-    };
-
-    let allocation_info = vk::MemoryAllocateInfo {
-      memory_type_index: context.find_memory_type(memory_reqs.memory_type_bits, properties).heap_index,
-      ..Default::default()
-    };
-
-    let memory = match unsafe {
-      context.device().allocate_memory(&allocation_info, None)
-    }.context("Failed to allocate memory for image") {
+    let allocation = match context.allocator().allocate_for_image(image, image_info.tiling, properties) {
       Ok(value) => value,
       Err(err) => unsafe {
         context.device().destroy_image(image, None);
@@ -40,28 +32,88 @@ impl Image {
       }
     };
 
-    if let Err(err) = unsafe {
-      context.device().bind_image_memory(image, memory, 0)
-    }.context("Failed to bind image memory") {
-      unsafe {
-        context.device().destroy_image(image, None);
-        context.device().free_memory(memory, None);
-      }
-      Err(err)?
-    };
-
     Ok(Self {
       device: context.device(),
       image,
-      memory,
+      allocation,
       extent: image_info.extent,
       layer_count: image_info.array_layers,
     })
   }
 
+  pub fn allocation(&self) -> &Allocation {
+    &self.allocation
+  }
+
   unsafe fn free(&mut self) {
+    // the allocation is returned to its pool once the field drops
     self.device.destroy_image(self.image, None);
-    self.device.free_memory(self.memory, None);
+  }
+}
+
+/// Records a layout transition barrier, deriving access masks and stages from the layouts involved.
+pub(crate) fn transition_layout(
+  device: &ash::Device,
+  command_buffer: vk::CommandBuffer,
+  image: vk::Image,
+  subresource_range: vk::ImageSubresourceRange,
+  old_layout: vk::ImageLayout,
+  new_layout: vk::ImageLayout,
+) {
+  let (src_access_mask, src_stage) = layout_access(old_layout);
+  let (dst_access_mask, dst_stage) = layout_access(new_layout);
+
+  let barrier = vk::ImageMemoryBarrier {
+    src_access_mask,
+    dst_access_mask,
+    old_layout,
+    new_layout,
+    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+    image,
+    subresource_range,
+    ..Default::default()
+  };
+
+  unsafe {
+    device.cmd_pipeline_barrier(
+      command_buffer,
+      src_stage,
+      dst_stage,
+      vk::DependencyFlags::empty(),
+      &[],
+      &[],
+      &[barrier],
+    );
+  }
+}
+
+fn layout_access(layout: vk::ImageLayout) -> (vk::AccessFlags, vk::PipelineStageFlags) {
+  match layout {
+    vk::ImageLayout::UNDEFINED => (vk::AccessFlags::empty(), vk::PipelineStageFlags::TOP_OF_PIPE),
+    vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::AccessFlags::TRANSFER_WRITE, vk::PipelineStageFlags::TRANSFER),
+    vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::AccessFlags::TRANSFER_READ, vk::PipelineStageFlags::TRANSFER),
+    vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+      vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+      vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+    ),
+    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (
+      vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+      vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+    ),
+    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+      vk::AccessFlags::SHADER_READ,
+      vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+    ),
+    vk::ImageLayout::GENERAL => (
+      vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+      vk::PipelineStageFlags::ALL_COMMANDS,
+    ),
+    vk::ImageLayout::PRESENT_SRC_KHR => (vk::AccessFlags::empty(), vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+    _ => (
+      vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+      vk::PipelineStageFlags::ALL_COMMANDS,
+    ),
   }
 }
 
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::{self, vk};
+
+use crate::graphics::context::RenderContext;
+use crate::graphics::image::{Image, transition_layout};
+use crate::graphics::readback::{check_readback_format, read_image, RgbaImage};
+
+/// A 2D image compute shaders read and write through a storage image binding. Always in `GENERAL` layout.
+pub struct StorageImage {
+  device: Arc<ash::Device>,
+  image: Image,
+  view: vk::ImageView,
+  format: vk::Format,
+}
+
+impl StorageImage {
+  pub const DEFAULT_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+  pub fn new(context: &RenderContext, width: u32, height: u32) -> Result<Self> {
+    Self::with_format(context, width, height, Self::DEFAULT_FORMAT)
+  }
+
+  /// Any format with storage image support works, but only 8 bit RGBA and BGRA images can use `read_pixels`.
+  pub fn with_format(context: &RenderContext, width: u32, height: u32, format: vk::Format) -> Result<Self> {
+    if !context.format_properties(format).optimal_tiling_features.contains(vk::FormatFeatureFlags::STORAGE_IMAGE) {
+      anyhow::bail!("{format:?} can't be used as a storage image on this device");
+    }
+
+    let image_info = vk::ImageCreateInfo {
+      image_type: vk::ImageType::TYPE_2D,
+      format,
+      extent: vk::Extent3D {
+        width,
+        height,
+        depth: 1,
+      },
+      mip_levels: 1,
+      array_layers: 1,
+      samples: vk::SampleCountFlags::TYPE_1,
+      tiling: vk::ImageTiling::OPTIMAL,
+      usage: vk::ImageUsageFlags::STORAGE
+        | vk::ImageUsageFlags::TRANSFER_SRC
+        | vk::ImageUsageFlags::TRANSFER_DST
+        | vk::ImageUsageFlags::SAMPLED,
+      sharing_mode: vk::SharingMode::EXCLUSIVE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      ..Default::default()
+    };
+    let image = Image::new(context, image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+    let range = vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level: 0,
+      level_count: 1,
+      base_array_layer: 0,
+      layer_count: 1,
+    };
+    let device = context.device();
+    let command_buffer = context.begin_single_time_commands()?;
+    transition_layout(&device, command_buffer, image.image, range, vk::ImageLayout::UNDEFINED, vk::ImageLayout::GENERAL);
+    context.end_single_time_commands(command_buffer).context("Failed to transition storage image")?;
+
+    let view_info = vk::ImageViewCreateInfo {
+      image: image.image,
+      view_type: vk::ImageViewType::TYPE_2D,
+      format,
+      subresource_range: range,
+      ..Default::default()
+    };
+    let view = unsafe {
+      device.create_image_view(&view_info, None)
+    }.context("Failed to create storage image view")?;
+
+    Ok(Self {
+      device,
+      image,
+      view,
+      format,
+    })
+  }
+
+  pub fn image(&self) -> &Image {
+    &self.image
+  }
+
+  pub fn view(&self) -> vk::ImageView {
+    self.view
+  }
+
+  pub fn format(&self) -> vk::Format {
+    self.format
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    vk::Extent2D {
+      width: self.image.extent.width,
+      height: self.image.extent.height,
+    }
+  }
+
+  /// Copies the image's contents into host memory as RGBA. Blocks until the gpu is done.
+  /// Only 8 bit RGBA and BGRA formats can be read back, other formats fail before anything is copied.
+  pub fn read_pixels(&self, context: &RenderContext) -> Result<RgbaImage> {
+    check_readback_format(self.format)?;
+    read_image(context, self.image.image, vk::ImageLayout::GENERAL, self.extent(), self.format)
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_image_view(self.view, None);
+  }
+}
+
+impl Drop for StorageImage {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
@@ -0,0 +1,705 @@
+use std::ffi;
+use std::ptr::NonNull;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::{Context, Result};
+use ash::{self, vk};
+use tracing::{trace, warn};
+
+/// Whether memory backs a linear resource, i.e. a buffer or a linearly tiled image, or an optimally tiled image.
+///
+/// The two are kept in separate pools so neighbouring sub-allocations never violate `bufferImageGranularity`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResourceKind {
+  Linear,
+  Optimal,
+}
+
+impl ResourceKind {
+  pub fn from_tiling(tiling: vk::ImageTiling) -> Self {
+    match tiling {
+      vk::ImageTiling::LINEAR => ResourceKind::Linear,
+      _ => ResourceKind::Optimal,
+    }
+  }
+}
+
+/// Memory usage, either for the whole allocator or a single heap.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryStats {
+  pub block_count: usize,
+  pub dedicated_count: usize,
+  /// Live allocations, dedicated ones included.
+  pub allocation_count: usize,
+  /// Bytes allocated from the driver.
+  pub reserved_bytes: vk::DeviceSize,
+  /// Bytes handed out to resources. The difference to `reserved_bytes` is free space in blocks.
+  pub used_bytes: vk::DeviceSize,
+}
+
+impl MemoryStats {
+  fn add(&mut self, other: &MemoryStats) {
+    self.block_count += other.block_count;
+    self.dedicated_count += other.dedicated_count;
+    self.allocation_count += other.allocation_count;
+    self.reserved_bytes += other.reserved_bytes;
+    self.used_bytes += other.used_bytes;
+  }
+}
+
+/// Sub-allocates device memory out of large blocks, one pool per memory type and resource kind,
+/// keeping the number of `vkAllocateMemory` calls well below `maxMemoryAllocationCount`.
+///
+/// Large resources, and those the driver prefers to have their own memory, get dedicated allocations.
+pub struct Allocator {
+  device: Arc<ash::Device>,
+  memory_properties: vk::PhysicalDeviceMemoryProperties,
+  non_coherent_atom_size: vk::DeviceSize,
+  block_size: vk::DeviceSize,
+  pools: Vec<Mutex<MemoryPool>>,
+  destroyed: AtomicBool,
+}
+
+impl Allocator {
+  pub const DEFAULT_BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+  pub(crate) fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: Arc<ash::Device>) -> Self {
+    let memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+
+    let pools = (0..memory_properties.memory_type_count)
+      .flat_map(|i| [ResourceKind::Linear, ResourceKind::Optimal].map(|kind| Mutex::new(MemoryPool::new(i, kind))))
+      .collect();
+
+    Self {
+      device,
+      memory_properties,
+      non_coherent_atom_size: properties.limits.non_coherent_atom_size.max(1),
+      block_size: Self::DEFAULT_BLOCK_SIZE,
+      pools,
+      destroyed: AtomicBool::new(false),
+    }
+  }
+
+  /// Allocates and binds memory for `buffer`.
+  pub fn allocate_for_buffer(
+    self: &Arc<Self>,
+    buffer: vk::Buffer,
+    properties: vk::MemoryPropertyFlags,
+  ) -> Result<Allocation> {
+    let mut dedicated_reqs = vk::MemoryDedicatedRequirements::default();
+    let mut reqs = vk::MemoryRequirements2 {
+      p_next: &mut dedicated_reqs as *mut _ as *mut ffi::c_void,
+      ..Default::default()
+    };
+    unsafe {
+      self.device.get_buffer_memory_requirements2(&vk::BufferMemoryRequirementsInfo2 {
+        buffer,
+        ..Default::default()
+      }, &mut reqs);
+    }
+
+    let dedicated = (dedicated_reqs.prefers_dedicated_allocation == vk::TRUE)
+      .then_some(vk::MemoryDedicatedAllocateInfo { buffer, ..Default::default() });
+    let allocation = self.allocate(reqs.memory_requirements, properties, ResourceKind::Linear, dedicated)?;
+
+    unsafe {
+      self.device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)
+    }.context("Failed to bind buffer memory")?;
+
+    Ok(allocation)
+  }
+
+  /// Allocates and binds memory for `image`, created with `tiling`.
+  pub fn allocate_for_image(
+    self: &Arc<Self>,
+    image: vk::Image,
+    tiling: vk::ImageTiling,
+    properties: vk::MemoryPropertyFlags,
+  ) -> Result<Allocation> {
+    let mut dedicated_reqs = vk::MemoryDedicatedRequirements::default();
+    let mut reqs = vk::MemoryRequirements2 {
+      p_next: &mut dedicated_reqs as *mut _ as *mut ffi::c_void,
+      ..Default::default()
+    };
+    unsafe {
+      self.device.get_image_memory_requirements2(&vk::ImageMemoryRequirementsInfo2 {
+        image,
+        ..Default::default()
+      }, &mut reqs);
+    }
+
+    let dedicated = (dedicated_reqs.prefers_dedicated_allocation == vk::TRUE)
+      .then_some(vk::MemoryDedicatedAllocateInfo { image, ..Default::default() });
+    let allocation = self.allocate(reqs.memory_requirements, properties, ResourceKind::from_tiling(tiling), dedicated)?;
+
+    unsafe {
+      self.device.bind_image_memory(image, allocation.memory, allocation.offset)
+    }.context("Failed to bind image memory")?;
+
+    Ok(allocation)
+  }
+
+  /// Allocates memory satisfying `requirements`. The caller is responsible for binding it.
+  ///
+  /// Passing `dedicated` forces a dedicated allocation for that resource, as does a size of
+  /// at least half a block.
+  pub fn allocate(
+    self: &Arc<Self>,
+    requirements: vk::MemoryRequirements,
+    properties: vk::MemoryPropertyFlags,
+    kind: ResourceKind,
+    dedicated: Option<vk::MemoryDedicatedAllocateInfo>,
+  ) -> Result<Allocation> {
+    let memory_type_index = self.find_memory_type_index(requirements.memory_type_bits, properties)?;
+    let pool_index = Self::pool_index(memory_type_index, kind);
+    let block_size = self.block_size(memory_type_index);
+
+    if dedicated.is_some() || requirements.size >= block_size / 2 {
+      return self.allocate_dedicated(requirements.size, memory_type_index, pool_index, dedicated);
+    }
+
+    let mut pool = self.pool(pool_index);
+    let (block, offset) = match pool.allocate(requirements.size, requirements.alignment) {
+      Some(value) => value,
+      None => {
+        let memory = self.allocate_memory(block_size, memory_type_index, None)?;
+        let mapped = match self.map(memory, memory_type_index) {
+          Ok(value) => value,
+          Err(err) => unsafe {
+            self.device.free_memory(memory, None);
+            Err(err)?
+          }
+        };
+        trace!("Allocated {block_size} byte memory block for memory type {memory_type_index}");
+        pool.add_block(memory, block_size, mapped);
+        pool.allocate(requirements.size, requirements.alignment)
+          .context("Allocation does not fit in a fresh memory block")?
+      }
+    };
+    let block_ref = pool.block(block).unwrap();
+
+    Ok(Allocation {
+      allocator: self.clone(),
+      pool: pool_index,
+      block: Some(block),
+      memory: block_ref.memory,
+      offset,
+      size: requirements.size,
+      memory_size: block_ref.size,
+      mapped: block_ref.mapped.map(|ptr| unsafe { NonNull::new_unchecked(ptr.as_ptr().add(offset as usize)) }),
+      coherent: self.is_coherent(memory_type_index),
+    })
+  }
+
+  /// Totals over every memory type.
+  pub fn stats(&self) -> MemoryStats {
+    let mut stats = MemoryStats::default();
+    for pool in &self.pools {
+      stats.add(&self.lock(pool).stats());
+    }
+    stats
+  }
+
+  /// Usage of each memory heap, indexed by heap index.
+  pub fn heap_stats(&self) -> Vec<MemoryStats> {
+    let mut stats = vec![MemoryStats::default(); self.memory_properties.memory_heap_count as usize];
+    for pool in &self.pools {
+      let pool = self.lock(pool);
+      let heap_index = self.memory_properties.memory_types[pool.memory_type_index as usize].heap_index;
+      stats[heap_index as usize].add(&pool.stats());
+    }
+    stats
+  }
+
+  pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+    &self.memory_properties
+  }
+
+  pub fn find_memory_type_index(&self, type_filter: u32, properties: vk::MemoryPropertyFlags) -> Result<u32> {
+    (0..self.memory_properties.memory_type_count)
+      .find(|i| {
+        (type_filter & (1 << i)) != 0
+          && self.memory_properties.memory_types[*i as usize].property_flags.contains(properties)
+      })
+      .context("Failed to find supported memory type")
+  }
+
+  /// Frees every block. Must run before the device is destroyed.
+  /// Allocations still alive afterwards are leaked and their drop does nothing.
+  pub(crate) unsafe fn destroy(&self) {
+    self.destroyed.store(true, Ordering::Release);
+
+    let stats = self.stats();
+    if stats.allocation_count > 0 {
+      warn!("{} allocations ({} bytes) outlived the allocator", stats.allocation_count, stats.used_bytes);
+    }
+
+    for pool in &self.pools {
+      for block in self.lock(pool).blocks.drain(..) {
+        self.device.free_memory(block.memory, None);
+      }
+    }
+  }
+
+  fn allocate_dedicated(
+    self: &Arc<Self>,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    pool_index: usize,
+    dedicated: Option<vk::MemoryDedicatedAllocateInfo>,
+  ) -> Result<Allocation> {
+    let memory = self.allocate_memory(size, memory_type_index, dedicated)?;
+    let mapped = match self.map(memory, memory_type_index) {
+      Ok(value) => value,
+      Err(err) => unsafe {
+        self.device.free_memory(memory, None);
+        Err(err)?
+      }
+    };
+
+    let mut pool = self.pool(pool_index);
+    pool.dedicated_count += 1;
+    pool.dedicated_bytes += size;
+
+    Ok(Allocation {
+      allocator: self.clone(),
+      pool: pool_index,
+      block: None,
+      memory,
+      offset: 0,
+      size,
+      memory_size: size,
+      mapped,
+      coherent: self.is_coherent(memory_type_index),
+    })
+  }
+
+  fn allocate_memory(
+    &self,
+    size: vk::DeviceSize,
+    memory_type_index: u32,
+    dedicated: Option<vk::MemoryDedicatedAllocateInfo>,
+  ) -> Result<vk::DeviceMemory> {
+    let allocate_info = vk::MemoryAllocateInfo {
+      p_next: dedicated.as_ref().map_or(std::ptr::null(), |d| d as *const _ as *const ffi::c_void),
+      allocation_size: size,
+      memory_type_index,
+      ..Default::default()
+    };
+
+    unsafe {
+      self.device.allocate_memory(&allocate_info, None)
+    }.context("Failed to allocate device memory")
+  }
+
+  /// Host visible memory stays mapped for its whole lifetime.
+  fn map(&self, memory: vk::DeviceMemory, memory_type_index: u32) -> Result<Option<NonNull<u8>>> {
+    let flags = self.memory_properties.memory_types[memory_type_index as usize].property_flags;
+    if !flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE) {
+      return Ok(None);
+    }
+
+    let ptr = unsafe {
+      self.device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+    }.context("Failed to map device memory")?;
+
+    Ok(NonNull::new(ptr as *mut u8))
+  }
+
+  fn free(&self, allocation: &Allocation) {
+    if self.destroyed.load(Ordering::Acquire) {
+      return;
+    }
+
+    let mut pool = self.pool(allocation.pool);
+    match allocation.block {
+      Some(block) => if let Some(memory) = pool.free(block, allocation.offset, allocation.size) {
+        unsafe { self.device.free_memory(memory, None) };
+      },
+      None => {
+        pool.dedicated_count -= 1;
+        pool.dedicated_bytes -= allocation.size;
+        unsafe { self.device.free_memory(allocation.memory, None) };
+      }
+    }
+  }
+
+  fn block_size(&self, memory_type_index: u32) -> vk::DeviceSize {
+    let heap_index = self.memory_properties.memory_types[memory_type_index as usize].heap_index;
+    let heap_size = self.memory_properties.memory_heaps[heap_index as usize].size;
+    // small heaps, like the 256MiB BAR heap, shouldn't be eaten up by a handful of blocks
+    self.block_size.min(heap_size / 8)
+  }
+
+  fn is_coherent(&self, memory_type_index: u32) -> bool {
+    self.memory_properties.memory_types[memory_type_index as usize].property_flags
+      .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+  }
+
+  fn pool_index(memory_type_index: u32, kind: ResourceKind) -> usize {
+    memory_type_index as usize * 2 + (kind == ResourceKind::Optimal) as usize
+  }
+
+  fn pool(&self, pool_index: usize) -> MutexGuard<'_, MemoryPool> {
+    self.lock(&self.pools[pool_index])
+  }
+
+  fn lock<'a>(&self, pool: &'a Mutex<MemoryPool>) -> MutexGuard<'a, MemoryPool> {
+    // a panic mid-allocation leaves the free lists consistent, so poisoning can be ignored
+    pool.lock().unwrap_or_else(|err| err.into_inner())
+  }
+}
+
+/// A range of device memory bound to a resource, returned to its pool on drop.
+pub struct Allocation {
+  allocator: Arc<Allocator>,
+  pool: usize,
+  /// `None` for dedicated allocations.
+  block: Option<u64>,
+  memory: vk::DeviceMemory,
+  offset: vk::DeviceSize,
+  size: vk::DeviceSize,
+  /// Size of the whole `memory`, i.e. of the block or the dedicated allocation.
+  memory_size: vk::DeviceSize,
+  mapped: Option<NonNull<u8>>,
+  coherent: bool,
+}
+
+// the mapping is only accessed through `&self` methods copying whole ranges, with the memory
+// itself owned by this allocation
+unsafe impl Send for Allocation {}
+unsafe impl Sync for Allocation {}
+
+impl Allocation {
+  pub fn memory(&self) -> vk::DeviceMemory {
+    self.memory
+  }
+
+  pub fn offset(&self) -> vk::DeviceSize {
+    self.offset
+  }
+
+  pub fn size(&self) -> vk::DeviceSize {
+    self.size
+  }
+
+  pub fn is_dedicated(&self) -> bool {
+    self.block.is_none()
+  }
+
+  /// Pointer to the start of the allocation, if it lives in host visible memory.
+  pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+    self.mapped
+  }
+
+  /// Copies `data` into the allocation at `offset`, flushing it for the device.
+  pub fn write(&self, offset: vk::DeviceSize, data: &[u8]) -> Result<()> {
+    let ptr = self.mapped_range(offset, data.len() as vk::DeviceSize)?;
+    unsafe {
+      std::ptr::copy_nonoverlapping(data.as_ptr(), ptr, data.len());
+    }
+    self.flush(offset, data.len() as vk::DeviceSize)
+  }
+
+  /// Copies `size` bytes starting at `offset` out of the allocation, after making device writes visible.
+  pub fn read(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<Vec<u8>> {
+    let ptr = self.mapped_range(offset, size)?;
+    self.invalidate(offset, size)?;
+    Ok(unsafe { std::slice::from_raw_parts(ptr, size as usize) }.to_vec())
+  }
+
+  /// Makes host writes visible to the device. Does nothing for coherent memory.
+  pub fn flush(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<()> {
+    if self.coherent {
+      return Ok(());
+    }
+    unsafe {
+      self.allocator.device.flush_mapped_memory_ranges(&[self.atom_range(offset, size)])
+    }.context("Failed to flush mapped memory")
+  }
+
+  /// Makes device writes visible to the host. Does nothing for coherent memory.
+  pub fn invalidate(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<()> {
+    if self.coherent {
+      return Ok(());
+    }
+    unsafe {
+      self.allocator.device.invalidate_mapped_memory_ranges(&[self.atom_range(offset, size)])
+    }.context("Failed to invalidate mapped memory")
+  }
+
+  fn mapped_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> Result<*mut u8> {
+    let ptr = self.mapped.context("Allocation is not host visible")?;
+    if offset + size > self.size {
+      anyhow::bail!("Range {offset}..{} is out of bounds of a {} byte allocation", offset + size, self.size);
+    }
+    Ok(unsafe { ptr.as_ptr().add(offset as usize) })
+  }
+
+  /// Flushes and invalidations must cover whole `nonCoherentAtomSize` units of the underlying memory,
+  /// without reaching past its end.
+  fn atom_range(&self, offset: vk::DeviceSize, size: vk::DeviceSize) -> vk::MappedMemoryRange {
+    let (start, size) = atom_range(
+      self.offset + offset,
+      size,
+      self.allocator.non_coherent_atom_size,
+      self.memory_size,
+    );
+    vk::MappedMemoryRange {
+      memory: self.memory,
+      offset: start,
+      size,
+      ..Default::default()
+    }
+  }
+}
+
+impl Drop for Allocation {
+  fn drop(&mut self) {
+    self.allocator.free(self);
+  }
+}
+
+struct MemoryPool {
+  memory_type_index: u32,
+  kind: ResourceKind,
+  blocks: Vec<MemoryBlock>,
+  next_block_id: u64,
+  dedicated_count: usize,
+  dedicated_bytes: vk::DeviceSize,
+}
+
+impl MemoryPool {
+  fn new(memory_type_index: u32, kind: ResourceKind) -> Self {
+    Self {
+      memory_type_index,
+      kind,
+      blocks: vec![],
+      next_block_id: 0,
+      dedicated_count: 0,
+      dedicated_bytes: 0,
+    }
+  }
+
+  fn add_block(&mut self, memory: vk::DeviceMemory, size: vk::DeviceSize, mapped: Option<NonNull<u8>>) {
+    self.blocks.push(MemoryBlock {
+      id: self.next_block_id,
+      memory,
+      size,
+      mapped,
+      free_ranges: vec![(0, size)],
+      allocation_count: 0,
+    });
+    self.next_block_id += 1;
+  }
+
+  fn block(&self, id: u64) -> Option<&MemoryBlock> {
+    self.blocks.iter().find(|b| b.id == id)
+  }
+
+  /// Returns the id of the block and the offset within it.
+  fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<(u64, vk::DeviceSize)> {
+    self.blocks.iter_mut()
+      .find_map(|block| block.allocate(size, alignment).map(|offset| (block.id, offset)))
+  }
+
+  /// Returns the block's memory if it became redundant and should be freed.
+  fn free(&mut self, id: u64, offset: vk::DeviceSize, size: vk::DeviceSize) -> Option<vk::DeviceMemory> {
+    let index = self.blocks.iter().position(|b| b.id == id)?;
+    self.blocks[index].free(offset, size);
+
+    // keep a single empty block around so alternating allocations don't thrash the driver
+    let empty_blocks = self.blocks.iter().filter(|b| b.allocation_count == 0).count();
+    if self.blocks[index].allocation_count == 0 && empty_blocks > 1 {
+      trace!("Freeing empty memory block for memory type {} ({:?})", self.memory_type_index, self.kind);
+      return Some(self.blocks.remove(index).memory);
+    }
+    None
+  }
+
+  fn stats(&self) -> MemoryStats {
+    MemoryStats {
+      block_count: self.blocks.len(),
+      dedicated_count: self.dedicated_count,
+      allocation_count: self.dedicated_count + self.blocks.iter().map(|b| b.allocation_count).sum::<usize>(),
+      reserved_bytes: self.dedicated_bytes + self.blocks.iter().map(|b| b.size).sum::<vk::DeviceSize>(),
+      used_bytes: self.dedicated_bytes + self.blocks.iter().map(|b| b.used()).sum::<vk::DeviceSize>(),
+    }
+  }
+}
+
+struct MemoryBlock {
+  id: u64,
+  memory: vk::DeviceMemory,
+  size: vk::DeviceSize,
+  mapped: Option<NonNull<u8>>,
+  /// `(offset, size)` pairs sorted by offset, with adjacent ranges always merged.
+  free_ranges: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+  allocation_count: usize,
+}
+
+// the mapped pointer is never dereferenced by the pool itself
+unsafe impl Send for MemoryBlock {}
+
+impl MemoryBlock {
+  /// First fit. Padding skipped for alignment stays in the free list.
+  fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+    let (index, offset) = self.free_ranges.iter().enumerate().find_map(|(i, &(start, len))| {
+      let offset = align_up(start, alignment);
+      (offset + size <= start + len).then_some((i, offset))
+    })?;
+
+    let (start, len) = self.free_ranges[index];
+    let end = start + len;
+    let mut remaining = Vec::with_capacity(2);
+    if offset > start {
+      remaining.push((start, offset - start));
+    }
+    if offset + size < end {
+      remaining.push((offset + size, end - offset - size));
+    }
+    self.free_ranges.splice(index..=index, remaining);
+    self.allocation_count += 1;
+
+    Some(offset)
+  }
+
+  fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+    let mut index = self.free_ranges.partition_point(|&(o, _)| o < offset);
+    self.free_ranges.insert(index, (offset, size));
+
+    if index + 1 < self.free_ranges.len() && offset + size == self.free_ranges[index + 1].0 {
+      self.free_ranges[index].1 += self.free_ranges.remove(index + 1).1;
+    }
+    if index > 0 && self.free_ranges[index - 1].0 + self.free_ranges[index - 1].1 == offset {
+      self.free_ranges[index - 1].1 += self.free_ranges.remove(index).1;
+      index -= 1;
+    }
+    debug_assert!(self.free_ranges[index].0 + self.free_ranges[index].1 <= self.size);
+
+    self.allocation_count -= 1;
+  }
+
+  fn used(&self) -> vk::DeviceSize {
+    self.size - self.free_ranges.iter().map(|&(_, len)| len).sum::<vk::DeviceSize>()
+  }
+}
+
+/// `offset` and `size` widened to whole atoms. Ranges reaching the end of the memory use `vk::WHOLE_SIZE`,
+/// since the end of a mapping doesn't have to be atom aligned.
+fn atom_range(
+  offset: vk::DeviceSize,
+  size: vk::DeviceSize,
+  atom: vk::DeviceSize,
+  memory_size: vk::DeviceSize,
+) -> (vk::DeviceSize, vk::DeviceSize) {
+  let start = offset / atom * atom;
+  let end = align_up(offset + size, atom);
+  if end >= memory_size {
+    (start, vk::WHOLE_SIZE)
+  } else {
+    (start, end - start)
+  }
+}
+
+fn align_up(value: vk::DeviceSize, alignment: vk::DeviceSize) -> vk::DeviceSize {
+  if alignment <= 1 {
+    return value;
+  }
+  value.div_ceil(alignment) * alignment
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn block(size: vk::DeviceSize) -> MemoryBlock {
+    MemoryBlock {
+      id: 0,
+      memory: vk::DeviceMemory::null(),
+      size,
+      mapped: None,
+      free_ranges: vec![(0, size)],
+      allocation_count: 0,
+    }
+  }
+
+  #[test]
+  fn allocations_are_aligned() {
+    let mut block = block(1024);
+    assert_eq!(block.allocate(10, 1), Some(0));
+    assert_eq!(block.allocate(16, 256), Some(256));
+    assert_eq!(block.allocate(4, 4), Some(12));
+    // the padding before 256 stays free
+    assert_eq!(block.free_ranges, vec![(10, 2), (16, 240), (272, 752)]);
+    assert_eq!(block.used(), 30);
+  }
+
+  #[test]
+  fn resource_kinds_use_separate_pools() {
+    assert_eq!(ResourceKind::from_tiling(vk::ImageTiling::LINEAR), ResourceKind::Linear);
+    assert_eq!(ResourceKind::from_tiling(vk::ImageTiling::OPTIMAL), ResourceKind::Optimal);
+
+    let pools: Vec<_> = (0..4)
+      .flat_map(|i| [ResourceKind::Linear, ResourceKind::Optimal].map(|kind| Allocator::pool_index(i, kind)))
+      .collect();
+    assert_eq!(pools, (0..8).collect::<Vec<_>>());
+  }
+
+  #[test]
+  fn free_coalesces_neighbours() {
+    let mut block = block(300);
+    let a = block.allocate(100, 1).unwrap();
+    let b = block.allocate(100, 1).unwrap();
+    let c = block.allocate(100, 1).unwrap();
+    assert!(block.free_ranges.is_empty());
+
+    block.free(b, 100);
+    assert_eq!(block.free_ranges, vec![(100, 100)]);
+    block.free(a, 100);
+    assert_eq!(block.free_ranges, vec![(0, 200)]);
+    block.free(c, 100);
+    assert_eq!(block.free_ranges, vec![(0, 300)]);
+    assert_eq!(block.allocation_count, 0);
+  }
+
+  #[test]
+  fn exhausted_blocks_refuse_allocations() {
+    let mut block = block(256);
+    assert_eq!(block.allocate(200, 1), Some(0));
+    assert_eq!(block.allocate(64, 1), None);
+    // fits by size, but not once aligned
+    assert_eq!(block.allocate(32, 128), None);
+    assert_eq!(block.allocate(56, 1), Some(200));
+    assert_eq!(block.allocate(1, 1), None);
+
+    block.free(0, 200);
+    assert_eq!(block.allocate(64, 1), Some(0));
+  }
+
+  #[test]
+  fn pools_only_free_redundant_empty_blocks() {
+    let mut pool = MemoryPool::new(0, ResourceKind::Linear);
+    assert_eq!(pool.allocate(16, 1), None);
+
+    pool.add_block(vk::DeviceMemory::null(), 64, None);
+    pool.add_block(vk::DeviceMemory::null(), 64, None);
+    assert_eq!(pool.allocate(64, 1), Some((0, 0)));
+    assert_eq!(pool.allocate(64, 1), Some((1, 0)));
+    assert_eq!(pool.allocate(1, 1), None);
+
+    // the first empty block is kept, the second is freed
+    assert!(pool.free(0, 0, 64).is_none());
+    assert!(pool.free(1, 0, 64).is_some());
+    assert_eq!(pool.blocks.len(), 1);
+  }
+
+  #[test]
+  fn atom_ranges_stay_inside_the_memory() {
+    assert_eq!(atom_range(70, 10, 64, 1024), (64, 64));
+    assert_eq!(atom_range(0, 1024, 64, 1024), (0, vk::WHOLE_SIZE));
+    // an allocation ending off-atom at the end of the memory
+    assert_eq!(atom_range(960, 40, 64, 1000), (960, vk::WHOLE_SIZE));
+  }
+}
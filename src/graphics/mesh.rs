@@ -0,0 +1,251 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use anyhow::{Context, Result};
+use ash::vk;
+use bevy_ecs::prelude::Component;
+use bytemuck::{Pod, Zeroable};
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::buffer::typed::{BufferMode, IndexBuffer, TypedBuffer};
+use crate::graphics::context::RenderContext;
+use crate::graphics::vertex::{Vertex, VertexLayout};
+
+/// The vertex type produced by the primitive generators.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Pod, Zeroable, Vertex)]
+pub struct MeshVertex {
+  pub position: [f32; 3],
+  pub normal: [f32; 3],
+  pub uv: [f32; 2],
+}
+
+/// Indexed geometry on the cpu side, uploaded with `Mesh::upload`.
+///
+/// Triangles wind counter-clockwise when seen from the front.
+#[derive(Debug, Clone)]
+pub struct Mesh<V: Vertex = MeshVertex> {
+  pub vertices: Vec<V>,
+  pub indices: Vec<u32>,
+  /// The pipeline drawing the mesh must use the same topology.
+  pub topology: vk::PrimitiveTopology,
+}
+
+impl<V: Vertex> Mesh<V> {
+  pub fn new(vertices: Vec<V>, indices: Vec<u32>) -> Self {
+    Self {
+      vertices,
+      indices,
+      topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+    }
+  }
+
+  pub fn upload(&self, context: &RenderContext) -> Result<GpuMesh> {
+    if let Some(index) = self.indices.iter().find(|i| **i as usize >= self.vertices.len()) {
+      anyhow::bail!("Mesh index {index} is out of bounds of {} vertices", self.vertices.len());
+    }
+
+    let vertex_buffer = TypedBuffer::new(context, &self.vertices, vk::BufferUsageFlags::VERTEX_BUFFER, BufferMode::Static)
+      .context("Failed to upload mesh vertices")?
+      .into_buffer();
+    let index_buffer = IndexBuffer::new(context, &self.indices)
+      .context("Failed to upload mesh indices")?;
+
+    Ok(GpuMesh {
+      vertex_buffer,
+      index_buffer,
+      index_count: self.indices.len() as u32,
+      topology: self.topology,
+      vertex_layout: VertexLayout::new().with_vertex::<V>(),
+      skip_reported: AtomicBool::new(false),
+    })
+  }
+}
+
+impl Mesh<MeshVertex> {
+  /// A `width` by `height` rectangle in the xy plane, facing +z.
+  pub fn quad(width: f32, height: f32) -> Self {
+    let mut mesh = Self::new(vec![], vec![]);
+    mesh.push_face([0.0, 0.0, 0.0], [width / 2.0, 0.0, 0.0], [0.0, height / 2.0, 0.0], [0.0, 0.0, 1.0]);
+    mesh
+  }
+
+  /// An axis aligned cube centered on the origin, with separate vertices per face for flat normals.
+  pub fn cube(size: f32) -> Self {
+    let h = size / 2.0;
+    let mut mesh = Self::new(vec![], vec![]);
+    // (normal, u, v) with u x v = normal, so every face winds outwards
+    let faces = [
+      ([1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+      ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+      ([0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+      ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+      ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+      ([0.0, 0.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+    ];
+    for (normal, u, v) in faces {
+      mesh.push_face(scale(normal, h), scale(u, h), scale(v, h), normal);
+    }
+    mesh
+  }
+
+  /// A sphere made of `stacks` rings from pole to pole, each split into `sectors` segments.
+  pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Self {
+    let sectors = sectors.max(3);
+    let stacks = stacks.max(2);
+
+    let mut vertices = Vec::with_capacity(((sectors + 1) * (stacks + 1)) as usize);
+    for i in 0..=stacks {
+      let phi = PI * i as f32 / stacks as f32;
+      for j in 0..=sectors {
+        let theta = 2.0 * PI * j as f32 / sectors as f32;
+        let normal = [phi.sin() * theta.cos(), phi.cos(), phi.sin() * theta.sin()];
+        vertices.push(MeshVertex {
+          position: scale(normal, radius),
+          normal,
+          uv: [j as f32 / sectors as f32, i as f32 / stacks as f32],
+        });
+      }
+    }
+
+    let mut indices = Vec::with_capacity((sectors * stacks * 6) as usize);
+    for i in 0..stacks {
+      for j in 0..sectors {
+        let k1 = i * (sectors + 1) + j;
+        let k2 = k1 + sectors + 1;
+        // the first and last rings meet in a point, leaving only one triangle per segment
+        if i != 0 {
+          indices.extend([k1, k1 + 1, k2]);
+        }
+        if i != stacks - 1 {
+          indices.extend([k1 + 1, k2 + 1, k2]);
+        }
+      }
+    }
+
+    Self::new(vertices, indices)
+  }
+
+  /// A `size` by `size` square in the xz plane facing +y, split into `subdivisions` cells along each side.
+  pub fn plane(size: f32, subdivisions: u32) -> Self {
+    let n = subdivisions.max(1);
+    let vertices = Self::grid_points(size, n).collect();
+
+    let mut indices = Vec::with_capacity((n * n * 6) as usize);
+    for z in 0..n {
+      for x in 0..n {
+        let a = z * (n + 1) + x;
+        let b = a + 1;
+        let c = a + n + 1;
+        let d = c + 1;
+        indices.extend([a, c, b, b, c, d]);
+      }
+    }
+
+    Self::new(vertices, indices)
+  }
+
+  /// Lines spanning a `size` by `size` square in the xz plane, `divisions` cells along each side.
+  /// Uses `LINE_LIST` topology.
+  pub fn grid(size: f32, divisions: u32) -> Self {
+    let n = divisions.max(1);
+    let vertices = Self::grid_points(size, n).collect();
+
+    let mut indices = Vec::with_capacity(((n + 1) * 4) as usize);
+    for i in 0..=n {
+      // one line along z through column i, one along x through row i
+      indices.extend([i, n * (n + 1) + i]);
+      indices.extend([i * (n + 1), i * (n + 1) + n]);
+    }
+
+    Self {
+      vertices,
+      indices,
+      topology: vk::PrimitiveTopology::LINE_LIST,
+    }
+  }
+
+  fn grid_points(size: f32, n: u32) -> impl Iterator<Item = MeshVertex> {
+    (0..=n).flat_map(move |z| (0..=n).map(move |x| {
+      let (u, v) = (x as f32 / n as f32, z as f32 / n as f32);
+      MeshVertex {
+        position: [(u - 0.5) * size, 0.0, (v - 0.5) * size],
+        normal: [0.0, 1.0, 0.0],
+        uv: [u, v],
+      }
+    }))
+  }
+
+  /// Adds the quad `center ± u ± v`, counter-clockwise around `normal`.
+  fn push_face(&mut self, center: [f32; 3], u: [f32; 3], v: [f32; 3], normal: [f32; 3]) {
+    let first = self.vertices.len() as u32;
+    let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+    for (su, sv) in corners {
+      self.vertices.push(MeshVertex {
+        position: [0, 1, 2].map(|i| center[i] + su * u[i] + sv * v[i]),
+        normal,
+        uv: [(su + 1.0) / 2.0, (1.0 - sv) / 2.0],
+      });
+    }
+    self.indices.extend([0, 1, 2, 2, 3, 0].map(|i| first + i));
+  }
+}
+
+fn scale(v: [f32; 3], s: f32) -> [f32; 3] {
+  v.map(|c| c * s)
+}
+
+/// A mesh uploaded to device local vertex and index buffers.
+pub struct GpuMesh {
+  vertex_buffer: Buffer,
+  index_buffer: IndexBuffer<u32>,
+  index_count: u32,
+  topology: vk::PrimitiveTopology,
+  vertex_layout: VertexLayout,
+  skip_reported: AtomicBool,
+}
+
+impl GpuMesh {
+  pub fn index_count(&self) -> u32 {
+    self.index_count
+  }
+
+  pub fn topology(&self) -> vk::PrimitiveTopology {
+    self.topology
+  }
+
+  /// The layout of the mesh's vertex type at binding 0, which a pipeline drawing it must match.
+  pub fn vertex_layout(&self) -> &VertexLayout {
+    &self.vertex_layout
+  }
+
+  /// Returns `true` only the first time, so skipping the mesh every frame is reported once.
+  pub(crate) fn report_skipped(&self) -> bool {
+    !self.skip_reported.swap(true, Ordering::Relaxed)
+  }
+
+  /// Binds the mesh's buffers to binding 0 and records an indexed draw.
+  pub fn draw(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    self.draw_instanced(device, command_buffer, 1);
+  }
+
+  pub fn draw_instanced(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, instance_count: u32) {
+    unsafe {
+      device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer.buffer], &[0]);
+      device.cmd_bind_index_buffer(command_buffer, self.index_buffer.handle(), 0, self.index_buffer.index_type());
+      device.cmd_draw_indexed(command_buffer, self.index_count, instance_count, 0, 0, 0);
+    }
+  }
+}
+
+/// Attaches an uploaded mesh to an entity, drawn every frame with the graphics' render pipeline.
+/// Meshes whose topology or vertex type don't match the pipeline are skipped.
+/// Cloning shares the gpu buffers.
+#[derive(Component, Clone)]
+pub struct MeshHandle(pub Arc<GpuMesh>);
+
+impl MeshHandle {
+  pub fn new(mesh: GpuMesh) -> Self {
+    Self(Arc::new(mesh))
+  }
+}
@@ -0,0 +1,88 @@
+use anyhow::{Context, Result};
+use winit::monitor::MonitorHandle;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MonitorSelection {
+  Primary,
+  Index(usize),
+  Name(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Monitor {
+  handle: MonitorHandle,
+  pub name: Option<String>,
+  pub position: (i32, i32),
+  pub size: (u32, u32),
+  pub scale_factor: f64,
+  pub video_modes: Vec<VideoMode>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct VideoMode {
+  pub width: u32,
+  pub height: u32,
+  pub bit_depth: u16,
+  pub refresh_rate_millihertz: u32,
+}
+
+impl Monitor {
+  #[allow(unused)]
+  pub(crate) fn handle(&self) -> &MonitorHandle {
+    &self.handle
+  }
+
+  /// Finds the monitor matching `selection` from the given handles.
+  /// Fails if no monitors are reported or the selection doesn't match any of them.
+  pub(crate) fn select(
+    monitors: impl IntoIterator<Item = MonitorHandle>,
+    primary: Option<MonitorHandle>,
+    selection: &MonitorSelection,
+  ) -> Result<Self> {
+    let mut monitors = monitors.into_iter();
+    let handle = match selection {
+      MonitorSelection::Primary => primary
+        .or_else(|| monitors.next())
+        .context("No monitors available")?,
+      MonitorSelection::Index(index) => monitors
+        .nth(*index)
+        .with_context(|| format!("No monitor at index {index}"))?,
+      MonitorSelection::Name(name) => monitors
+        .find(|m| m.name().as_ref() == Some(name))
+        .with_context(|| format!("No monitor named [{name}]"))?,
+    };
+
+    Ok(handle.into())
+  }
+
+  pub fn center(&self) -> (i32, i32) {
+    (
+      self.position.0 + (self.size.0 as f32 * 0.5).floor() as i32,
+      self.position.1 + (self.size.1 as f32 * 0.5).floor() as i32,
+    )
+  }
+}
+
+impl From<MonitorHandle> for Monitor {
+  fn from(handle: MonitorHandle) -> Self {
+    let position = handle.position();
+    let size = handle.size();
+    let video_modes = handle.video_modes()
+      .map(|mode| VideoMode {
+        width: mode.size().width,
+        height: mode.size().height,
+        bit_depth: mode.bit_depth(),
+        refresh_rate_millihertz: mode.refresh_rate_millihertz(),
+      })
+      .collect();
+
+    Self {
+      name: handle.name(),
+      position: (position.x, position.y),
+      size: (size.width, size.height),
+      scale_factor: handle.scale_factor(),
+      video_modes,
+      handle,
+    }
+  }
+}
@@ -8,13 +8,27 @@ use crate::{
     shader::Shader,
   }
 };
+use crate::graphics::descriptor::DescriptorSetLayout;
+use crate::graphics::device::DeviceFeature;
+use crate::graphics::render_pass::{AttachmentFormats, RenderPass};
 use crate::graphics::Graphics;
 use crate::graphics::shader::builder::{ShaderBuilder, ShaderStagesSpecified};
 use crate::graphics::shader::ShaderCreateInfo;
+use crate::graphics::vertex::{Vertex, VertexLayout};
+
+use self::layout::PipelineLayout;
+use self::state::{BlendMode, CullMode, DepthMode, FrontFace, PipelineState, PipelineStateOverrides, PolygonMode, Topology};
+
+pub mod cache;
+pub mod compute;
+pub mod layout;
+pub mod state;
 
 pub struct RenderPipeline {
   device: Arc<ash::Device>,
   pipeline: vk::Pipeline,
+  layout: Arc<PipelineLayout>,
+  descriptor_set_layouts: Vec<DescriptorSetLayout>,
   config: RenderPipelineConfig,
   shader: Shader,
 }
@@ -22,15 +36,76 @@ pub struct RenderPipeline {
 impl RenderPipeline {
   pub fn new(context: &RenderContext, shader_info: ShaderCreateInfo, config: RenderPipelineConfig) -> Result<Self> {
     let shader = shader_info.compile();
-    let pipeline = Self::create_graphics_pipeline(context, &shader, &config)?;
+
+    shader.reflection().validate_vertex_layout(&config.vertex_layout)
+      .context("Vertex layout does not match the vertex shader")?;
+
+    // without an explicit layout, the one the shader declares is used
+    let (layout, descriptor_set_layouts) = match &config.pipeline_layout {
+      Some(layout) => (layout.clone(), vec![]),
+      None => {
+        let reflected = shader.reflection().pipeline_layout(context)
+          .context("Failed to build pipeline layout from shader reflection")?;
+        (Arc::new(reflected.pipeline_layout), reflected.set_layouts)
+      }
+    };
+    let pipeline = Self::create_graphics_pipeline(context, &shader, &config, &layout)?;
 
     Ok(Self {
       device: context.device(),
       pipeline,
+      layout,
+      descriptor_set_layouts,
       config,
       shader,
     })
   }
+
+  pub fn handle(&self) -> vk::Pipeline {
+    self.pipeline
+  }
+
+  pub fn layout(&self) -> &PipelineLayout {
+    &self.layout
+  }
+
+  /// The set layouts reflected from the shader, indexed by set number.
+  /// Empty when the pipeline was given an explicit layout.
+  pub fn descriptor_set_layouts(&self) -> &[DescriptorSetLayout] {
+    &self.descriptor_set_layouts
+  }
+
+  pub fn config(&self) -> &RenderPipelineConfig {
+    &self.config
+  }
+
+  /// Binds the pipeline, and sets a dynamic viewport and scissor to cover `extent`.
+  pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+    unsafe {
+      device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+      if let ViewportState::Dynamic = self.config.viewport {
+        let scissor = vk::Rect2D {
+          offset: vk::Offset2D { x: 0, y: 0 },
+          extent,
+        };
+        device.cmd_set_viewport(command_buffer, 0, &[full_viewport(extent)]);
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+      }
+    }
+  }
+
+  /// Pipelines without a render pass draw through dynamic rendering.
+  pub fn uses_render_pass(&self) -> bool {
+    self.config.render_pass.is_some()
+  }
+
+  /// The attachments the pipeline draws into, from its render pass subpass if it has one.
+  pub fn attachment_formats(&self) -> Result<AttachmentFormats> {
+    match &self.config.render_pass {
+      Some(render_pass) => render_pass.attachment_formats(self.config.subpass),
+      None => Ok(self.config.attachment_formats.clone()),
+    }
+  }
 }
 
 impl RenderPipeline {
@@ -38,105 +113,46 @@ impl RenderPipeline {
     self.device.destroy_pipeline(self.pipeline, None);
   }
 
-  fn create_graphics_pipeline(context: &RenderContext, shader: &Shader, config: &RenderPipelineConfig) -> Result<vk::Pipeline> {
-    if config.pipeline_layout == vk::PipelineLayout::null() {
-      anyhow::bail!("pipeline layout is null") // TODO: Add to builder
+  fn create_graphics_pipeline(
+    context: &RenderContext,
+    shader: &Shader,
+    config: &RenderPipelineConfig,
+    layout: &PipelineLayout,
+  ) -> Result<vk::Pipeline> {
+    if config.render_pass.is_none() && !context.dynamic_rendering_enabled() {
+      anyhow::bail!("pipeline has no render pass and the device does not support dynamic rendering")
     }
-
-    if config.render_pass == vk::RenderPass::null() {
-      anyhow::bail!("render pass is null") // TODO: Add to builder
+    if config.state.polygon_mode != PolygonMode::Fill && !context.feature_enabled(DeviceFeature::FillModeNonSolid) {
+      anyhow::bail!("{:?} polygon mode needs the FillModeNonSolid device feature", config.state.polygon_mode)
     }
+    let attachment_formats = match &config.render_pass {
+      Some(render_pass) => render_pass.attachment_formats(config.subpass)?,
+      None => config.attachment_formats.clone(),
+    };
 
     // TODO: Overhaul pipeline creation to make it more type-driven
 
     let shader_stage_create_infos = shader.pipeline_shader_info();
 
     let vertex_input_info = vk::PipelineVertexInputStateCreateInfo {
-      vertex_binding_description_count: 0,
-      // p_vertex_binding_descriptions: (),
-      vertex_attribute_description_count: 0,
-      // p_vertex_attribute_descriptions: (),
+      vertex_binding_description_count: config.vertex_layout.bindings().len() as u32,
+      p_vertex_binding_descriptions: config.vertex_layout.bindings().as_ptr(),
+      vertex_attribute_description_count: config.vertex_layout.attributes().len() as u32,
+      p_vertex_attribute_descriptions: config.vertex_layout.attributes().as_ptr(),
       ..Default::default()
     };
 
-    let pipeline_create_info = vk::GraphicsPipelineCreateInfo {
-      stage_count: shader_stage_create_infos.len() as u32,
-      p_stages: shader_stage_create_infos.as_ptr(),
-      p_vertex_input_state: &vertex_input_info,
-      p_input_assembly_state: &config.input_assembly_info,
-      // p_tessellation_state: &config.input_assembly_info,
-      p_viewport_state: &config.viewport_info,
-      p_rasterization_state: &config.rasterization_info,
-      p_multisample_state: &config.multisample_info,
-      p_depth_stencil_state: &config.depth_stencil_info,
-      p_color_blend_state: &config.color_blend_info,
-      // p_dynamic_state: (),
-      layout: config.pipeline_layout,
-      render_pass: config.render_pass,
-      subpass: config.subpass,
-      // base_pipeline_handle: Default::default(),
-      // base_pipeline_index: 0,
+    let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
+      topology: config.state.topology.into(),
+      primitive_restart_enable: config.primitive_restart.into(),
       ..Default::default()
     };
 
-    unsafe {
-      context.device().create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_create_info], None)
-        .map(|pipelines| pipelines[0])
-        .map_err(|err| anyhow::anyhow!("failed to create graphics pipelines: {err:?}"))
-    }
-  }
-}
-
-impl Drop for RenderPipeline {
-  fn drop(&mut self) {
-    unsafe {
-      self.free();
-    }
-  }
-}
-
-#[derive(Default)]
-pub struct RenderPipelineConfig {
-  pub viewport: vk::Viewport,
-  pub scissor: vk::Rect2D,
-  pub viewport_info: vk::PipelineViewportStateCreateInfo,
-  pub input_assembly_info: vk::PipelineInputAssemblyStateCreateInfo,
-  pub rasterization_info: vk::PipelineRasterizationStateCreateInfo,
-  pub multisample_info: vk::PipelineMultisampleStateCreateInfo,
-  pub color_blend_attachment: vk::PipelineColorBlendAttachmentState,
-  pub color_blend_info: vk::PipelineColorBlendStateCreateInfo,
-  pub depth_stencil_info: vk::PipelineDepthStencilStateCreateInfo,
-  pub pipeline_layout: vk::PipelineLayout,
-  pub render_pass: vk::RenderPass,
-  pub subpass: u32,
-}
-
-unsafe impl Send for RenderPipelineConfig {}
-
-unsafe impl Sync for RenderPipelineConfig {}
-
-impl RenderPipelineConfig {
-  pub fn new((width, height): (u32, u32)) -> Self {
-    let viewport = vk::Viewport {
-      x: 0.0,
-      y: 0.0,
-      width: width as f32,
-      height: height as f32,
-      min_depth: 0.0,
-      max_depth: 1.0,
+    // dynamic viewports only need their count, the values are ignored
+    let (viewport, scissor) = match config.viewport {
+      ViewportState::Dynamic => (vk::Viewport::default(), vk::Rect2D::default()),
+      ViewportState::Fixed { viewport, scissor } => (viewport, scissor),
     };
-
-    let scissor = vk::Rect2D {
-      offset: vk::Offset2D {
-        x: 0,
-        y: 0,
-      },
-      extent: vk::Extent2D {
-        width,
-        height,
-      },
-    };
-
     let viewport_info = vk::PipelineViewportStateCreateInfo {
       viewport_count: 1,
       p_viewports: &viewport,
@@ -145,64 +161,41 @@ impl RenderPipelineConfig {
       ..Default::default()
     };
 
-    let input_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
-      topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-      primitive_restart_enable: vk::FALSE,
-      ..Default::default()
-    };
-
     let rasterization_info = vk::PipelineRasterizationStateCreateInfo {
-      depth_clamp_enable: vk::FALSE,
+      depth_clamp_enable: config.depth_clamp.into(),
       rasterizer_discard_enable: vk::FALSE,
-      polygon_mode: vk::PolygonMode::FILL,
-      cull_mode: vk::CullModeFlags::BACK,
-      front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+      polygon_mode: config.state.polygon_mode.into(),
+      cull_mode: config.state.cull_mode.into(),
+      front_face: config.state.front_face.into(),
       depth_bias_enable: vk::FALSE,
-      depth_bias_constant_factor: 0.0,
-      depth_bias_clamp: 0.0,
-      depth_bias_slope_factor: 0.0,
-      line_width: 1.0,
+      line_width: config.line_width,
       ..Default::default()
     };
 
     let multisample_info = vk::PipelineMultisampleStateCreateInfo {
-      rasterization_samples: vk::SampleCountFlags::TYPE_1,
-      sample_shading_enable: vk::FALSE,
-      min_sample_shading: 1.0,
-      alpha_to_coverage_enable: vk::FALSE,
+      rasterization_samples: attachment_formats.samples,
+      sample_shading_enable: config.sample_shading.is_some().into(),
+      min_sample_shading: config.sample_shading.unwrap_or(1.0),
+      alpha_to_coverage_enable: config.alpha_to_coverage.into(),
       alpha_to_one_enable: vk::FALSE,
       ..Default::default()
     };
 
-    let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
-      blend_enable: vk::TRUE,
-      src_color_blend_factor: vk::BlendFactor::SRC_COLOR,
-      dst_color_blend_factor: vk::BlendFactor::DST_COLOR,
-      color_blend_op: vk::BlendOp::ADD,
-      src_alpha_blend_factor: vk::BlendFactor::SRC_ALPHA,
-      dst_alpha_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
-      alpha_blend_op: vk::BlendOp::ADD,
-      color_write_mask: vk::ColorComponentFlags::RGBA,
-    };
-
+    // every color attachment blends the same way
+    let color_blend_attachments = vec![config.state.blend.attachment_state(); attachment_formats.color.len()];
     let color_blend_info = vk::PipelineColorBlendStateCreateInfo {
       logic_op_enable: vk::FALSE,
       logic_op: vk::LogicOp::COPY,
-      attachment_count: 1,
-      p_attachments: &color_blend_attachment,
-      blend_constants: [
-        0.0,
-        0.0,
-        0.0,
-        0.0,
-      ],
+      attachment_count: color_blend_attachments.len() as u32,
+      p_attachments: color_blend_attachments.as_ptr(),
+      blend_constants: config.blend_constants,
       ..Default::default()
     };
 
     let depth_stencil_info = vk::PipelineDepthStencilStateCreateInfo {
-      depth_test_enable: vk::TRUE,
-      depth_write_enable: vk::TRUE,
-      depth_compare_op: vk::CompareOp::LESS,
+      depth_test_enable: config.state.depth.test_enabled().into(),
+      depth_write_enable: config.state.depth.write_enabled().into(),
+      depth_compare_op: config.state.depth.compare_op(),
       depth_bounds_test_enable: vk::FALSE,
       stencil_test_enable: vk::FALSE,
       min_depth_bounds: 0.0,
@@ -210,27 +203,172 @@ impl RenderPipelineConfig {
       ..Default::default()
     };
 
-    let pipeline_layout = vk::PipelineLayout::null();
+    let dynamic_states = config.all_dynamic_states();
+    let dynamic_state_info = vk::PipelineDynamicStateCreateInfo {
+      dynamic_state_count: dynamic_states.len() as u32,
+      p_dynamic_states: dynamic_states.as_ptr(),
+      ..Default::default()
+    };
+
+    let rendering_info = vk::PipelineRenderingCreateInfo {
+      color_attachment_count: attachment_formats.color.len() as u32,
+      p_color_attachment_formats: attachment_formats.color.as_ptr(),
+      depth_attachment_format: attachment_formats.depth.unwrap_or(vk::Format::UNDEFINED),
+      ..Default::default()
+    };
+
+    let pipeline_create_info = vk::GraphicsPipelineCreateInfo {
+      p_next: if config.render_pass.is_none() {
+        &rendering_info as *const _ as *const std::ffi::c_void
+      } else {
+        std::ptr::null()
+      },
+      stage_count: shader_stage_create_infos.len() as u32,
+      p_stages: shader_stage_create_infos.as_ptr(),
+      p_vertex_input_state: &vertex_input_info,
+      p_input_assembly_state: &input_assembly_info,
+      // p_tessellation_state: &config.input_assembly_info,
+      p_viewport_state: &viewport_info,
+      p_rasterization_state: &rasterization_info,
+      p_multisample_state: &multisample_info,
+      p_depth_stencil_state: &depth_stencil_info,
+      p_color_blend_state: &color_blend_info,
+      p_dynamic_state: &dynamic_state_info,
+      layout: layout.handle(),
+      render_pass: config.render_pass.as_ref().map_or(vk::RenderPass::null(), |r| r.handle()),
+      subpass: config.subpass,
+      // base_pipeline_handle: Default::default(),
+      // base_pipeline_index: 0,
+      ..Default::default()
+    };
+
+    unsafe {
+      context.device().create_graphics_pipelines(context.pipeline_cache().handle(), &[pipeline_create_info], None)
+        .map(|pipelines| pipelines[0])
+        .map_err(|err| anyhow::anyhow!("failed to create graphics pipelines: {err:?}"))
+    }
+  }
+}
+
+impl Drop for RenderPipeline {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// How a pipeline maps clip space onto its attachments.
+#[derive(Debug, Copy, Clone)]
+pub enum ViewportState {
+  /// Set when the pipeline is bound, to cover whatever it draws into. Survives resizes without rebuilding the pipeline.
+  Dynamic,
+  /// Baked into the pipeline.
+  Fixed {
+    viewport: vk::Viewport,
+    scissor: vk::Rect2D,
+  },
+}
 
-    let render_pass = vk::RenderPass::null();
+impl ViewportState {
+  /// A fixed viewport and scissor covering all of `extent`.
+  pub fn fixed(extent: vk::Extent2D) -> Self {
+    Self::Fixed {
+      viewport: full_viewport(extent),
+      scissor: vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+      },
+    }
+  }
+}
 
-    let subpass: u32 = 0;
+/// Everything about a graphics pipeline except its shaders. Only turned into Vulkan structs when the pipeline is created.
+#[derive(Clone)]
+pub struct RenderPipelineConfig {
+  pub vertex_layout: VertexLayout,
+  pub viewport: ViewportState,
+  pub state: PipelineState,
+  pub primitive_restart: bool,
+  pub line_width: f32,
+  pub depth_clamp: bool,
+  /// The minimum fraction of samples to shade individually, or `None` to shade once per pixel.
+  pub sample_shading: Option<f32>,
+  pub alpha_to_coverage: bool,
+  pub blend_constants: [f32; 4],
+  /// State set while recording instead of at creation, beyond the viewport and scissor.
+  pub dynamic_states: Vec<vk::DynamicState>,
+  /// The layout reflected from the shader is used when `None`.
+  pub pipeline_layout: Option<Arc<PipelineLayout>>,
+  /// When `None`, the pipeline renders through dynamic rendering into `attachment_formats`.
+  pub render_pass: Option<Arc<RenderPass>>,
+  pub subpass: u32,
+  /// Only used for dynamic rendering, render passes describe their own attachments.
+  pub attachment_formats: AttachmentFormats,
+}
 
+impl RenderPipelineConfig {
+  /// Opaque, depth tested, back face culled triangle lists with a dynamic viewport.
+  pub fn new() -> Self {
     Self {
-      viewport,
-      scissor,
-      viewport_info,
-      input_assembly_info,
-      rasterization_info,
-      multisample_info,
-      color_blend_attachment,
-      color_blend_info,
-      depth_stencil_info,
-      pipeline_layout,
-      render_pass,
-      subpass,
+      vertex_layout: VertexLayout::new(),
+      viewport: ViewportState::Dynamic,
+      state: PipelineState::default(),
+      primitive_restart: false,
+      line_width: 1.0,
+      depth_clamp: false,
+      sample_shading: None,
+      alpha_to_coverage: false,
+      blend_constants: [0.0; 4],
+      dynamic_states: Vec::new(),
+      pipeline_layout: None,
+      render_pass: None,
+      subpass: 0,
+      attachment_formats: AttachmentFormats::default(),
     }
   }
+
+  pub fn with_state(mut self, state: PipelineState) -> Self {
+    self.state = state;
+    self
+  }
+
+  /// Bakes a viewport and scissor covering `extent` into the pipeline.
+  pub fn with_fixed_viewport(mut self, extent: vk::Extent2D) -> Self {
+    self.viewport = ViewportState::fixed(extent);
+    self
+  }
+
+  /// Every dynamic state the pipeline is created with.
+  pub fn all_dynamic_states(&self) -> Vec<vk::DynamicState> {
+    let mut dynamic_states = match self.viewport {
+      ViewportState::Dynamic => vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+      ViewportState::Fixed { .. } => vec![],
+    };
+    for state in &self.dynamic_states {
+      if !dynamic_states.contains(state) {
+        dynamic_states.push(*state);
+      }
+    }
+    dynamic_states
+  }
+}
+
+impl Default for RenderPipelineConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn full_viewport(extent: vk::Extent2D) -> vk::Viewport {
+  vk::Viewport {
+    x: 0.0,
+    y: 0.0,
+    width: extent.width as f32,
+    height: extent.height as f32,
+    min_depth: 0.0,
+    max_depth: 1.0,
+  }
 }
 
 #[derive(Default, Clone)]
@@ -251,6 +389,11 @@ pub struct RenderPipelineBuilder<'c, S, C> {
   shader: Option<ShaderCreateInfo>,
   config_specified: PhantomData<C>,
   config: Option<RenderPipelineConfig>,
+  vertex_layout: Option<VertexLayout>,
+  pipeline_layout: Option<Arc<PipelineLayout>>,
+  render_pass: Option<(Arc<RenderPass>, u32)>,
+  attachment_formats: Option<AttachmentFormats>,
+  state: PipelineStateOverrides,
 }
 
 impl<'c> RenderPipelineBuilder<'c, ShaderMissing, ConfigMissing> {
@@ -261,6 +404,11 @@ impl<'c> RenderPipelineBuilder<'c, ShaderMissing, ConfigMissing> {
       shader: None,
       config_specified: PhantomData,
       config: None,
+      vertex_layout: None,
+      pipeline_layout: None,
+      render_pass: None,
+      attachment_formats: None,
+      state: Default::default(),
     }
   }
 }
@@ -274,6 +422,11 @@ impl<'c, C> RenderPipelineBuilder<'c, ShaderMissing, C> {
         shader: Some(shader_info),
         config_specified: PhantomData,
         config: self.config,
+        vertex_layout: self.vertex_layout,
+        pipeline_layout: self.pipeline_layout,
+        render_pass: self.render_pass,
+        attachment_formats: self.attachment_formats,
+        state: self.state,
       }
     } else {
       todo!("must return default shader")
@@ -303,16 +456,106 @@ impl<'c, S> RenderPipelineBuilder<'c, S, ConfigMissing> {
       shader: self.shader,
       config_specified: PhantomData,
       config: Some(config),
+      vertex_layout: self.vertex_layout,
+      pipeline_layout: self.pipeline_layout,
+      render_pass: self.render_pass,
+      attachment_formats: self.attachment_formats,
+      state: self.state,
     }
   }
 }
 
+impl<'c, S, C> RenderPipelineBuilder<'c, S, C> {
+  /// Feeds `V` to the vertex shader from binding 0, one per vertex.
+  pub fn with_vertex<V: Vertex>(self) -> Self {
+    self.with_vertex_layout(VertexLayout::new().with_vertex::<V>())
+  }
+
+  /// Overrides the config's pipeline layout.
+  pub fn with_pipeline_layout(mut self, pipeline_layout: Arc<PipelineLayout>) -> Self {
+    self.pipeline_layout = Some(pipeline_layout);
+    self
+  }
+
+  /// Creates the pipeline for `subpass` of `render_pass`, instead of dynamic rendering.
+  pub fn with_render_pass(mut self, render_pass: Arc<RenderPass>, subpass: u32) -> Self {
+    self.render_pass = Some((render_pass, subpass));
+    self
+  }
+
+  /// The attachments to draw into with dynamic rendering.
+  pub fn with_attachment_formats(mut self, attachment_formats: AttachmentFormats) -> Self {
+    self.attachment_formats = Some(attachment_formats);
+    self
+  }
+
+  /// Overrides the config's vertex layout, e.g. to add per-instance bindings.
+  pub fn with_vertex_layout(mut self, vertex_layout: VertexLayout) -> Self {
+    self.vertex_layout = Some(vertex_layout);
+    self
+  }
+
+  /// Overrides the config's whole fixed function state, e.g. with one loaded from an asset file.
+  /// Individually set modes still take precedence.
+  pub fn with_pipeline_state(mut self, state: PipelineState) -> Self {
+    self.state.state = Some(state);
+    self
+  }
+
+  pub fn with_blend_mode(mut self, blend: BlendMode) -> Self {
+    self.state.blend = Some(blend);
+    self
+  }
+
+  pub fn with_depth_mode(mut self, depth: DepthMode) -> Self {
+    self.state.depth = Some(depth);
+    self
+  }
+
+  pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+    self.state.cull_mode = Some(cull_mode);
+    self
+  }
+
+  pub fn with_front_face(mut self, front_face: FrontFace) -> Self {
+    self.state.front_face = Some(front_face);
+    self
+  }
+
+  /// `PolygonMode::Line` draws wireframes.
+  pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+    self.state.polygon_mode = Some(polygon_mode);
+    self
+  }
+
+  pub fn with_topology(mut self, topology: Topology) -> Self {
+    self.state.topology = Some(topology);
+    self
+  }
+}
+
 impl<'c> RenderPipelineBuilder<'c, ShaderSpecified, ConfigSpecified> {
   pub fn build(self) -> Result<RenderPipeline> {
+    let mut config = self.config.unwrap();
+    if let Some(vertex_layout) = self.vertex_layout {
+      config.vertex_layout = vertex_layout;
+    }
+    if let Some(pipeline_layout) = self.pipeline_layout {
+      config.pipeline_layout = Some(pipeline_layout);
+    }
+    if let Some((render_pass, subpass)) = self.render_pass {
+      config.render_pass = Some(render_pass);
+      config.subpass = subpass;
+    }
+    if let Some(attachment_formats) = self.attachment_formats {
+      config.attachment_formats = attachment_formats;
+    }
+    self.state.apply(&mut config.state);
+
     RenderPipeline::new(
       self.context,
       self.shader.unwrap(),
-      self.config.unwrap(),
+      config,
     ).context("failed to create render pipeline")
   }
 }
\ No newline at end of file
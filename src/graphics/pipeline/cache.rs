@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+use tracing::{debug, trace, warn};
+
+/// Size of `VkPipelineCacheHeaderVersionOne`, which starts every cache blob.
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// A driver pipeline cache persisted between runs, so pipelines don't have to be recompiled from scratch every startup.
+pub struct PipelineCache {
+  device: Arc<ash::Device>,
+  cache: vk::PipelineCache,
+  path: PathBuf,
+  vendor_id: u32,
+  device_id: u32,
+  uuid: [u8; vk::UUID_SIZE],
+}
+
+impl PipelineCache {
+  /// Seeds the cache from `path` if it holds data written by this exact device and driver, otherwise starts empty.
+  pub(crate) fn new(
+    device: Arc<ash::Device>,
+    properties: &vk::PhysicalDeviceProperties,
+    path: impl Into<PathBuf>,
+  ) -> Result<Self> {
+    let path = path.into();
+    let mut cache = Self {
+      device,
+      cache: vk::PipelineCache::null(),
+      path,
+      vendor_id: properties.vendor_id,
+      device_id: properties.device_id,
+      uuid: properties.pipeline_cache_uuid,
+    };
+
+    let initial_data = match std::fs::read(&cache.path) {
+      Ok(data) => match cache.validate(&data) {
+        Ok(()) => data,
+        Err(err) => {
+          warn!("Discarding pipeline cache {:?}: {err}", cache.path);
+          Vec::new()
+        }
+      },
+      Err(_) => Vec::new(),
+    };
+
+    cache.cache = Self::create(&cache.device, &initial_data)
+      .or_else(|err| {
+        // a driver may still reject data that looked compatible
+        warn!("Failed to load pipeline cache {:?}, starting empty: {err:#}", cache.path);
+        Self::create(&cache.device, &[])
+      })?;
+    debug!("Loaded pipeline cache with {} bytes from {:?}", initial_data.len(), cache.path);
+
+    Ok(cache)
+  }
+
+  pub fn handle(&self) -> vk::PipelineCache {
+    self.cache
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Writes the cache's current contents to its file.
+  pub fn save(&self) -> Result<()> {
+    let data = unsafe {
+      self.device.get_pipeline_cache_data(self.cache)
+    }.context("Failed to read pipeline cache data")?;
+
+    if let Some(dir) = self.path.parent() {
+      std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {dir:?}"))?;
+    }
+    // write beside the real file first, so a crash mid-write can't leave a truncated cache behind
+    let staging_path = self.path.with_extension("tmp");
+    std::fs::write(&staging_path, &data).with_context(|| format!("Failed to write {staging_path:?}"))?;
+    std::fs::rename(&staging_path, &self.path).with_context(|| format!("Failed to write {:?}", self.path))?;
+
+    trace!("Saved pipeline cache with {} bytes to {:?}", data.len(), self.path);
+    Ok(())
+  }
+
+  /// Replaces the cache with an empty one and deletes its file.
+  /// Pipelines created from the old cache are unaffected.
+  pub fn clear(&mut self) -> Result<()> {
+    let cache = Self::create(&self.device, &[])?;
+    unsafe {
+      self.device.destroy_pipeline_cache(self.cache, None);
+    }
+    self.cache = cache;
+
+    match std::fs::remove_file(&self.path) {
+      Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+        Err(err).with_context(|| format!("Failed to delete {:?}", self.path))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  fn create(device: &ash::Device, initial_data: &[u8]) -> Result<vk::PipelineCache> {
+    let create_info = vk::PipelineCacheCreateInfo::builder()
+      .initial_data(initial_data);
+    unsafe {
+      device.create_pipeline_cache(&create_info, None)
+    }.context("Failed to create pipeline cache")
+  }
+
+  /// Checks the blob's header against this device, as drivers aren't required to reject foreign data themselves.
+  fn validate(&self, data: &[u8]) -> Result<()> {
+    if data.len() < HEADER_SIZE {
+      anyhow::bail!("truncated header");
+    }
+    let read_u32 = |offset: usize| u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    let header_size = read_u32(0) as usize;
+    let header_version = vk::PipelineCacheHeaderVersion::from_raw(read_u32(4) as i32);
+    let vendor_id = read_u32(8);
+    let device_id = read_u32(12);
+    let uuid = &data[16..HEADER_SIZE];
+
+    if header_size < HEADER_SIZE || header_size > data.len() {
+      anyhow::bail!("invalid header size {header_size}");
+    }
+    if header_version != vk::PipelineCacheHeaderVersion::ONE {
+      anyhow::bail!("unsupported header version {header_version:?}");
+    }
+    if vendor_id != self.vendor_id || device_id != self.device_id {
+      anyhow::bail!(
+        "written by device {vendor_id:#06x}:{device_id:#06x}, but this is {:#06x}:{:#06x}",
+        self.vendor_id, self.device_id,
+      );
+    }
+    if uuid != self.uuid {
+      anyhow::bail!("written by a different driver version");
+    }
+    Ok(())
+  }
+
+  /// Saves and destroys the cache. Must run before the device is destroyed.
+  pub(crate) unsafe fn free(&mut self) {
+    if let Err(err) = self.save() {
+      warn!("{err:#}");
+    }
+    self.device.destroy_pipeline_cache(self.cache, None);
+    self.cache = vk::PipelineCache::null();
+  }
+}
@@ -0,0 +1,185 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::context::RenderContext;
+use crate::graphics::descriptor::{DescriptorSet, DescriptorSetLayout};
+use crate::graphics::Graphics;
+use crate::graphics::shader::{Shader, ShaderCreateInfo};
+use crate::graphics::shader::stage::Stage;
+
+use super::{ShaderMissing, ShaderSpecified};
+use super::layout::PipelineLayout;
+
+/// A pipeline running a single compute shader stage.
+pub struct ComputePipeline {
+  device: Arc<ash::Device>,
+  pipeline: vk::Pipeline,
+  layout: Arc<PipelineLayout>,
+  descriptor_set_layouts: Vec<DescriptorSetLayout>,
+  workgroup_size: [u32; 3],
+  #[allow(unused)]
+  shader: Shader,
+}
+
+impl ComputePipeline {
+  /// Uses the layout the shader declares when `pipeline_layout` is `None`.
+  pub fn new(context: &RenderContext, shader_info: ShaderCreateInfo, pipeline_layout: Option<Arc<PipelineLayout>>) -> Result<Self> {
+    let shader = shader_info.compile();
+
+    let workgroup_size = shader.reflection().stage(Stage::Compute)
+      .context("Shader has no compute stage")?
+      .workgroup_size
+      .unwrap_or([1, 1, 1]);
+
+    let (layout, descriptor_set_layouts) = match pipeline_layout {
+      Some(layout) => (layout, vec![]),
+      None => {
+        let reflected = shader.reflection().pipeline_layout(context)
+          .context("Failed to build pipeline layout from shader reflection")?;
+        (Arc::new(reflected.pipeline_layout), reflected.set_layouts)
+      }
+    };
+
+    let stage = shader.pipeline_shader_info().into_iter()
+      .find(|info| info.stage == vk::ShaderStageFlags::COMPUTE)
+      .context("Shader has no compute module")?;
+    let create_info = vk::ComputePipelineCreateInfo {
+      stage,
+      layout: layout.handle(),
+      ..Default::default()
+    };
+    let pipeline = unsafe {
+      context.device().create_compute_pipelines(context.pipeline_cache().handle(), &[create_info], None)
+        .map(|pipelines| pipelines[0])
+        .map_err(|(_, err)| anyhow::anyhow!("failed to create compute pipeline: {err:?}"))?
+    };
+
+    Ok(Self {
+      device: context.device(),
+      pipeline,
+      layout,
+      descriptor_set_layouts,
+      workgroup_size,
+      shader,
+    })
+  }
+
+  pub fn handle(&self) -> vk::Pipeline {
+    self.pipeline
+  }
+
+  pub fn layout(&self) -> &PipelineLayout {
+    &self.layout
+  }
+
+  /// The set layouts reflected from the shader, indexed by set number.
+  /// Empty when the pipeline was given an explicit layout.
+  pub fn descriptor_set_layouts(&self) -> &[DescriptorSetLayout] {
+    &self.descriptor_set_layouts
+  }
+
+  /// The shader's `local_size`, the invocations in each workgroup.
+  pub fn workgroup_size(&self) -> [u32; 3] {
+    self.workgroup_size
+  }
+
+  /// The number of workgroups needed for at least `invocations` invocations along each axis.
+  pub fn workgroup_count(&self, invocations: [u32; 3]) -> [u32; 3] {
+    [0, 1, 2].map(|axis| invocations[axis].div_ceil(self.workgroup_size[axis]))
+  }
+
+  pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+      device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+    }
+  }
+
+  /// Binds `descriptor_sets` to consecutive set indices, starting at 0.
+  pub fn bind_descriptor_sets(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, descriptor_sets: &[&DescriptorSet]) {
+    for (set_index, set) in descriptor_sets.iter().enumerate() {
+      set.bind_to(device, command_buffer, vk::PipelineBindPoint::COMPUTE, &self.layout, set_index as u32);
+    }
+  }
+
+  /// Records a dispatch of `workgroups` workgroups. The pipeline must be bound.
+  pub fn dispatch(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, [x, y, z]: [u32; 3]) {
+    unsafe {
+      device.cmd_dispatch(command_buffer, x, y, z);
+    }
+  }
+
+  /// Records a dispatch whose workgroup counts are read from a `vk::DispatchIndirectCommand` in `buffer` at `offset`.
+  /// The buffer needs `INDIRECT_BUFFER` usage. The pipeline must be bound.
+  pub fn dispatch_indirect(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, buffer: &Buffer, offset: vk::DeviceSize) {
+    unsafe {
+      device.cmd_dispatch_indirect(command_buffer, buffer.buffer, offset);
+    }
+  }
+}
+
+impl ComputePipeline {
+  unsafe fn free(&mut self) {
+    self.device.destroy_pipeline(self.pipeline, None);
+  }
+}
+
+impl Drop for ComputePipeline {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+pub struct ComputePipelineBuilder<'c, S> {
+  context: &'c RenderContext,
+  shader_specified: PhantomData<S>,
+  shader: Option<ShaderCreateInfo>,
+  pipeline_layout: Option<Arc<PipelineLayout>>,
+}
+
+impl<'c> ComputePipelineBuilder<'c, ShaderMissing> {
+  pub fn new(graphics: &'c Graphics) -> Self {
+    Self::from_context(graphics.context())
+  }
+
+  /// For use without `Graphics`, e.g. with a headless context.
+  pub fn from_context(context: &'c RenderContext) -> Self {
+    Self {
+      context,
+      shader_specified: PhantomData,
+      shader: None,
+      pipeline_layout: None,
+    }
+  }
+
+  pub fn with_shader(self, shader_info: ShaderCreateInfo) -> ComputePipelineBuilder<'c, ShaderSpecified> {
+    ComputePipelineBuilder {
+      context: self.context,
+      shader_specified: PhantomData,
+      shader: Some(shader_info),
+      pipeline_layout: self.pipeline_layout,
+    }
+  }
+}
+
+impl<'c, S> ComputePipelineBuilder<'c, S> {
+  /// Overrides the layout reflected from the shader.
+  pub fn with_pipeline_layout(mut self, pipeline_layout: Arc<PipelineLayout>) -> Self {
+    self.pipeline_layout = Some(pipeline_layout);
+    self
+  }
+}
+
+impl<'c> ComputePipelineBuilder<'c, ShaderSpecified> {
+  pub fn build(self) -> Result<ComputePipeline> {
+    ComputePipeline::new(
+      self.context,
+      self.shader.unwrap(),
+      self.pipeline_layout,
+    ).context("failed to create compute pipeline")
+  }
+}
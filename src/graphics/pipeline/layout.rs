@@ -0,0 +1,121 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+use bytemuck::Pod;
+
+use crate::graphics::context::RenderContext;
+use crate::graphics::descriptor::DescriptorSetLayout;
+
+/// The descriptor set layouts and push constant ranges a pipeline's shaders can access.
+pub struct PipelineLayout {
+  device: Arc<ash::Device>,
+  layout: vk::PipelineLayout,
+  set_layouts: Vec<vk::DescriptorSetLayout>,
+  push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineLayout {
+  pub fn builder() -> PipelineLayoutBuilder {
+    PipelineLayoutBuilder::default()
+  }
+
+  /// A layout without any descriptors or push constants.
+  pub fn empty(context: &RenderContext) -> Result<Self> {
+    Self::builder().build(context)
+  }
+
+  pub fn handle(&self) -> vk::PipelineLayout {
+    self.layout
+  }
+
+  pub fn set_layouts(&self) -> &[vk::DescriptorSetLayout] {
+    &self.set_layouts
+  }
+
+  pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+    &self.push_constant_ranges
+  }
+
+  /// Records a push constant update of `value` at byte `offset`.
+  pub fn push_constants<T: Pod>(
+    &self,
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    stages: vk::ShaderStageFlags,
+    offset: u32,
+    value: &T,
+  ) {
+    unsafe {
+      device.cmd_push_constants(command_buffer, self.layout, stages, offset, bytemuck::bytes_of(value));
+    }
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_pipeline_layout(self.layout, None);
+  }
+}
+
+impl Drop for PipelineLayout {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// Set layouts are referenced by index in the order they are added.
+#[derive(Default, Clone)]
+pub struct PipelineLayoutBuilder {
+  set_layouts: Vec<vk::DescriptorSetLayout>,
+  push_constant_ranges: Vec<vk::PushConstantRange>,
+}
+
+impl PipelineLayoutBuilder {
+  /// The set layout only needs to outlive the `build` call.
+  pub fn with_set_layout(mut self, set_layout: &DescriptorSetLayout) -> Self {
+    self.set_layouts.push(set_layout.handle());
+    self
+  }
+
+  pub fn with_push_constant_range(mut self, stages: vk::ShaderStageFlags, offset: u32, size: u32) -> Self {
+    self.push_constant_ranges.push(vk::PushConstantRange {
+      stage_flags: stages,
+      offset,
+      size,
+    });
+    self
+  }
+
+  /// A range sized for `T`, placed after any ranges added before.
+  pub fn with_push_constants<T: Pod>(self, stages: vk::ShaderStageFlags) -> Self {
+    let offset = self.push_constant_ranges.iter().map(|r| r.offset + r.size).max().unwrap_or(0);
+    self.with_push_constant_range(stages, offset, std::mem::size_of::<T>() as u32)
+  }
+
+  pub fn build(self, context: &RenderContext) -> Result<PipelineLayout> {
+    for range in &self.push_constant_ranges {
+      if range.offset % 4 != 0 || range.size % 4 != 0 || range.size == 0 {
+        anyhow::bail!("Push constant range {}..{} must be a non-empty multiple of 4 bytes", range.offset, range.offset + range.size);
+      }
+    }
+
+    let create_info = vk::PipelineLayoutCreateInfo {
+      set_layout_count: self.set_layouts.len() as u32,
+      p_set_layouts: self.set_layouts.as_ptr(),
+      push_constant_range_count: self.push_constant_ranges.len() as u32,
+      p_push_constant_ranges: self.push_constant_ranges.as_ptr(),
+      ..Default::default()
+    };
+
+    let layout = unsafe {
+      context.device().create_pipeline_layout(&create_info, None)
+    }.context("Failed to create pipeline layout")?;
+
+    Ok(PipelineLayout {
+      device: context.device(),
+      layout,
+      set_layouts: self.set_layouts,
+      push_constant_ranges: self.push_constant_ranges,
+    })
+  }
+}
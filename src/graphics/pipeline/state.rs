@@ -0,0 +1,279 @@
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+/// How fragment colors combine with what's already in the attachment.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BlendMode {
+  /// Overwrites the attachment.
+  #[default]
+  Opaque,
+  /// Straight alpha, color is scaled by its alpha when blended.
+  Alpha,
+  /// Color was already multiplied by its alpha.
+  Premultiplied,
+  /// Adds alpha-scaled color, e.g. for particles and light.
+  Additive,
+  /// Multiplies the attachment by the color, e.g. for shadows and tinting.
+  Multiply,
+}
+
+impl BlendMode {
+  pub fn attachment_state(self) -> vk::PipelineColorBlendAttachmentState {
+    let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+      BlendMode::Opaque => return vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::FALSE,
+        color_write_mask: vk::ColorComponentFlags::RGBA,
+        ..Default::default()
+      },
+      BlendMode::Alpha => (
+        vk::BlendFactor::SRC_ALPHA,
+        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        vk::BlendFactor::ONE,
+        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+      ),
+      BlendMode::Premultiplied => (
+        vk::BlendFactor::ONE,
+        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        vk::BlendFactor::ONE,
+        vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+      ),
+      BlendMode::Additive => (
+        vk::BlendFactor::SRC_ALPHA,
+        vk::BlendFactor::ONE,
+        vk::BlendFactor::ONE,
+        vk::BlendFactor::ONE,
+      ),
+      BlendMode::Multiply => (
+        vk::BlendFactor::DST_COLOR,
+        vk::BlendFactor::ZERO,
+        vk::BlendFactor::ZERO,
+        vk::BlendFactor::ONE,
+      ),
+    };
+
+    vk::PipelineColorBlendAttachmentState {
+      blend_enable: vk::TRUE,
+      src_color_blend_factor: src_color,
+      dst_color_blend_factor: dst_color,
+      color_blend_op: vk::BlendOp::ADD,
+      src_alpha_blend_factor: src_alpha,
+      dst_alpha_blend_factor: dst_alpha,
+      alpha_blend_op: vk::BlendOp::ADD,
+      color_write_mask: vk::ColorComponentFlags::RGBA,
+    }
+  }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CompareOp {
+  Never,
+  #[default]
+  Less,
+  Equal,
+  LessOrEqual,
+  Greater,
+  NotEqual,
+  GreaterOrEqual,
+  Always,
+}
+
+impl From<CompareOp> for vk::CompareOp {
+  fn from(value: CompareOp) -> Self {
+    match value {
+      CompareOp::Never => vk::CompareOp::NEVER,
+      CompareOp::Less => vk::CompareOp::LESS,
+      CompareOp::Equal => vk::CompareOp::EQUAL,
+      CompareOp::LessOrEqual => vk::CompareOp::LESS_OR_EQUAL,
+      CompareOp::Greater => vk::CompareOp::GREATER,
+      CompareOp::NotEqual => vk::CompareOp::NOT_EQUAL,
+      CompareOp::GreaterOrEqual => vk::CompareOp::GREATER_OR_EQUAL,
+      CompareOp::Always => vk::CompareOp::ALWAYS,
+    }
+  }
+}
+
+/// Whether fragments are depth tested, and whether passing ones write their depth.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DepthMode {
+  Off,
+  /// Tests without writing, e.g. for transparent geometry drawn after opaque geometry.
+  TestOnly(CompareOp),
+  TestAndWrite(CompareOp),
+}
+
+impl DepthMode {
+  pub fn test_enabled(self) -> bool {
+    !matches!(self, DepthMode::Off)
+  }
+
+  pub fn write_enabled(self) -> bool {
+    matches!(self, DepthMode::TestAndWrite(_))
+  }
+
+  pub fn compare_op(self) -> vk::CompareOp {
+    match self {
+      DepthMode::Off => vk::CompareOp::ALWAYS,
+      DepthMode::TestOnly(op) | DepthMode::TestAndWrite(op) => op.into(),
+    }
+  }
+}
+
+impl Default for DepthMode {
+  fn default() -> Self {
+    DepthMode::TestAndWrite(CompareOp::Less)
+  }
+}
+
+/// Which faces are discarded before rasterization.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CullMode {
+  None,
+  Front,
+  #[default]
+  Back,
+  FrontAndBack,
+}
+
+impl From<CullMode> for vk::CullModeFlags {
+  fn from(value: CullMode) -> Self {
+    match value {
+      CullMode::None => vk::CullModeFlags::NONE,
+      CullMode::Front => vk::CullModeFlags::FRONT,
+      CullMode::Back => vk::CullModeFlags::BACK,
+      CullMode::FrontAndBack => vk::CullModeFlags::FRONT_AND_BACK,
+    }
+  }
+}
+
+/// The winding order of front facing triangles.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FrontFace {
+  #[default]
+  CounterClockwise,
+  Clockwise,
+}
+
+impl From<FrontFace> for vk::FrontFace {
+  fn from(value: FrontFace) -> Self {
+    match value {
+      FrontFace::CounterClockwise => vk::FrontFace::COUNTER_CLOCKWISE,
+      FrontFace::Clockwise => vk::FrontFace::CLOCKWISE,
+    }
+  }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PolygonMode {
+  #[default]
+  Fill,
+  /// Wireframe. Requires `DeviceFeature::FillModeNonSolid`.
+  Line,
+  /// Requires `DeviceFeature::FillModeNonSolid`.
+  Point,
+}
+
+impl From<PolygonMode> for vk::PolygonMode {
+  fn from(value: PolygonMode) -> Self {
+    match value {
+      PolygonMode::Fill => vk::PolygonMode::FILL,
+      PolygonMode::Line => vk::PolygonMode::LINE,
+      PolygonMode::Point => vk::PolygonMode::POINT,
+    }
+  }
+}
+
+/// How vertices are assembled into primitives.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Topology {
+  PointList,
+  LineList,
+  LineStrip,
+  #[default]
+  TriangleList,
+  TriangleStrip,
+  TriangleFan,
+}
+
+impl From<Topology> for vk::PrimitiveTopology {
+  fn from(value: Topology) -> Self {
+    match value {
+      Topology::PointList => vk::PrimitiveTopology::POINT_LIST,
+      Topology::LineList => vk::PrimitiveTopology::LINE_LIST,
+      Topology::LineStrip => vk::PrimitiveTopology::LINE_STRIP,
+      Topology::TriangleList => vk::PrimitiveTopology::TRIANGLE_LIST,
+      Topology::TriangleStrip => vk::PrimitiveTopology::TRIANGLE_STRIP,
+      Topology::TriangleFan => vk::PrimitiveTopology::TRIANGLE_FAN,
+    }
+  }
+}
+
+/// The fixed function state of a graphics pipeline, in a form that can be stored in asset files.
+/// Missing fields deserialize to their defaults.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineState {
+  pub blend: BlendMode,
+  pub depth: DepthMode,
+  pub cull_mode: CullMode,
+  pub front_face: FrontFace,
+  pub polygon_mode: PolygonMode,
+  pub topology: Topology,
+}
+
+impl PipelineState {
+  pub fn with_blend_mode(mut self, blend: BlendMode) -> Self {
+    self.blend = blend;
+    self
+  }
+
+  pub fn with_depth_mode(mut self, depth: DepthMode) -> Self {
+    self.depth = depth;
+    self
+  }
+
+  pub fn with_cull_mode(mut self, cull_mode: CullMode) -> Self {
+    self.cull_mode = cull_mode;
+    self
+  }
+
+  pub fn with_front_face(mut self, front_face: FrontFace) -> Self {
+    self.front_face = front_face;
+    self
+  }
+
+  pub fn with_polygon_mode(mut self, polygon_mode: PolygonMode) -> Self {
+    self.polygon_mode = polygon_mode;
+    self
+  }
+
+  pub fn with_topology(mut self, topology: Topology) -> Self {
+    self.topology = topology;
+    self
+  }
+}
+
+/// State set on a `RenderPipelineBuilder`, applied over its config's.
+#[derive(Debug, Default, Copy, Clone)]
+pub(crate) struct PipelineStateOverrides {
+  pub state: Option<PipelineState>,
+  pub blend: Option<BlendMode>,
+  pub depth: Option<DepthMode>,
+  pub cull_mode: Option<CullMode>,
+  pub front_face: Option<FrontFace>,
+  pub polygon_mode: Option<PolygonMode>,
+  pub topology: Option<Topology>,
+}
+
+impl PipelineStateOverrides {
+  pub fn apply(self, state: &mut PipelineState) {
+    if let Some(overridden) = self.state {
+      *state = overridden;
+    }
+    state.blend = self.blend.unwrap_or(state.blend);
+    state.depth = self.depth.unwrap_or(state.depth);
+    state.cull_mode = self.cull_mode.unwrap_or(state.cull_mode);
+    state.front_face = self.front_face.unwrap_or(state.front_face);
+    state.polygon_mode = self.polygon_mode.unwrap_or(state.polygon_mode);
+    state.topology = self.topology.unwrap_or(state.topology);
+  }
+}
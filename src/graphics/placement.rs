@@ -0,0 +1,60 @@
+use std::{fs, path::{Path, PathBuf}};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::graphics::window::Window;
+
+/// Window size, position and state as saved between runs.
+///
+/// Size and position are those of the restored window, so un-maximizing after a restore behaves as before.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowPlacement {
+  pub width: u32,
+  pub height: u32,
+  /// `None` where the platform doesn't expose window positions, e.g. on Wayland.
+  #[serde(default)]
+  pub position: Option<(i32, i32)>,
+  #[serde(default)]
+  pub monitor: Option<String>,
+  pub maximized: bool,
+  pub fullscreen: bool,
+}
+
+impl WindowPlacement {
+  const FILE_NAME: &'static str = "window.ron";
+
+  pub fn capture(window: &Window) -> Self {
+    let (width, height) = window.restored_size();
+    Self {
+      width,
+      height,
+      position: window.restored_position(),
+      monitor: window.current_monitor().ok().and_then(|monitor| monitor.name),
+      maximized: window.is_maximized(),
+      fullscreen: window.is_fullscreen(),
+    }
+  }
+
+  /// Placement file inside the user config directory, e.g. `~/.config/<app_name>/window.ron`.
+  pub fn file_path(app_name: &str) -> Result<PathBuf> {
+    Ok(
+      dirs::config_dir().context("Failed to find user config directory")?
+        .join(app_name)
+        .join(Self::FILE_NAME)
+    )
+  }
+
+  pub fn load(path: &Path) -> Result<Self> {
+    let contents = fs::read_to_string(path).context("Failed to read window placement file")?;
+    ron::from_str(&contents).context("Failed to parse window placement file")
+  }
+
+  pub fn save(&self, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent).context("Failed to create window placement directory")?;
+    }
+    let contents = ron::ser::to_string_pretty(self, Default::default())
+      .context("Failed to serialize window placement")?;
+    fs::write(path, contents).context("Failed to write window placement file")
+  }
+}
@@ -0,0 +1,200 @@
+use std::{fs::File, io::BufWriter, path::Path};
+use anyhow::{Context, Result};
+use ash::vk;
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::context::RenderContext;
+use crate::graphics::image::transition_layout;
+
+/// Tightly packed 8 bit RGBA pixels read back from the gpu or loaded from disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+  pub width: u32,
+  pub height: u32,
+  pub pixels: Vec<u8>,
+}
+
+/// Result of comparing two images, e.g. a rendered frame against a reference.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ImageDiff {
+  /// Pixels with any channel differing by more than the tolerance.
+  pub mismatched_pixels: usize,
+  pub max_channel_delta: u8,
+}
+
+impl RgbaImage {
+  /// Converts raw texel data in `format` to RGBA. Only 8 bit RGBA and BGRA formats are supported.
+  pub fn from_raw(width: u32, height: u32, format: vk::Format, mut data: Vec<u8>) -> Result<Self> {
+    check_readback_format(format)?;
+    if matches!(format, vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB) {
+      for texel in data.chunks_exact_mut(4) {
+        texel.swap(0, 2);
+      }
+    }
+
+    data.truncate(width as usize * height as usize * 4);
+    Ok(Self {
+      width,
+      height,
+      pixels: data,
+    })
+  }
+
+  pub fn load_png(path: &Path) -> Result<Self> {
+    let file = File::open(path).with_context(|| format!("Failed to open {path:?}"))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().context("Failed to read png header")?;
+
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).context("Failed to decode png")?;
+    data.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+      png::ColorType::Rgba => data,
+      png::ColorType::Rgb => data
+        .chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+        .collect(),
+      color_type => anyhow::bail!("Unsupported png color type: {color_type:?}"),
+    };
+
+    Ok(Self {
+      width: info.width,
+      height: info.height,
+      pixels,
+    })
+  }
+
+  pub fn save_png(&self, path: &Path) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("Failed to create {path:?}"))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().context("Failed to write png header")?;
+    writer.write_image_data(&self.pixels).context("Failed to write png data")
+  }
+
+  /// Compares two images of the same size, allowing each channel to differ by `tolerance`.
+  pub fn diff(&self, other: &Self, tolerance: u8) -> Result<ImageDiff> {
+    if (self.width, self.height) != (other.width, other.height) {
+      anyhow::bail!(
+        "Image sizes differ: {}x{} vs {}x{}",
+        self.width, self.height, other.width, other.height
+      );
+    }
+
+    let mut diff = ImageDiff {
+      mismatched_pixels: 0,
+      max_channel_delta: 0,
+    };
+
+    for (a, b) in self.pixels.chunks_exact(4).zip(other.pixels.chunks_exact(4)) {
+      let delta = a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+      diff.max_channel_delta = diff.max_channel_delta.max(delta);
+      if delta > tolerance {
+        diff.mismatched_pixels += 1;
+      }
+    }
+
+    Ok(diff)
+  }
+}
+
+/// Fails for formats that can't be converted to `RgbaImage`. Readback buffers hold 4 bytes per texel,
+/// so this must pass before any copy into one is recorded.
+pub(crate) fn check_readback_format(format: vk::Format) -> Result<()> {
+  match format {
+    vk::Format::R8G8B8A8_UNORM
+    | vk::Format::R8G8B8A8_SRGB
+    | vk::Format::B8G8R8A8_UNORM
+    | vk::Format::B8G8R8A8_SRGB => Ok(()),
+    _ => anyhow::bail!("Unsupported format for readback: {format:?}, only 8 bit RGBA and BGRA can be read back"),
+  }
+}
+
+/// Copies a color image into host memory, returning it to `layout` afterwards. Blocks until the copy is done.
+pub(crate) fn read_image(
+  context: &RenderContext,
+  image: vk::Image,
+  layout: vk::ImageLayout,
+  extent: vk::Extent2D,
+  format: vk::Format,
+) -> Result<RgbaImage> {
+  let buffer = create_readback_buffer(context, extent, format)?;
+  let device = context.device();
+
+  let command_buffer = context.begin_single_time_commands()?;
+  record_image_copy(&device, command_buffer, image, layout, layout, extent, &buffer);
+  context.end_single_time_commands(command_buffer)?;
+
+  RgbaImage::from_raw(extent.width, extent.height, format, read_buffer(&buffer)?)
+}
+
+/// Host visible buffer large enough for an image of `extent` in `format`. Fails for formats that can't be read back.
+pub(crate) fn create_readback_buffer(context: &RenderContext, extent: vk::Extent2D, format: vk::Format) -> Result<Buffer> {
+  check_readback_format(format)?;
+  Buffer::new(
+    context,
+    extent.width as vk::DeviceSize * extent.height as vk::DeviceSize * 4,
+    vk::BufferUsageFlags::TRANSFER_DST,
+    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+  )
+}
+
+/// Records a copy of a color image into `buffer`, moving the image from `layout` to `final_layout`.
+pub(crate) fn record_image_copy(
+  device: &ash::Device,
+  command_buffer: vk::CommandBuffer,
+  image: vk::Image,
+  layout: vk::ImageLayout,
+  final_layout: vk::ImageLayout,
+  extent: vk::Extent2D,
+  buffer: &Buffer,
+) {
+  let range = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+  };
+
+  if layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL {
+    transition_layout(device, command_buffer, image, range, layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+  }
+
+  let copy_region = vk::BufferImageCopy {
+    image_subresource: vk::ImageSubresourceLayers {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      layer_count: 1,
+      ..Default::default()
+    },
+    image_extent: vk::Extent3D {
+      width: extent.width,
+      height: extent.height,
+      depth: 1,
+    },
+    ..Default::default()
+  };
+
+  unsafe {
+    device.cmd_copy_image_to_buffer(
+      command_buffer,
+      image,
+      vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+      buffer.buffer,
+      &[copy_region],
+    );
+  }
+
+  if final_layout != vk::ImageLayout::TRANSFER_SRC_OPTIMAL && final_layout != vk::ImageLayout::UNDEFINED {
+    transition_layout(device, command_buffer, image, range, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, final_layout);
+  }
+}
+
+/// Copies the contents of a host visible buffer. The gpu must be done writing to it.
+pub(crate) fn read_buffer(buffer: &Buffer) -> Result<Vec<u8>> {
+  buffer.allocation().read(0, buffer.size).context("Failed to read readback buffer")
+}
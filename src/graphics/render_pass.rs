@@ -0,0 +1,520 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+
+use crate::graphics::context::RenderContext;
+use crate::graphics::swapchain::Swapchain;
+
+/// How a render pass or dynamic rendering treats one image: its format, and what happens to its
+/// contents at the start and end of rendering.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Attachment {
+  pub format: vk::Format,
+  pub samples: vk::SampleCountFlags,
+  pub load_op: vk::AttachmentLoadOp,
+  pub store_op: vk::AttachmentStoreOp,
+  pub stencil_load_op: vk::AttachmentLoadOp,
+  pub stencil_store_op: vk::AttachmentStoreOp,
+  pub initial_layout: vk::ImageLayout,
+  pub final_layout: vk::ImageLayout,
+}
+
+impl Attachment {
+  /// Cleared at the start and stored at the end, left in `COLOR_ATTACHMENT_OPTIMAL`.
+  pub fn color(format: vk::Format) -> Self {
+    Self {
+      format,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::CLEAR,
+      store_op: vk::AttachmentStoreOp::STORE,
+      stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+      stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    }
+  }
+
+  /// Draws on top of a `Frame`, which is already cleared and in `COLOR_ATTACHMENT_OPTIMAL`.
+  pub fn frame(format: vk::Format) -> Self {
+    Self {
+      load_op: vk::AttachmentLoadOp::LOAD,
+      initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+      ..Self::color(format)
+    }
+  }
+
+  /// Cleared at the start and discarded at the end, since depth is rarely needed afterwards.
+  pub fn depth(format: vk::Format) -> Self {
+    Self {
+      format,
+      samples: vk::SampleCountFlags::TYPE_1,
+      load_op: vk::AttachmentLoadOp::CLEAR,
+      store_op: vk::AttachmentStoreOp::DONT_CARE,
+      stencil_load_op: vk::AttachmentLoadOp::CLEAR,
+      stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    }
+  }
+
+  /// The single sampled target a multisampled color attachment resolves into.
+  pub fn resolve(format: vk::Format) -> Self {
+    Self {
+      load_op: vk::AttachmentLoadOp::DONT_CARE,
+      ..Self::color(format)
+    }
+  }
+
+  pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+    self.samples = samples;
+    self
+  }
+
+  pub fn with_ops(mut self, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+    self.load_op = load_op;
+    self.store_op = store_op;
+    self
+  }
+
+  pub fn with_stencil_ops(mut self, load_op: vk::AttachmentLoadOp, store_op: vk::AttachmentStoreOp) -> Self {
+    self.stencil_load_op = load_op;
+    self.stencil_store_op = store_op;
+    self
+  }
+
+  pub fn with_layouts(mut self, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> Self {
+    self.initial_layout = initial_layout;
+    self.final_layout = final_layout;
+    self
+  }
+
+  /// The same attachment for `RenderContext::cmd_begin_rendering`. The image must already be in `image_layout`,
+  /// since dynamic rendering performs no layout transitions.
+  pub fn rendering_info(
+    &self,
+    image_view: vk::ImageView,
+    image_layout: vk::ImageLayout,
+    clear_value: vk::ClearValue,
+  ) -> vk::RenderingAttachmentInfo {
+    vk::RenderingAttachmentInfo {
+      image_view,
+      image_layout,
+      load_op: self.load_op,
+      store_op: self.store_op,
+      clear_value,
+      ..Default::default()
+    }
+  }
+
+  fn description(&self) -> vk::AttachmentDescription {
+    vk::AttachmentDescription {
+      flags: vk::AttachmentDescriptionFlags::empty(),
+      format: self.format,
+      samples: self.samples,
+      load_op: self.load_op,
+      store_op: self.store_op,
+      stencil_load_op: self.stencil_load_op,
+      stencil_store_op: self.stencil_store_op,
+      initial_layout: self.initial_layout,
+      final_layout: self.final_layout,
+    }
+  }
+}
+
+/// The formats a pipeline renders into, taken from a render pass subpass or given directly for
+/// dynamic rendering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentFormats {
+  pub color: Vec<vk::Format>,
+  pub depth: Option<vk::Format>,
+  pub samples: vk::SampleCountFlags,
+}
+
+impl AttachmentFormats {
+  pub fn new(color: impl IntoIterator<Item = vk::Format>) -> Self {
+    Self {
+      color: color.into_iter().collect(),
+      depth: None,
+      samples: vk::SampleCountFlags::TYPE_1,
+    }
+  }
+
+  pub fn with_depth(mut self, format: vk::Format) -> Self {
+    self.depth = Some(format);
+    self
+  }
+
+  pub fn with_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+    self.samples = samples;
+    self
+  }
+}
+
+impl Default for AttachmentFormats {
+  /// A single color attachment in the format swapchains prefer.
+  fn default() -> Self {
+    Self::new([vk::Format::B8G8R8A8_SRGB])
+  }
+}
+
+/// Which of the render pass' attachments a subpass uses, by index.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Subpass {
+  pub color: Vec<u32>,
+  pub depth: Option<u32>,
+  /// Parallel to `color`, resolving each color attachment into the one at the same position.
+  pub resolve: Vec<u32>,
+  pub input: Vec<u32>,
+}
+
+impl Subpass {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_color(mut self, attachment: u32) -> Self {
+    self.color.push(attachment);
+    self
+  }
+
+  pub fn with_depth(mut self, attachment: u32) -> Self {
+    self.depth = Some(attachment);
+    self
+  }
+
+  pub fn with_resolve(mut self, attachment: u32) -> Self {
+    self.resolve.push(attachment);
+    self
+  }
+
+  pub fn with_input(mut self, attachment: u32) -> Self {
+    self.input.push(attachment);
+    self
+  }
+}
+
+pub struct RenderPass {
+  device: Arc<ash::Device>,
+  render_pass: vk::RenderPass,
+  attachments: Vec<Attachment>,
+  subpasses: Vec<Subpass>,
+}
+
+impl RenderPass {
+  pub fn builder() -> RenderPassBuilder {
+    RenderPassBuilder::default()
+  }
+
+  pub fn handle(&self) -> vk::RenderPass {
+    self.render_pass
+  }
+
+  pub fn attachments(&self) -> &[Attachment] {
+    &self.attachments
+  }
+
+  pub fn subpasses(&self) -> &[Subpass] {
+    &self.subpasses
+  }
+
+  /// The formats a pipeline used in `subpass` must be created with.
+  pub fn attachment_formats(&self, subpass: u32) -> Result<AttachmentFormats> {
+    let description = self.subpasses.get(subpass as usize)
+      .with_context(|| format!("Render pass has no subpass {subpass}"))?;
+    let color: Vec<_> = description.color.iter().map(|i| self.attachments[*i as usize]).collect();
+    let depth = description.depth.map(|i| self.attachments[i as usize]);
+
+    Ok(AttachmentFormats {
+      samples: color.first().or(depth.as_ref()).map_or(vk::SampleCountFlags::TYPE_1, |a| a.samples),
+      color: color.iter().map(|a| a.format).collect(),
+      depth: depth.map(|a| a.format),
+    })
+  }
+
+  /// `clear_values` is indexed by attachment, and only read for attachments that are cleared.
+  pub fn begin(
+    &self,
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    framebuffer: &Framebuffer,
+    clear_values: &[vk::ClearValue],
+  ) {
+    let begin_info = vk::RenderPassBeginInfo {
+      render_pass: self.render_pass,
+      framebuffer: framebuffer.handle(),
+      render_area: vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent: framebuffer.extent(),
+      },
+      clear_value_count: clear_values.len() as u32,
+      p_clear_values: clear_values.as_ptr(),
+      ..Default::default()
+    };
+    unsafe {
+      device.cmd_begin_render_pass(command_buffer, &begin_info, vk::SubpassContents::INLINE);
+    }
+  }
+
+  pub fn next_subpass(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+      device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE);
+    }
+  }
+
+  pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    unsafe {
+      device.cmd_end_render_pass(command_buffer);
+    }
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_render_pass(self.render_pass, None);
+  }
+}
+
+impl Drop for RenderPass {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// Attachments are referenced by index in the order they are added, regardless of kind.
+/// Without explicit subpasses, a single one uses every attachment.
+#[derive(Default, Clone)]
+pub struct RenderPassBuilder {
+  attachments: Vec<Attachment>,
+  color: Vec<u32>,
+  depth: Option<u32>,
+  resolve: Vec<u32>,
+  subpasses: Vec<Subpass>,
+}
+
+impl RenderPassBuilder {
+  pub fn with_color_attachment(mut self, attachment: Attachment) -> Self {
+    self.color.push(self.attachments.len() as u32);
+    self.attachments.push(attachment);
+    self
+  }
+
+  pub fn with_depth_attachment(mut self, attachment: Attachment) -> Self {
+    self.depth = Some(self.attachments.len() as u32);
+    self.attachments.push(attachment);
+    self
+  }
+
+  pub fn with_resolve_attachment(mut self, attachment: Attachment) -> Self {
+    self.resolve.push(self.attachments.len() as u32);
+    self.attachments.push(attachment);
+    self
+  }
+
+  pub fn with_subpass(mut self, subpass: Subpass) -> Self {
+    self.subpasses.push(subpass);
+    self
+  }
+
+  pub fn build(self, context: &RenderContext) -> Result<RenderPass> {
+    let subpasses = if self.subpasses.is_empty() {
+      vec![Subpass {
+        color: self.color,
+        depth: self.depth,
+        resolve: self.resolve,
+        input: vec![],
+      }]
+    } else {
+      self.subpasses
+    };
+
+    for (i, subpass) in subpasses.iter().enumerate() {
+      let used = subpass.color.iter().chain(&subpass.depth).chain(&subpass.resolve).chain(&subpass.input);
+      if let Some(attachment) = used.copied().find(|a| *a as usize >= self.attachments.len()) {
+        anyhow::bail!("Subpass {i} uses attachment {attachment}, but the render pass only has {}", self.attachments.len());
+      }
+      if !subpass.resolve.is_empty() && subpass.resolve.len() != subpass.color.len() {
+        anyhow::bail!("Subpass {i} must resolve either none or all of its {} color attachments", subpass.color.len());
+      }
+    }
+
+    let attachments: Vec<_> = self.attachments.iter().map(Attachment::description).collect();
+
+    let reference = |attachment: u32, layout: vk::ImageLayout| vk::AttachmentReference {
+      attachment,
+      layout,
+    };
+    // kept alive until the render pass is created, since the descriptions point into them
+    let references: Vec<_> = subpasses.iter()
+      .map(|s| (
+        s.color.iter().map(|a| reference(*a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)).collect::<Vec<_>>(),
+        s.depth.map(|a| reference(a, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)),
+        s.resolve.iter().map(|a| reference(*a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)).collect::<Vec<_>>(),
+        s.input.iter().map(|a| reference(*a, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)).collect::<Vec<_>>(),
+      ))
+      .collect();
+
+    let subpass_descriptions: Vec<_> = references.iter()
+      .map(|(color, depth, resolve, input)| vk::SubpassDescription {
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        input_attachment_count: input.len() as u32,
+        p_input_attachments: input.as_ptr(),
+        color_attachment_count: color.len() as u32,
+        p_color_attachments: color.as_ptr(),
+        p_resolve_attachments: if resolve.is_empty() { std::ptr::null() } else { resolve.as_ptr() },
+        p_depth_stencil_attachment: depth.as_ref().map_or(std::ptr::null(), |d| d as *const _),
+        ..Default::default()
+      })
+      .collect();
+
+    let attachment_stages = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+      | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+      | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS;
+    let attachment_access = vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE;
+
+    // waits for earlier work on the attachments, then orders each subpass after the one before
+    let mut dependencies = vec![vk::SubpassDependency {
+      src_subpass: vk::SUBPASS_EXTERNAL,
+      dst_subpass: 0,
+      src_stage_mask: attachment_stages,
+      dst_stage_mask: attachment_stages,
+      src_access_mask: vk::AccessFlags::empty(),
+      dst_access_mask: attachment_access,
+      dependency_flags: vk::DependencyFlags::empty(),
+    }];
+    dependencies.extend((1..subpasses.len() as u32).map(|i| vk::SubpassDependency {
+      src_subpass: i - 1,
+      dst_subpass: i,
+      src_stage_mask: attachment_stages,
+      dst_stage_mask: attachment_stages | vk::PipelineStageFlags::FRAGMENT_SHADER,
+      src_access_mask: attachment_access,
+      dst_access_mask: attachment_access | vk::AccessFlags::INPUT_ATTACHMENT_READ,
+      dependency_flags: vk::DependencyFlags::BY_REGION,
+    }));
+
+    let create_info = vk::RenderPassCreateInfo {
+      attachment_count: attachments.len() as u32,
+      p_attachments: attachments.as_ptr(),
+      subpass_count: subpass_descriptions.len() as u32,
+      p_subpasses: subpass_descriptions.as_ptr(),
+      dependency_count: dependencies.len() as u32,
+      p_dependencies: dependencies.as_ptr(),
+      ..Default::default()
+    };
+
+    let render_pass = unsafe {
+      context.device().create_render_pass(&create_info, None)
+    }.context("Failed to create render pass")?;
+
+    Ok(RenderPass {
+      device: context.device(),
+      render_pass,
+      attachments: self.attachments,
+      subpasses,
+    })
+  }
+}
+
+/// The image views a render pass draws into, one per attachment.
+pub struct Framebuffer {
+  device: Arc<ash::Device>,
+  framebuffer: vk::Framebuffer,
+  extent: vk::Extent2D,
+}
+
+impl Framebuffer {
+  /// The views must outlive the framebuffer and be ordered like the render pass' attachments.
+  pub fn new(context: &RenderContext, render_pass: &RenderPass, views: &[vk::ImageView], extent: vk::Extent2D) -> Result<Self> {
+    if views.len() != render_pass.attachments().len() {
+      anyhow::bail!(
+        "Framebuffer has {} views, but the render pass has {} attachments",
+        views.len(),
+        render_pass.attachments().len(),
+      );
+    }
+
+    let create_info = vk::FramebufferCreateInfo {
+      render_pass: render_pass.handle(),
+      attachment_count: views.len() as u32,
+      p_attachments: views.as_ptr(),
+      width: extent.width,
+      height: extent.height,
+      layers: 1,
+      ..Default::default()
+    };
+
+    let framebuffer = unsafe {
+      context.device().create_framebuffer(&create_info, None)
+    }.context("Failed to create framebuffer")?;
+
+    Ok(Self {
+      device: context.device(),
+      framebuffer,
+      extent,
+    })
+  }
+
+  pub fn handle(&self) -> vk::Framebuffer {
+    self.framebuffer
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    self.extent
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_framebuffer(self.framebuffer, None);
+  }
+}
+
+impl Drop for Framebuffer {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// One framebuffer per swapchain image, with the image as attachment 0.
+///
+/// Must be recreated along with the swapchain, see `is_stale`.
+pub struct SwapchainFramebuffers {
+  swapchain_generation: u64,
+  render_pass: vk::RenderPass,
+  framebuffers: Vec<Framebuffer>,
+}
+
+impl SwapchainFramebuffers {
+  /// `extra_views` fill the attachments after the swapchain image, e.g. a depth buffer.
+  pub fn new(context: &RenderContext, render_pass: &RenderPass, swapchain: &Swapchain, extra_views: &[vk::ImageView]) -> Result<Self> {
+    let first = render_pass.attachments().first().context("Render pass has no attachments")?;
+    if first.format != swapchain.format().format {
+      anyhow::bail!(
+        "Render pass attachment 0 is {:?}, but the swapchain's format is {:?}",
+        first.format,
+        swapchain.format().format,
+      );
+    }
+
+    let framebuffers = swapchain.image_views().iter()
+      .map(|view| {
+        let views: Vec<_> = std::iter::once(*view).chain(extra_views.iter().copied()).collect();
+        Framebuffer::new(context, render_pass, &views, swapchain.extent())
+      })
+      .collect::<Result<Vec<_>>>()?;
+
+    Ok(Self {
+      swapchain_generation: swapchain.generation(),
+      render_pass: render_pass.handle(),
+      framebuffers,
+    })
+  }
+
+  /// Whether these were created for another swapchain or render pass.
+  pub fn is_stale(&self, render_pass: &RenderPass, swapchain: &Swapchain) -> bool {
+    self.swapchain_generation != swapchain.generation() || self.render_pass != render_pass.handle()
+  }
+
+  pub fn get(&self, image_index: u32) -> Option<&Framebuffer> {
+    self.framebuffers.get(image_index as usize)
+  }
+}
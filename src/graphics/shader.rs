@@ -8,6 +8,7 @@ use strum::IntoEnumIterator;
 use tracing::{debug, error, trace};
 
 use crate::graphics::shader::lang::Source;
+use crate::graphics::shader::reflect::{ShaderReflection, StageReflection};
 use crate::graphics::shader::stage::Stage;
 
 use super::context::RenderContext;
@@ -15,6 +16,14 @@ use super::context::RenderContext;
 pub mod stage;
 pub mod lang;
 pub mod builder;
+pub mod reflect;
+
+/// Where compiled shader stages and the pipeline cache are kept, next to the executable.
+pub(crate) fn cache_dir() -> Result<PathBuf> {
+  Ok(env::current_exe()?
+    .parent().context("failed to access current_exe parent dir")?
+    .join("tmp/res/shaders"))
+}
 
 #[derive(Default, Clone)]
 pub struct ShaderCreateInfo {
@@ -42,6 +51,7 @@ impl ShaderSource {
 pub struct Shader {
   pub device: Arc<ash::Device>,
   pub modules: HashMap<Stage, vk::ShaderModule>,
+  pub reflection: ShaderReflection,
 }
 
 impl Shader {
@@ -52,11 +62,18 @@ impl Shader {
     debug!("[{:?}] Loading shader...", &create_info.path);
     match Self::fetch_shader_bytecode(&create_info) {
       Ok(result) => {
+        trace!("[{:?}] Reflecting stages...", &create_info.path);
+        let reflection = ShaderReflection::new(
+          result.iter()
+            .map(|(stage, bytecode)| StageReflection::new(*stage, bytecode))
+            .collect::<Result<Vec<_>>>()?
+        );
         trace!("[{:?}] Building modules...", &create_info.path);
         let shader_modules = Self::build_shader_modules(context, &create_info, result)?;
         Ok(Self {
           device: context.device(),
           shader_modules,
+          reflection,
         })
       }
       Err(err) => Err(err),
@@ -71,6 +88,11 @@ impl Shader {
     &self.shader_modules
   }
 
+  /// What each stage's SPIR-V declares: interface variables, bindings, push constants and workgroup size.
+  pub fn reflection(&self) -> &ShaderReflection {
+    &self.reflection
+  }
+
   pub fn pipeline_shader_info(&self) -> Vec<vk::PipelineShaderStageCreateInfo> {
     let mut create_info: Vec<vk::PipelineShaderStageCreateInfo> = Default::default();
     for (stage, module) in self.shader_modules.iter() {
@@ -74,10 +74,7 @@ impl Source {
   }
 
   fn shader_cache_dir(path: &PathBuf) -> Result<PathBuf> {
-    let runtime_dir = env::current_exe()?
-      .parent().context("failed to access current_exe parent dir")?
-      .join("tmp/res/shaders");
-    let shader_cache_dir = runtime_dir.join(
+    let shader_cache_dir = super::cache_dir()?.join(
       path.file_stem().context("failed to read shader dir")?
     );
 
@@ -0,0 +1,377 @@
+use std::collections::BTreeMap;
+use anyhow::{Context, Result};
+use ash::vk;
+use spirv_reflect::types::{ReflectDecorationFlags, ReflectDescriptorType, ReflectFormat, ReflectInterfaceVariable};
+use tracing::warn;
+
+use crate::graphics::context::RenderContext;
+use crate::graphics::descriptor::{DescriptorBinding, DescriptorSetLayout};
+use crate::graphics::pipeline::layout::PipelineLayout;
+use crate::graphics::shader::stage::Stage;
+use crate::graphics::vertex::VertexLayout;
+
+/// A stage input or output at a user defined location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceVariable {
+  pub name: String,
+  /// The HLSL semantic, e.g. `POSITION0`. Empty for GLSL.
+  pub semantic: String,
+  pub location: u32,
+  /// Consecutive locations taken up, starting at `location`. Matrices take one per column, arrays one per element.
+  pub locations: u32,
+  /// `UNDEFINED` for types without a single attribute format, like matrices.
+  pub format: vk::Format,
+}
+
+impl InterfaceVariable {
+  fn covers(&self, location: u32) -> bool {
+    (self.location..self.location + self.locations).contains(&location)
+  }
+
+  fn describe(&self) -> String {
+    match (self.semantic.is_empty(), self.name.is_empty()) {
+      (false, _) => format!("`{}`", self.semantic),
+      (true, false) => format!("`{}`", self.name),
+      (true, true) => "input".to_owned(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectedBinding {
+  pub name: String,
+  pub set: u32,
+  pub binding: DescriptorBinding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushConstantBlock {
+  pub name: String,
+  pub offset: u32,
+  pub size: u32,
+}
+
+/// Everything a single compiled stage exposes to the pipeline.
+#[derive(Debug, Clone)]
+pub struct StageReflection {
+  pub stage: Stage,
+  pub inputs: Vec<InterfaceVariable>,
+  pub outputs: Vec<InterfaceVariable>,
+  pub bindings: Vec<ReflectedBinding>,
+  pub push_constants: Vec<PushConstantBlock>,
+  /// Only set for compute stages.
+  pub workgroup_size: Option<[u32; 3]>,
+}
+
+impl StageReflection {
+  pub fn new(stage: Stage, spirv: &[u8]) -> Result<Self> {
+    let module = spirv_reflect::ShaderModule::load_u8_data(spirv)
+      .map_err(|err| anyhow::anyhow!("failed to reflect {stage} stage: {err}"))?;
+    let entry_point = stage.into_entry_point_string();
+    let entry_point = Some(entry_point.as_str());
+    let stages = vk::ShaderStageFlags::from(stage);
+
+    let inputs = module.enumerate_input_variables(entry_point)
+      .map_err(|err| anyhow::anyhow!("failed to reflect {stage} stage inputs: {err}"))?;
+    let outputs = module.enumerate_output_variables(entry_point)
+      .map_err(|err| anyhow::anyhow!("failed to reflect {stage} stage outputs: {err}"))?;
+
+    let bindings = module.enumerate_descriptor_bindings(entry_point)
+      .map_err(|err| anyhow::anyhow!("failed to reflect {stage} stage descriptor bindings: {err}"))?
+      .into_iter()
+      .map(|b| Ok(ReflectedBinding {
+        set: b.set,
+        binding: DescriptorBinding {
+          binding: b.binding,
+          descriptor_type: descriptor_type(b.descriptor_type)
+            .with_context(|| format!("unsupported descriptor type for binding `{}`", b.name))?,
+          count: b.count.max(1),
+          stages,
+        },
+        name: b.name,
+      }))
+      .collect::<Result<Vec<_>>>()?;
+
+    let push_constants = module.enumerate_push_constant_blocks(entry_point)
+      .map_err(|err| anyhow::anyhow!("failed to reflect {stage} stage push constants: {err}"))?
+      .into_iter()
+      .map(|b| PushConstantBlock {
+        name: b.name,
+        offset: b.offset,
+        size: b.size,
+      })
+      .collect();
+
+    Ok(Self {
+      stage,
+      inputs: interface_variables(inputs),
+      outputs: interface_variables(outputs),
+      bindings,
+      push_constants,
+      workgroup_size: (stage == Stage::Compute).then(|| workgroup_size(spirv)).flatten(),
+    })
+  }
+}
+
+/// Reflection over all stages of a shader.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderReflection {
+  stages: Vec<StageReflection>,
+}
+
+/// Descriptor set layouts and the pipeline layout built from them, indexed by set number.
+pub struct ReflectedLayout {
+  pub set_layouts: Vec<DescriptorSetLayout>,
+  pub pipeline_layout: PipelineLayout,
+}
+
+impl ShaderReflection {
+  pub fn new(stages: impl IntoIterator<Item = StageReflection>) -> Self {
+    Self {
+      stages: stages.into_iter().collect(),
+    }
+  }
+
+  pub fn stages(&self) -> &[StageReflection] {
+    &self.stages
+  }
+
+  pub fn stage(&self, stage: Stage) -> Option<&StageReflection> {
+    self.stages.iter().find(|s| s.stage == stage)
+  }
+
+  /// Bindings of every stage merged by set, with the stage flags of shared bindings combined.
+  pub fn descriptor_sets(&self) -> Result<BTreeMap<u32, Vec<DescriptorBinding>>> {
+    let mut sets: BTreeMap<u32, Vec<DescriptorBinding>> = BTreeMap::new();
+    for reflected in self.stages.iter().flat_map(|s| &s.bindings) {
+      let bindings = sets.entry(reflected.set).or_default();
+      match bindings.iter_mut().find(|b| b.binding == reflected.binding.binding) {
+        Some(existing) => {
+          if existing.descriptor_type != reflected.binding.descriptor_type || existing.count != reflected.binding.count {
+            anyhow::bail!(
+              "Binding {} of set {} (`{}`) is declared as {:?} x{} and {:?} x{} by different stages",
+              reflected.binding.binding,
+              reflected.set,
+              reflected.name,
+              existing.descriptor_type,
+              existing.count,
+              reflected.binding.descriptor_type,
+              reflected.binding.count,
+            );
+          }
+          existing.stages |= reflected.binding.stages;
+        }
+        None => bindings.push(reflected.binding),
+      }
+    }
+    Ok(sets)
+  }
+
+  /// One range per stage, spanning all of that stage's push constant blocks.
+  pub fn push_constant_ranges(&self) -> Vec<vk::PushConstantRange> {
+    self.stages.iter()
+      .filter(|s| !s.push_constants.is_empty())
+      .map(|s| {
+        let start = s.push_constants.iter().map(|b| b.offset).min().unwrap_or(0);
+        let end = s.push_constants.iter().map(|b| b.offset + b.size).max().unwrap_or(0);
+        vk::PushConstantRange {
+          stage_flags: s.stage.into(),
+          offset: start,
+          size: end - start,
+        }
+      })
+      .collect()
+  }
+
+  /// Builds the layouts the shader's bindings and push constants require.
+  /// Sets skipped by the shader get empty layouts, so set numbers keep matching layout indices.
+  pub fn pipeline_layout(&self, context: &RenderContext) -> Result<ReflectedLayout> {
+    let sets = self.descriptor_sets()?;
+    let set_count = sets.keys().next_back().map_or(0, |last| last + 1);
+
+    let mut set_layouts = Vec::with_capacity(set_count as usize);
+    for set in 0..set_count {
+      let mut builder = DescriptorSetLayout::builder();
+      for binding in sets.get(&set).into_iter().flatten() {
+        builder = builder.with_binding(binding.binding, binding.descriptor_type, binding.count, binding.stages);
+      }
+      set_layouts.push(builder.build(context).with_context(|| format!("failed to create layout for set {set}"))?);
+    }
+
+    let mut builder = PipelineLayout::builder();
+    for set_layout in &set_layouts {
+      builder = builder.with_set_layout(set_layout);
+    }
+    for range in self.push_constant_ranges() {
+      builder = builder.with_push_constant_range(range.stage_flags, range.offset, range.size);
+    }
+
+    Ok(ReflectedLayout {
+      pipeline_layout: builder.build(context)?,
+      set_layouts,
+    })
+  }
+
+  /// Checks that every vertex shader input is fed by an attribute of a compatible type.
+  pub fn validate_vertex_layout(&self, vertex_layout: &VertexLayout) -> Result<()> {
+    let Some(vertex) = self.stage(Stage::Vertex) else {
+      return Ok(());
+    };
+
+    for input in &vertex.inputs {
+      let attribute_at = |location| vertex_layout.attributes().iter().find(|a| a.location == location);
+      let Some(attribute) = attribute_at(input.location) else {
+        anyhow::bail!(
+          "Vertex shader input {} at location {} has no matching attribute in the vertex layout",
+          input.describe(),
+          input.location,
+        );
+      };
+      if let Some(missing) = (input.location + 1..input.location + input.locations).find(|l| attribute_at(*l).is_none()) {
+        anyhow::bail!(
+          "Vertex shader input {} spans locations {} to {}, but the vertex layout has no attribute at location {missing}",
+          input.describe(),
+          input.location,
+          input.location + input.locations - 1,
+        );
+      }
+
+      if let (Some(expected), Some(provided)) = (numeric_class(input.format), numeric_class(attribute.format)) {
+        if expected != provided {
+          anyhow::bail!(
+            "Vertex shader input {} at location {} is {:?}, but the vertex layout provides {:?}",
+            input.describe(),
+            input.location,
+            input.format,
+            attribute.format,
+          );
+        }
+      }
+    }
+
+    for attribute in vertex_layout.attributes() {
+      if !vertex.inputs.iter().any(|i| i.covers(attribute.location)) {
+        warn!("Vertex attribute at location {} is not read by the vertex shader", attribute.location);
+      }
+    }
+
+    Ok(())
+  }
+}
+
+fn interface_variables(variables: Vec<ReflectInterfaceVariable>) -> Vec<InterfaceVariable> {
+  let mut variables: Vec<InterfaceVariable> = variables.into_iter()
+    .filter(|v| !v.decoration_flags.contains(ReflectDecorationFlags::BUILT_IN))
+    .map(|v| InterfaceVariable {
+      locations: v.numeric.matrix.column_count.max(1) * v.array.dims.iter().product::<u32>().max(1),
+      format: format(v.format),
+      name: v.name,
+      semantic: v.semantic,
+      location: v.location,
+    })
+    .collect();
+  variables.sort_by_key(|v| v.location);
+  variables
+}
+
+fn descriptor_type(descriptor_type: ReflectDescriptorType) -> Option<vk::DescriptorType> {
+  Some(match descriptor_type {
+    ReflectDescriptorType::Sampler => vk::DescriptorType::SAMPLER,
+    ReflectDescriptorType::CombinedImageSampler => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+    ReflectDescriptorType::SampledImage => vk::DescriptorType::SAMPLED_IMAGE,
+    ReflectDescriptorType::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+    ReflectDescriptorType::UniformTexelBuffer => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+    ReflectDescriptorType::StorageTexelBuffer => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+    ReflectDescriptorType::UniformBuffer => vk::DescriptorType::UNIFORM_BUFFER,
+    ReflectDescriptorType::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+    ReflectDescriptorType::UniformBufferDynamic => vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+    ReflectDescriptorType::StorageBufferDynamic => vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+    ReflectDescriptorType::InputAttachment => vk::DescriptorType::INPUT_ATTACHMENT,
+    ReflectDescriptorType::AccelerationStructureNV | ReflectDescriptorType::Undefined => return None,
+  })
+}
+
+fn format(format: ReflectFormat) -> vk::Format {
+  match format {
+    ReflectFormat::Undefined => vk::Format::UNDEFINED,
+    ReflectFormat::R32_UINT => vk::Format::R32_UINT,
+    ReflectFormat::R32_SINT => vk::Format::R32_SINT,
+    ReflectFormat::R32_SFLOAT => vk::Format::R32_SFLOAT,
+    ReflectFormat::R32G32_UINT => vk::Format::R32G32_UINT,
+    ReflectFormat::R32G32_SINT => vk::Format::R32G32_SINT,
+    ReflectFormat::R32G32_SFLOAT => vk::Format::R32G32_SFLOAT,
+    ReflectFormat::R32G32B32_UINT => vk::Format::R32G32B32_UINT,
+    ReflectFormat::R32G32B32_SINT => vk::Format::R32G32B32_SINT,
+    ReflectFormat::R32G32B32_SFLOAT => vk::Format::R32G32B32_SFLOAT,
+    ReflectFormat::R32G32B32A32_UINT => vk::Format::R32G32B32A32_UINT,
+    ReflectFormat::R32G32B32A32_SINT => vk::Format::R32G32B32A32_SINT,
+    ReflectFormat::R32G32B32A32_SFLOAT => vk::Format::R32G32B32A32_SFLOAT,
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum NumericClass {
+  Float,
+  Sint,
+  Uint,
+}
+
+/// The type a shader sees when reading an attribute of `format`. Normalized and scaled formats read as floats.
+/// `None` for formats this check doesn't know about.
+fn numeric_class(format: vk::Format) -> Option<NumericClass> {
+  use vk::Format as F;
+  Some(match format {
+    F::R8_UNORM | F::R8_SNORM | F::R8_USCALED | F::R8_SSCALED | F::R8_SRGB
+    | F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_USCALED | F::R8G8_SSCALED | F::R8G8_SRGB
+    | F::R8G8B8_UNORM | F::R8G8B8_SNORM | F::R8G8B8_USCALED | F::R8G8B8_SSCALED | F::R8G8B8_SRGB
+    | F::B8G8R8_UNORM | F::B8G8R8_SNORM | F::B8G8R8_SRGB
+    | F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_USCALED | F::R8G8B8A8_SSCALED | F::R8G8B8A8_SRGB
+    | F::B8G8R8A8_UNORM | F::B8G8R8A8_SNORM | F::B8G8R8A8_SRGB
+    | F::A2R10G10B10_UNORM_PACK32 | F::A2R10G10B10_SNORM_PACK32
+    | F::A2B10G10R10_UNORM_PACK32 | F::A2B10G10R10_SNORM_PACK32
+    | F::R16_UNORM | F::R16_SNORM | F::R16_USCALED | F::R16_SSCALED | F::R16_SFLOAT
+    | F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_USCALED | F::R16G16_SSCALED | F::R16G16_SFLOAT
+    | F::R16G16B16_UNORM | F::R16G16B16_SNORM | F::R16G16B16_SFLOAT
+    | F::R16G16B16A16_UNORM | F::R16G16B16A16_SNORM | F::R16G16B16A16_USCALED | F::R16G16B16A16_SSCALED
+    | F::R16G16B16A16_SFLOAT
+    | F::R32_SFLOAT | F::R32G32_SFLOAT | F::R32G32B32_SFLOAT | F::R32G32B32A32_SFLOAT
+    | F::R64_SFLOAT | F::R64G64_SFLOAT | F::R64G64B64_SFLOAT | F::R64G64B64A64_SFLOAT
+    | F::B10G11R11_UFLOAT_PACK32 => NumericClass::Float,
+    F::R8_SINT | F::R8G8_SINT | F::R8G8B8_SINT | F::B8G8R8_SINT | F::R8G8B8A8_SINT | F::B8G8R8A8_SINT
+    | F::A2R10G10B10_SINT_PACK32 | F::A2B10G10R10_SINT_PACK32
+    | F::R16_SINT | F::R16G16_SINT | F::R16G16B16_SINT | F::R16G16B16A16_SINT
+    | F::R32_SINT | F::R32G32_SINT | F::R32G32B32_SINT | F::R32G32B32A32_SINT
+    | F::R64_SINT | F::R64G64_SINT | F::R64G64B64_SINT | F::R64G64B64A64_SINT => NumericClass::Sint,
+    F::R8_UINT | F::R8G8_UINT | F::R8G8B8_UINT | F::B8G8R8_UINT | F::R8G8B8A8_UINT | F::B8G8R8A8_UINT
+    | F::A2R10G10B10_UINT_PACK32 | F::A2B10G10R10_UINT_PACK32
+    | F::R16_UINT | F::R16G16_UINT | F::R16G16B16_UINT | F::R16G16B16A16_UINT
+    | F::R32_UINT | F::R32G32_UINT | F::R32G32B32_UINT | F::R32G32B32A32_UINT
+    | F::R64_UINT | F::R64G64_UINT | F::R64G64B64_UINT | F::R64G64B64A64_UINT => NumericClass::Uint,
+    _ => return None,
+  })
+}
+
+/// spirv-reflect doesn't expose `LocalSize`, so the execution modes are read straight from the words.
+fn workgroup_size(spirv: &[u8]) -> Option<[u32; 3]> {
+  const OP_EXECUTION_MODE: u32 = 16;
+  const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+  const HEADER_WORDS: usize = 5;
+
+  let words: Vec<u32> = spirv.chunks_exact(4)
+    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+    .collect();
+
+  let mut i = HEADER_WORDS;
+  while i < words.len() {
+    let word_count = (words[i] >> 16) as usize;
+    let opcode = words[i] & 0xffff;
+    if word_count == 0 {
+      return None;
+    }
+    if opcode == OP_EXECUTION_MODE && word_count >= 6 && words.get(i + 2) == Some(&EXECUTION_MODE_LOCAL_SIZE) {
+      return words.get(i + 3..i + 6).map(|s| [s[0], s[1], s[2]]);
+    }
+    i += word_count;
+  }
+  None
+}
@@ -1,23 +1,281 @@
-use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use anyhow::{Context, Result};
+use ash::{self, vk, extensions::*};
+use tracing::{debug, trace};
 
-pub struct Swapchain {}
+use crate::graphics::context::RenderContext;
+use crate::graphics::window::{SwapchainSupport, Window};
+
+pub struct Swapchain {
+  device: Arc<ash::Device>,
+  loader: khr::Swapchain,
+  swapchain: vk::SwapchainKHR,
+  images: Vec<vk::Image>,
+  image_views: Vec<vk::ImageView>,
+  format: vk::SurfaceFormatKHR,
+  present_mode: vk::PresentModeKHR,
+  extent: vk::Extent2D,
+  out_of_date: bool,
+  generation: u64,
+}
+
+/// Source of `Swapchain::generation`. Vulkan handles of destroyed swapchains may be reused, so they can't identify one.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
 
 impl Swapchain {
-  const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+  pub const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+
+  /// Creates a swapchain for the window's surface, reusing resources of `old` if given.
+  /// Returns `None` while the window has no area to present to, e.g. when minimized.
+  pub fn new(context: &RenderContext, window: &Window, old: Option<&Swapchain>) -> Result<Option<Self>> {
+    let support = window.swapchain_support(context.physical_device())?;
+    let extent = Self::choose_extent(&support, window.size());
+    if extent.width == 0 || extent.height == 0 {
+      return Ok(None);
+    }
+
+    let format = Self::choose_surface_format(&support)?;
+    let present_mode = Self::choose_present_mode(&support);
+    let image_count = match support.capabilities.max_image_count {
+      0 => support.capabilities.min_image_count + 1,
+      max => (support.capabilities.min_image_count + 1).min(max),
+    };
+
+    let (graphics_family, present_family) = context.queue_families();
+    let queue_family_indices = [graphics_family, present_family];
+    let (sharing_mode, queue_family_index_count) = if graphics_family != present_family {
+      (vk::SharingMode::CONCURRENT, queue_family_indices.len() as u32)
+    } else {
+      (vk::SharingMode::EXCLUSIVE, 0)
+    };
+
+    let create_info = vk::SwapchainCreateInfoKHR {
+      surface: *window.surface(),
+      min_image_count: image_count,
+      image_format: format.format,
+      image_color_space: format.color_space,
+      image_extent: extent,
+      image_array_layers: 1,
+      image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | vk::ImageUsageFlags::TRANSFER_SRC
+        | vk::ImageUsageFlags::TRANSFER_DST,
+      image_sharing_mode: sharing_mode,
+      queue_family_index_count,
+      p_queue_family_indices: queue_family_indices.as_ptr(),
+      pre_transform: support.capabilities.current_transform,
+      composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+      present_mode,
+      clipped: vk::TRUE,
+      old_swapchain: old.map_or(vk::SwapchainKHR::null(), |old| old.swapchain),
+      ..Default::default()
+    };
+
+    let loader = khr::Swapchain::new(context.instance(), &context.device());
+    let swapchain = unsafe {
+      loader.create_swapchain(&create_info, None)
+    }.context("Failed to create swapchain")?;
+
+    let images = match unsafe {
+      loader.get_swapchain_images(swapchain)
+    }.context("Failed to get swapchain images") {
+      Ok(value) => value,
+      Err(err) => unsafe {
+        loader.destroy_swapchain(swapchain, None);
+        Err(err)?
+      }
+    };
+
+    let mut image_views = Vec::with_capacity(images.len());
+    for image in &images {
+      match Self::create_image_view(context, *image, format.format) {
+        Ok(view) => image_views.push(view),
+        Err(err) => unsafe {
+          for view in image_views.drain(..) {
+            context.device().destroy_image_view(view, None);
+          }
+          loader.destroy_swapchain(swapchain, None);
+          Err(err)?
+        }
+      }
+    }
+
+    debug!(
+      "Created swapchain: [{}x{}, {:?}, {:?}, {} images]",
+      extent.width, extent.height, format.format, present_mode, images.len()
+    );
+
+    Ok(Some(Self {
+      device: context.device(),
+      loader,
+      swapchain,
+      images,
+      image_views,
+      format,
+      present_mode,
+      extent,
+      out_of_date: false,
+      generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+    }))
+  }
+
+  pub fn handle(&self) -> vk::SwapchainKHR {
+    self.swapchain
+  }
+
+  /// Unique for every swapchain created, including recreations of the same window's swapchain.
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  pub fn images(&self) -> &[vk::Image] {
+    &self.images
+  }
 
-  pub fn new() -> Result<Self> {
-    Ok(Self {})
+  pub fn image_views(&self) -> &[vk::ImageView] {
+    &self.image_views
+  }
+
+  pub fn format(&self) -> vk::SurfaceFormatKHR {
+    self.format
+  }
+
+  pub fn present_mode(&self) -> vk::PresentModeKHR {
+    self.present_mode
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    self.extent
+  }
+
+  /// Whether the swapchain no longer matches its surface and must be recreated before use.
+  pub fn out_of_date(&self) -> bool {
+    self.out_of_date
+  }
+
+  pub fn mark_out_of_date(&mut self) {
+    self.out_of_date = true;
+  }
+
+  /// Returns `None` and marks the swapchain out of date if it no longer matches the surface.
+  pub fn acquire_next_image(&mut self, semaphore: vk::Semaphore) -> Result<Option<u32>> {
+    match unsafe {
+      self.loader.acquire_next_image(self.swapchain, u64::MAX, semaphore, vk::Fence::null())
+    } {
+      Ok((image_index, suboptimal)) => {
+        if suboptimal {
+          self.out_of_date = true;
+        }
+        Ok(Some(image_index))
+      }
+      Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+        self.out_of_date = true;
+        Ok(None)
+      }
+      Err(err) => Err(err).context("Failed to acquire swapchain image"),
+    }
+  }
+
+  pub fn present(&mut self, queue: vk::Queue, image_index: u32, wait_semaphores: &[vk::Semaphore]) -> Result<()> {
+    let present_info = vk::PresentInfoKHR {
+      wait_semaphore_count: wait_semaphores.len() as u32,
+      p_wait_semaphores: wait_semaphores.as_ptr(),
+      swapchain_count: 1,
+      p_swapchains: &self.swapchain,
+      p_image_indices: &image_index,
+      ..Default::default()
+    };
+
+    match unsafe {
+      self.loader.queue_present(queue, &present_info)
+    } {
+      Ok(suboptimal) => {
+        if suboptimal {
+          self.out_of_date = true;
+        }
+        Ok(())
+      }
+      Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+        self.out_of_date = true;
+        Ok(())
+      }
+      Err(err) => Err(err).context("Failed to present swapchain image"),
+    }
   }
 }
 
 impl Swapchain {
-  unsafe fn free(&mut self) {}
+  unsafe fn free(&mut self) {
+    for view in self.image_views.drain(..) {
+      self.device.destroy_image_view(view, None);
+    }
+    self.loader.destroy_swapchain(self.swapchain, None);
+  }
+
+  fn choose_surface_format(support: &SwapchainSupport) -> Result<vk::SurfaceFormatKHR> {
+    support.formats.iter()
+      .find(|f| {
+        f.format == vk::Format::B8G8R8A8_SRGB && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+      })
+      .or_else(|| support.formats.first())
+      .copied()
+      .context("Surface reports no formats")
+  }
+
+  fn choose_present_mode(support: &SwapchainSupport) -> vk::PresentModeKHR {
+    if support.present_modes.contains(&vk::PresentModeKHR::MAILBOX) {
+      vk::PresentModeKHR::MAILBOX
+    } else {
+      // always supported
+      vk::PresentModeKHR::FIFO
+    }
+  }
+
+  /// The extent a swapchain for the window would have right now, zero sized while minimized.
+  pub fn surface_extent(context: &RenderContext, window: &Window) -> Result<vk::Extent2D> {
+    let support = window.swapchain_support(context.physical_device())?;
+    Ok(Self::choose_extent(&support, window.size()))
+  }
+
+  fn choose_extent(support: &SwapchainSupport, (width, height): (u32, u32)) -> vk::Extent2D {
+    let capabilities = &support.capabilities;
+    if capabilities.current_extent.width != u32::MAX {
+      capabilities.current_extent
+    } else {
+      vk::Extent2D {
+        width: width.clamp(capabilities.min_image_extent.width, capabilities.max_image_extent.width),
+        height: height.clamp(capabilities.min_image_extent.height, capabilities.max_image_extent.height),
+      }
+    }
+  }
+
+  fn create_image_view(context: &RenderContext, image: vk::Image, format: vk::Format) -> Result<vk::ImageView> {
+    let create_info = vk::ImageViewCreateInfo {
+      image,
+      view_type: vk::ImageViewType::TYPE_2D,
+      format,
+      components: vk::ComponentMapping::default(),
+      subresource_range: vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+      },
+      ..Default::default()
+    };
+
+    unsafe {
+      context.device().create_image_view(&create_info, None)
+    }.context("Failed to create swapchain image view")
+  }
 }
 
 impl Drop for Swapchain {
   fn drop(&mut self) {
+    trace!("Dropping Swapchain...");
     unsafe {
       self.free();
     }
   }
-}
\ No newline at end of file
+}
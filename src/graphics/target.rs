@@ -0,0 +1,125 @@
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::{self, vk};
+
+use crate::graphics::context::RenderContext;
+use crate::graphics::image::Image;
+use crate::graphics::readback::{read_image, RgbaImage};
+
+/// A color image rendered to with `Graphics::render_offscreen` instead of a swapchain.
+pub struct RenderTarget {
+  device: Arc<ash::Device>,
+  image: Image,
+  image_view: vk::ImageView,
+  format: vk::Format,
+  extent: vk::Extent2D,
+  layout: vk::ImageLayout,
+}
+
+impl RenderTarget {
+  pub const DEFAULT_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
+  pub fn new(context: &RenderContext, width: u32, height: u32) -> Result<Self> {
+    Self::with_format(context, width, height, Self::DEFAULT_FORMAT)
+  }
+
+  pub fn with_format(context: &RenderContext, width: u32, height: u32, format: vk::Format) -> Result<Self> {
+    let image_info = vk::ImageCreateInfo {
+      image_type: vk::ImageType::TYPE_2D,
+      format,
+      extent: vk::Extent3D {
+        width,
+        height,
+        depth: 1,
+      },
+      mip_levels: 1,
+      array_layers: 1,
+      samples: vk::SampleCountFlags::TYPE_1,
+      tiling: vk::ImageTiling::OPTIMAL,
+      usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | vk::ImageUsageFlags::TRANSFER_SRC
+        | vk::ImageUsageFlags::TRANSFER_DST
+        | vk::ImageUsageFlags::SAMPLED,
+      sharing_mode: vk::SharingMode::EXCLUSIVE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      ..Default::default()
+    };
+
+    let image = Image::new(context, image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+    let view_info = vk::ImageViewCreateInfo {
+      image: image.image,
+      view_type: vk::ImageViewType::TYPE_2D,
+      format,
+      subresource_range: vk::ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+      },
+      ..Default::default()
+    };
+
+    let image_view = unsafe {
+      context.device().create_image_view(&view_info, None)
+    }.context("Failed to create render target image view")?;
+
+    Ok(Self {
+      device: context.device(),
+      image,
+      image_view,
+      format,
+      extent: vk::Extent2D { width, height },
+      layout: vk::ImageLayout::UNDEFINED,
+    })
+  }
+
+  pub fn image(&self) -> &Image {
+    &self.image
+  }
+
+  pub fn image_view(&self) -> vk::ImageView {
+    self.image_view
+  }
+
+  pub fn format(&self) -> vk::Format {
+    self.format
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    self.extent
+  }
+
+  /// Layout the image was left in by the last operation on it.
+  pub fn layout(&self) -> vk::ImageLayout {
+    self.layout
+  }
+
+  pub(crate) fn set_layout(&mut self, layout: vk::ImageLayout) {
+    self.layout = layout;
+  }
+
+  /// Copies the target's contents into host memory as RGBA. Blocks until the gpu is done.
+  /// Only 8 bit RGBA and BGRA formats can be read back.
+  pub fn read_pixels(&self, context: &RenderContext) -> Result<RgbaImage> {
+    if self.layout == vk::ImageLayout::UNDEFINED {
+      anyhow::bail!("Render target has not been rendered to");
+    }
+    read_image(context, self.image.image, self.layout, self.extent, self.format)
+  }
+}
+
+impl RenderTarget {
+  unsafe fn free(&mut self) {
+    self.device.destroy_image_view(self.image_view, None);
+  }
+}
+
+impl Drop for RenderTarget {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
@@ -0,0 +1,340 @@
+use std::path::Path;
+use std::sync::Arc;
+use anyhow::{Context, Result};
+use ash::vk;
+use tracing::{trace, warn};
+
+use crate::graphics::buffer::Buffer;
+use crate::graphics::context::RenderContext;
+use crate::graphics::image::{Image, transition_layout};
+
+use self::sampler::{Sampler, SamplerDesc};
+
+pub mod sampler;
+
+/// How a texture is uploaded and sampled.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TextureOptions {
+  /// Whether 8 bit color is sRGB encoded, as in most color maps. Normal or data maps should disable this.
+  pub srgb: bool,
+  pub mipmaps: bool,
+  pub sampler: SamplerDesc,
+}
+
+impl TextureOptions {
+  pub fn with_srgb(mut self, srgb: bool) -> Self {
+    self.srgb = srgb;
+    self
+  }
+
+  pub fn with_mipmaps(mut self, mipmaps: bool) -> Self {
+    self.mipmaps = mipmaps;
+    self
+  }
+
+  pub fn with_sampler(mut self, sampler: SamplerDesc) -> Self {
+    self.sampler = sampler;
+    self
+  }
+}
+
+impl Default for TextureOptions {
+  fn default() -> Self {
+    Self {
+      srgb: true,
+      mipmaps: true,
+      sampler: SamplerDesc::default(),
+    }
+  }
+}
+
+/// A sampled 2D image with its view and sampler, left in `SHADER_READ_ONLY_OPTIMAL`.
+pub struct Texture {
+  device: Arc<ash::Device>,
+  image: Image,
+  view: vk::ImageView,
+  sampler: Arc<Sampler>,
+  format: vk::Format,
+  mip_levels: u32,
+}
+
+impl Texture {
+  /// Loads a PNG, JPEG or Radiance HDR file. HDR files keep their full range as 32 bit floats.
+  pub fn load(context: &RenderContext, path: impl AsRef<Path>, options: TextureOptions) -> Result<Self> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).with_context(|| format!("Failed to read texture {path:?}"))?;
+    Self::from_memory(context, &bytes, options).with_context(|| format!("Failed to load texture {path:?}"))
+  }
+
+  /// Decodes an encoded PNG, JPEG or Radiance HDR image.
+  pub fn from_memory(context: &RenderContext, bytes: &[u8], options: TextureOptions) -> Result<Self> {
+    let decoded = image::load_from_memory(bytes).context("Failed to decode image")?;
+    let (width, height) = (decoded.width(), decoded.height());
+
+    match decoded {
+      image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => {
+        Self::from_rgba32f(context, width, height, decoded.to_rgba32f().as_raw(), options)
+      }
+      _ => Self::from_rgba8(context, width, height, decoded.to_rgba8().as_raw(), options),
+    }
+  }
+
+  pub fn from_rgba8(context: &RenderContext, width: u32, height: u32, pixels: &[u8], options: TextureOptions) -> Result<Self> {
+    let format = if options.srgb { vk::Format::R8G8B8A8_SRGB } else { vk::Format::R8G8B8A8_UNORM };
+    Self::from_pixels(context, width, height, format, pixels, options)
+  }
+
+  pub fn from_rgba32f(context: &RenderContext, width: u32, height: u32, pixels: &[f32], options: TextureOptions) -> Result<Self> {
+    Self::from_pixels(context, width, height, vk::Format::R32G32B32A32_SFLOAT, bytemuck::cast_slice(pixels), options)
+  }
+
+  /// `pixels` must be tightly packed rows of `format`.
+  pub fn from_pixels(
+    context: &RenderContext,
+    width: u32,
+    height: u32,
+    format: vk::Format,
+    pixels: &[u8],
+    options: TextureOptions,
+  ) -> Result<Self> {
+    if width == 0 || height == 0 {
+      anyhow::bail!("Texture must not be empty");
+    }
+    let expected = width as usize * height as usize * format_size(format)?;
+    if pixels.len() != expected {
+      anyhow::bail!("Texture data is {} bytes, but {width}x{height} {format:?} needs {expected}", pixels.len());
+    }
+
+    let blit = Self::blit_filter(context, format);
+    let mip_levels = match (options.mipmaps, blit) {
+      (true, Some(_)) => 32 - width.max(height).leading_zeros(),
+      (true, None) => {
+        warn!("{format:?} doesn't support blitting, so the texture gets no mipmaps");
+        1
+      }
+      (false, _) => 1,
+    };
+
+    let image_info = vk::ImageCreateInfo {
+      image_type: vk::ImageType::TYPE_2D,
+      format,
+      extent: vk::Extent3D {
+        width,
+        height,
+        depth: 1,
+      },
+      mip_levels,
+      array_layers: 1,
+      samples: vk::SampleCountFlags::TYPE_1,
+      tiling: vk::ImageTiling::OPTIMAL,
+      usage: vk::ImageUsageFlags::SAMPLED
+        | vk::ImageUsageFlags::TRANSFER_DST
+        | vk::ImageUsageFlags::TRANSFER_SRC,
+      sharing_mode: vk::SharingMode::EXCLUSIVE,
+      initial_layout: vk::ImageLayout::UNDEFINED,
+      ..Default::default()
+    };
+    let image = Image::new(context, image_info, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+
+    let staging = Buffer::new_staging(context, pixels)?;
+
+    let device = context.device();
+    let all_levels = Self::color_range(0, mip_levels);
+    let command_buffer = context.begin_single_time_commands()?;
+    transition_layout(
+      &device,
+      command_buffer,
+      image.image,
+      all_levels,
+      vk::ImageLayout::UNDEFINED,
+      vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+    staging.record_copy_to_image(command_buffer, &image);
+    match blit.filter(|_| mip_levels > 1) {
+      Some(filter) => Self::record_mipmaps(&device, command_buffer, &image, mip_levels, filter),
+      None => transition_layout(
+        &device,
+        command_buffer,
+        image.image,
+        all_levels,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      ),
+    }
+    context.end_single_time_commands(command_buffer).context("Failed to upload texture")?;
+
+    let view_info = vk::ImageViewCreateInfo {
+      image: image.image,
+      view_type: vk::ImageViewType::TYPE_2D,
+      format,
+      subresource_range: all_levels,
+      ..Default::default()
+    };
+    let view = unsafe {
+      device.create_image_view(&view_info, None)
+    }.context("Failed to create texture image view")?;
+
+    let sampler = match context.sampler(options.sampler) {
+      Ok(sampler) => sampler,
+      Err(err) => unsafe {
+        device.destroy_image_view(view, None);
+        Err(err)?
+      }
+    };
+
+    trace!("Created {width}x{height} {format:?} texture with {mip_levels} mip levels");
+
+    Ok(Self {
+      device,
+      image,
+      view,
+      sampler,
+      format,
+      mip_levels,
+    })
+  }
+
+  pub fn image(&self) -> &Image {
+    &self.image
+  }
+
+  pub fn view(&self) -> vk::ImageView {
+    self.view
+  }
+
+  pub fn sampler(&self) -> &Sampler {
+    &self.sampler
+  }
+
+  pub fn format(&self) -> vk::Format {
+    self.format
+  }
+
+  pub fn extent(&self) -> vk::Extent2D {
+    vk::Extent2D {
+      width: self.image.extent.width,
+      height: self.image.extent.height,
+    }
+  }
+
+  pub fn mip_levels(&self) -> u32 {
+    self.mip_levels
+  }
+
+  /// The filter usable to blit between mip levels, or `None` if the format can't be blitted.
+  fn blit_filter(context: &RenderContext, format: vk::Format) -> Option<vk::Filter> {
+    let features = context.format_properties(format).optimal_tiling_features;
+    if !features.contains(vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST) {
+      None
+    } else if features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR) {
+      Some(vk::Filter::LINEAR)
+    } else {
+      Some(vk::Filter::NEAREST)
+    }
+  }
+
+  /// Fills every level from the one above by halving blits, starting with level 0 in `TRANSFER_DST_OPTIMAL`.
+  /// Leaves all levels in `SHADER_READ_ONLY_OPTIMAL`.
+  fn record_mipmaps(device: &ash::Device, command_buffer: vk::CommandBuffer, image: &Image, mip_levels: u32, filter: vk::Filter) {
+    let mut width = image.extent.width as i32;
+    let mut height = image.extent.height as i32;
+
+    for level in 1..mip_levels {
+      let source = Self::color_range(level - 1, 1);
+      transition_layout(
+        device,
+        command_buffer,
+        image.image,
+        source,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+      );
+
+      let next_width = (width / 2).max(1);
+      let next_height = (height / 2).max(1);
+      let blit = vk::ImageBlit {
+        src_subresource: Self::color_layers(level - 1),
+        src_offsets: [vk::Offset3D::default(), vk::Offset3D { x: width, y: height, z: 1 }],
+        dst_subresource: Self::color_layers(level),
+        dst_offsets: [vk::Offset3D::default(), vk::Offset3D { x: next_width, y: next_height, z: 1 }],
+      };
+      unsafe {
+        device.cmd_blit_image(
+          command_buffer,
+          image.image,
+          vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+          image.image,
+          vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+          &[blit],
+          filter,
+        );
+      }
+
+      transition_layout(
+        device,
+        command_buffer,
+        image.image,
+        source,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+      );
+
+      width = next_width;
+      height = next_height;
+    }
+
+    // the last level is only ever blitted into
+    transition_layout(
+      device,
+      command_buffer,
+      image.image,
+      Self::color_range(mip_levels - 1, 1),
+      vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+      vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+  }
+
+  fn color_range(base_mip_level: u32, level_count: u32) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      base_mip_level,
+      level_count,
+      base_array_layer: 0,
+      layer_count: 1,
+    }
+  }
+
+  fn color_layers(mip_level: u32) -> vk::ImageSubresourceLayers {
+    vk::ImageSubresourceLayers {
+      aspect_mask: vk::ImageAspectFlags::COLOR,
+      mip_level,
+      base_array_layer: 0,
+      layer_count: 1,
+    }
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_image_view(self.view, None);
+  }
+}
+
+impl Drop for Texture {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// Bytes per texel of the uncompressed formats textures are created with.
+fn format_size(format: vk::Format) -> Result<usize> {
+  Ok(match format {
+    vk::Format::R8_UNORM | vk::Format::R8_SRGB => 1,
+    vk::Format::R8G8_UNORM | vk::Format::R8G8_SRGB => 2,
+    vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB
+    | vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => 4,
+    vk::Format::R16G16B16A16_SFLOAT => 8,
+    vk::Format::R32G32B32A32_SFLOAT => 16,
+    _ => anyhow::bail!("Unsupported texture format {format:?}"),
+  })
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use anyhow::{Context, Result};
+use ash::vk;
+use tracing::trace;
+
+/// Filtering and addressing of a sampler. Samplers with equal descriptions are shared through the `SamplerCache`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+  pub mag_filter: vk::Filter,
+  pub min_filter: vk::Filter,
+  pub mipmap_mode: vk::SamplerMipmapMode,
+  pub address_mode_u: vk::SamplerAddressMode,
+  pub address_mode_v: vk::SamplerAddressMode,
+  pub address_mode_w: vk::SamplerAddressMode,
+  pub border_color: vk::BorderColor,
+  /// Clamped to the device's limit. `None` disables anisotropic filtering, as does a device without `SamplerAnisotropy`.
+  pub max_anisotropy: Option<u32>,
+  /// The number of mip levels that may be sampled, all of them when `None`.
+  pub max_lod: Option<u32>,
+}
+
+impl SamplerDesc {
+  /// Trilinear filtering with repeating addressing.
+  pub fn linear() -> Self {
+    Self {
+      mag_filter: vk::Filter::LINEAR,
+      min_filter: vk::Filter::LINEAR,
+      mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+      address_mode_u: vk::SamplerAddressMode::REPEAT,
+      address_mode_v: vk::SamplerAddressMode::REPEAT,
+      address_mode_w: vk::SamplerAddressMode::REPEAT,
+      border_color: vk::BorderColor::FLOAT_TRANSPARENT_BLACK,
+      max_anisotropy: None,
+      max_lod: None,
+    }
+  }
+
+  /// Unfiltered, e.g. for pixel art or lookup tables.
+  pub fn nearest() -> Self {
+    Self {
+      mag_filter: vk::Filter::NEAREST,
+      min_filter: vk::Filter::NEAREST,
+      mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+      ..Self::linear()
+    }
+  }
+
+  pub fn with_filter(mut self, mag_filter: vk::Filter, min_filter: vk::Filter) -> Self {
+    self.mag_filter = mag_filter;
+    self.min_filter = min_filter;
+    self
+  }
+
+  pub fn with_mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+    self.mipmap_mode = mipmap_mode;
+    self
+  }
+
+  /// Uses `address_mode` along every axis.
+  pub fn with_address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+    self.address_mode_u = address_mode;
+    self.address_mode_v = address_mode;
+    self.address_mode_w = address_mode;
+    self
+  }
+
+  pub fn with_border_color(mut self, border_color: vk::BorderColor) -> Self {
+    self.border_color = border_color;
+    self
+  }
+
+  pub fn with_anisotropy(mut self, max_anisotropy: u32) -> Self {
+    self.max_anisotropy = Some(max_anisotropy);
+    self
+  }
+
+  pub fn with_max_lod(mut self, max_lod: u32) -> Self {
+    self.max_lod = Some(max_lod);
+    self
+  }
+}
+
+impl Default for SamplerDesc {
+  /// Trilinear filtering with 16x anisotropy and repeating addressing.
+  fn default() -> Self {
+    Self::linear().with_anisotropy(16)
+  }
+}
+
+pub struct Sampler {
+  device: Arc<ash::Device>,
+  sampler: vk::Sampler,
+  desc: SamplerDesc,
+}
+
+impl Sampler {
+  pub fn handle(&self) -> vk::Sampler {
+    self.sampler
+  }
+
+  pub fn desc(&self) -> &SamplerDesc {
+    &self.desc
+  }
+
+  unsafe fn free(&mut self) {
+    self.device.destroy_sampler(self.sampler, None);
+  }
+}
+
+impl Drop for Sampler {
+  fn drop(&mut self) {
+    unsafe {
+      self.free();
+    }
+  }
+}
+
+/// Hands out one shared sampler per distinct `SamplerDesc`.
+pub struct SamplerCache {
+  device: Arc<ash::Device>,
+  /// `None` when the device doesn't have anisotropic filtering enabled.
+  max_anisotropy: Option<f32>,
+  samplers: Mutex<HashMap<SamplerDesc, Arc<Sampler>>>,
+}
+
+impl SamplerCache {
+  pub(crate) fn new(device: Arc<ash::Device>, max_anisotropy: Option<f32>) -> Self {
+    Self {
+      device,
+      max_anisotropy,
+      samplers: Default::default(),
+    }
+  }
+
+  pub fn get(&self, desc: SamplerDesc) -> Result<Arc<Sampler>> {
+    let mut samplers = self.samplers.lock().unwrap();
+    if let Some(sampler) = samplers.get(&desc) {
+      return Ok(sampler.clone());
+    }
+
+    let anisotropy = desc.max_anisotropy.zip(self.max_anisotropy)
+      .map(|(anisotropy, max)| (anisotropy as f32).clamp(1.0, max.max(1.0)));
+    let create_info = vk::SamplerCreateInfo {
+      mag_filter: desc.mag_filter,
+      min_filter: desc.min_filter,
+      mipmap_mode: desc.mipmap_mode,
+      address_mode_u: desc.address_mode_u,
+      address_mode_v: desc.address_mode_v,
+      address_mode_w: desc.address_mode_w,
+      mip_lod_bias: 0.0,
+      anisotropy_enable: anisotropy.is_some().into(),
+      max_anisotropy: anisotropy.unwrap_or(1.0),
+      compare_enable: vk::FALSE,
+      compare_op: vk::CompareOp::ALWAYS,
+      min_lod: 0.0,
+      max_lod: desc.max_lod.map_or(vk::LOD_CLAMP_NONE, |l| l as f32),
+      border_color: desc.border_color,
+      unnormalized_coordinates: vk::FALSE,
+      ..Default::default()
+    };
+
+    let sampler = unsafe {
+      self.device.create_sampler(&create_info, None)
+    }.context("Failed to create sampler")?;
+    trace!("Created sampler: {desc:?}");
+
+    let sampler = Arc::new(Sampler {
+      device: self.device.clone(),
+      sampler,
+      desc,
+    });
+    samplers.insert(desc, sampler.clone());
+    Ok(sampler)
+  }
+
+  /// The number of distinct samplers created so far.
+  pub fn len(&self) -> usize {
+    self.samplers.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Drops the cache's references. Samplers still held elsewhere stay alive.
+  pub fn clear(&self) {
+    self.samplers.lock().unwrap().clear();
+  }
+}
@@ -0,0 +1,185 @@
+pub use ash::vk;
+use bytemuck::Pod;
+
+pub use koyote_derive::Vertex;
+
+/// A `#[repr(C)]` struct fed to the vertex shader, one field per input location.
+///
+/// Usually derived:
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+/// struct ColorVertex {
+///   position: [f32; 4], // location 0
+///   color: [f32; 4],    // location 1
+/// }
+/// ```
+pub trait Vertex: Pod {
+  /// Attribute descriptions for each field, starting at `first_location`.
+  fn attributes(binding: u32, first_location: u32) -> Vec<vk::VertexInputAttributeDescription>;
+
+  fn binding(binding: u32, input_rate: vk::VertexInputRate) -> vk::VertexInputBindingDescription {
+    vk::VertexInputBindingDescription {
+      binding,
+      stride: std::mem::size_of::<Self>() as u32,
+      input_rate,
+    }
+  }
+}
+
+/// Field types usable in a derived `Vertex`, with the format their data is read as.
+pub trait VertexAttribute {
+  const FORMAT: vk::Format;
+  /// Matrices take up one location per column.
+  const LOCATIONS: u32 = 1;
+}
+
+macro_rules! vertex_attribute {
+  ($($ty:ty => $format:ident $(* $locations:literal)?),* $(,)?) => {
+    $(
+      impl VertexAttribute for $ty {
+        const FORMAT: vk::Format = vk::Format::$format;
+        $(const LOCATIONS: u32 = $locations;)?
+      }
+    )*
+  };
+}
+
+vertex_attribute! {
+  f32 => R32_SFLOAT,
+  [f32; 2] => R32G32_SFLOAT,
+  [f32; 3] => R32G32B32_SFLOAT,
+  [f32; 4] => R32G32B32A32_SFLOAT,
+  u32 => R32_UINT,
+  [u32; 2] => R32G32_UINT,
+  [u32; 3] => R32G32B32_UINT,
+  [u32; 4] => R32G32B32A32_UINT,
+  i32 => R32_SINT,
+  [i32; 2] => R32G32_SINT,
+  [i32; 3] => R32G32B32_SINT,
+  [i32; 4] => R32G32B32A32_SINT,
+  [u8; 4] => R8G8B8A8_UNORM,
+  [[f32; 3]; 3] => R32G32B32_SFLOAT * 3,
+  [[f32; 4]; 4] => R32G32B32A32_SFLOAT * 4,
+}
+
+/// Used by `#[derive(Vertex)]`.
+#[doc(hidden)]
+pub fn push_attributes<T: VertexAttribute>(
+  attributes: &mut Vec<vk::VertexInputAttributeDescription>,
+  binding: u32,
+  location: &mut u32,
+  offset: u32,
+) {
+  let stride = std::mem::size_of::<T>() as u32 / T::LOCATIONS;
+  for i in 0..T::LOCATIONS {
+    attributes.push(vk::VertexInputAttributeDescription {
+      location: *location,
+      binding,
+      format: T::FORMAT,
+      offset: offset + i * stride,
+    });
+    *location += 1;
+  }
+}
+
+/// Used by `#[derive(Vertex)]` for fields with an explicit format, which may be of any type.
+#[doc(hidden)]
+pub fn push_attribute_with_format(
+  attributes: &mut Vec<vk::VertexInputAttributeDescription>,
+  binding: u32,
+  location: &mut u32,
+  offset: u32,
+  format: vk::Format,
+) {
+  attributes.push(vk::VertexInputAttributeDescription {
+    location: *location,
+    binding,
+    format,
+    offset,
+  });
+  *location += 1;
+}
+
+/// Vertex buffer bindings and their attributes, as consumed by a pipeline.
+///
+/// Each added vertex type gets the next binding index, with its attributes following on from the
+/// previous binding's locations.
+#[derive(Debug, Default, Clone)]
+pub struct VertexLayout {
+  bindings: Vec<vk::VertexInputBindingDescription>,
+  attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexLayout {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Adds a binding advanced once per vertex.
+  pub fn with_vertex<V: Vertex>(self) -> Self {
+    self.with_binding::<V>(vk::VertexInputRate::VERTEX)
+  }
+
+  /// Adds a binding advanced once per instance.
+  pub fn with_instance<V: Vertex>(self) -> Self {
+    self.with_binding::<V>(vk::VertexInputRate::INSTANCE)
+  }
+
+  pub fn with_binding<V: Vertex>(mut self, input_rate: vk::VertexInputRate) -> Self {
+    let binding = self.bindings.len() as u32;
+    let first_location = self.attributes.iter().map(|a| a.location + 1).max().unwrap_or(0);
+    self.bindings.push(V::binding(binding, input_rate));
+    self.attributes.extend(V::attributes(binding, first_location));
+    self
+  }
+
+  pub fn bindings(&self) -> &[vk::VertexInputBindingDescription] {
+    &self.bindings
+  }
+
+  pub fn attributes(&self) -> &[vk::VertexInputAttributeDescription] {
+    &self.attributes
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.bindings.is_empty()
+  }
+}
+
+impl PartialEq for VertexLayout {
+  fn eq(&self, other: &Self) -> bool {
+    let binding = |b: &vk::VertexInputBindingDescription| (b.binding, b.stride, b.input_rate);
+    let attribute = |a: &vk::VertexInputAttributeDescription| (a.location, a.binding, a.format, a.offset);
+    self.bindings.iter().map(binding).eq(other.bindings.iter().map(binding))
+      && self.attributes.iter().map(attribute).eq(other.attributes.iter().map(attribute))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bytemuck::{Pod, Zeroable};
+
+  use super::*;
+
+  #[repr(C)]
+  #[derive(Copy, Clone, Vertex)]
+  struct Pair<T: VertexAttribute + Pod> {
+    first: T,
+    second: T,
+  }
+
+  // both fields share a type, so there is no padding
+  unsafe impl<T: VertexAttribute + Pod> Zeroable for Pair<T> {}
+  unsafe impl<T: VertexAttribute + Pod> Pod for Pair<T> {}
+
+  #[test]
+  fn derives_generic_vertices() {
+    let attributes = Pair::<[f32; 2]>::attributes(1, 3);
+    let attributes: Vec<_> = attributes.iter().map(|a| (a.binding, a.location, a.format, a.offset)).collect();
+    assert_eq!(attributes, [
+      (1, 3, vk::Format::R32G32_SFLOAT, 0),
+      (1, 4, vk::Format::R32G32_SFLOAT, 8),
+    ]);
+  }
+}
@@ -1,38 +1,138 @@
 use anyhow::{Context, Result};
-use tracing::{trace};
+use tracing::{trace, warn};
 use winit::{
-  event_loop::EventLoop,
+  event_loop::EventLoopWindowTarget,
   dpi::{
     LogicalSize,
     PhysicalPosition,
+    PhysicalSize,
   },
+  window::Fullscreen,
 };
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use ash::{self, vk, extensions::*};
 
+use crate::graphics::context::RenderContext;
+use crate::graphics::monitor::{Monitor, MonitorSelection};
+use crate::graphics::placement::WindowPlacement;
+use crate::graphics::swapchain::Swapchain;
+
+pub use winit::window::WindowId;
+
 pub struct Window {
   window: winit::window::Window,
   surface: Option<Box<vk::SurfaceKHR>>,
   surface_loader: Option<khr::Surface>,
+  swapchain: Option<Swapchain>,
+  /// Size and position while neither maximized nor fullscreen, which is what gets saved and restored.
+  restored_size: (u32, u32),
+  restored_position: Option<(i32, i32)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowCreateInfo {
+  pub title: String,
+  pub width: u32,
+  pub height: u32,
+  pub centered: bool,
+  pub monitor: Option<MonitorSelection>,
+  /// Saved placement to restore. Ignored if its monitor is no longer connected.
+  /// Without a saved position the window is placed as if there were no placement.
+  pub placement: Option<WindowPlacement>,
+}
+
+impl Default for WindowCreateInfo {
+  fn default() -> Self {
+    Self {
+      title: "Koyote".to_owned(),
+      width: 800,
+      height: 500,
+      centered: false,
+      monitor: None,
+      placement: None,
+    }
+  }
 }
 
 impl Window {
-  pub fn new(event_loop: &EventLoop<()>, title: &'static str, width: u32, height: u32) -> Result<Self> {
-    let window = winit::window::WindowBuilder::new()
-      .with_title(title)
-      .with_inner_size(LogicalSize::new(width, height))
-      .with_visible(false)
-      .build(event_loop)
+  pub fn new(window_target: &EventLoopWindowTarget<()>, create_info: &WindowCreateInfo) -> Result<Self> {
+    let monitor = match &create_info.monitor {
+      Some(selection) => Some(Monitor::select(
+        window_target.available_monitors(),
+        window_target.primary_monitor(),
+        selection,
+      )?),
+      None => None,
+    };
+
+    let find_monitor = |name: &String| window_target.available_monitors().find(|m| m.name().as_ref() == Some(name));
+    let placement = create_info.placement.as_ref().filter(|placement| {
+      let available = placement.monitor.as_ref().is_none_or(|name| find_monitor(name).is_some());
+      if !available {
+        warn!("Saved monitor for window placement is unavailable, using defaults");
+      }
+      available
+    });
+
+    let mut builder = winit::window::WindowBuilder::new()
+      .with_title(&create_info.title)
+      .with_inner_size(LogicalSize::new(create_info.width, create_info.height))
+      .with_visible(false);
+
+    if let Some(placement) = placement {
+      builder = builder
+        .with_inner_size(PhysicalSize::new(placement.width, placement.height))
+        .with_maximized(placement.maximized);
+      if let Some((x, y)) = placement.position {
+        builder = builder.with_position(PhysicalPosition::new(x, y));
+      }
+      if placement.fullscreen {
+        // without a known monitor the window's current one is used
+        let monitor = placement.monitor.as_ref().and_then(find_monitor);
+        builder = builder.with_fullscreen(Some(Fullscreen::Borderless(monitor)));
+      }
+    }
+
+    let window = builder
+      .build(window_target)
       .context("Failed to create window")?;
 
-    Ok(Self {
+    let restored_size = placement
+      .map(|placement| (placement.width, placement.height))
+      .unwrap_or_else(|| window.inner_size().into());
+    let restored_position = placement.map_or_else(
+      || window.outer_position().ok().map(Into::into),
+      |placement| placement.position,
+    );
+
+    let window = Self {
       window,
       surface: None,
       surface_loader: None,
-    })
+      swapchain: None,
+      restored_size,
+      restored_position,
+    };
+
+    if placement.is_some_and(|placement| placement.position.is_some()) {
+      return Ok(window);
+    }
+
+    match (&monitor, create_info.centered) {
+      (Some(monitor), true) => window.center_on(monitor),
+      (Some(monitor), false) => window.move_to(monitor),
+      (None, true) => if let Err(err) = window.center_on_monitor() {
+        warn!("Failed to center window: {err:#}");
+      },
+      (None, false) => {}
+    }
+
+    Ok(window)
   }
 
   unsafe fn free(&mut self) {
+    // swapchain must go before the surface it was created from
+    self.swapchain = None;
     if let Some(surface_loader) = self.surface_loader.as_ref() {
       surface_loader.destroy_surface(**self.surface.as_ref().unwrap(), None);
     }
@@ -62,6 +162,52 @@ impl Window {
     &self.window
   }
 
+  pub fn id(&self) -> WindowId {
+    self.window.id()
+  }
+
+  #[allow(unused)]
+  pub(crate) fn swapchain(&self) -> Option<&Swapchain> {
+    self.swapchain.as_ref()
+  }
+
+  #[allow(unused)]
+  pub(crate) fn swapchain_mut(&mut self) -> Option<&mut Swapchain> {
+    self.swapchain.as_mut()
+  }
+
+  /// Creates or recreates the swapchain if it is missing or out of date.
+  /// Returns whether there is a swapchain ready to render to, which isn't the case while minimized.
+  pub(crate) fn prepare_swapchain(&mut self, context: &RenderContext) -> Result<bool> {
+    if self.swapchain.as_ref().is_some_and(|s| !s.out_of_date()) {
+      return Ok(true);
+    }
+
+    // checked before waiting, since minimized windows would otherwise stall the device every frame
+    let extent = Swapchain::surface_extent(context, self)?;
+    if extent.width == 0 || extent.height == 0 {
+      return Ok(false);
+    }
+
+    // the old swapchain's images may still be in flight
+    context.wait_idle()?;
+    let old = self.swapchain.take();
+    match Swapchain::new(context, self, old.as_ref()) {
+      Ok(Some(swapchain)) => {
+        self.swapchain = Some(swapchain);
+        Ok(true)
+      }
+      Ok(None) => {
+        self.swapchain = old;
+        Ok(false)
+      }
+      Err(err) => {
+        self.swapchain = old;
+        Err(err)
+      }
+    }
+  }
+
   #[allow(unused)]
   pub(crate) fn surface(&self) -> &vk::SurfaceKHR {
     unsafe {
@@ -104,23 +250,97 @@ impl Window {
     (x.width, x.height)
   }
 
+  pub fn position(&self) -> Result<(i32, i32)> {
+    let position = self.window.outer_position().context("Failed to get window position")?;
+    Ok((position.x, position.y))
+  }
+
+  /// Inner size the window had when last neither maximized nor fullscreen.
+  pub fn restored_size(&self) -> (u32, u32) {
+    self.restored_size
+  }
+
+  /// Position the window had when last neither maximized nor fullscreen.
+  /// `None` where the platform doesn't expose window positions, e.g. on Wayland.
+  pub fn restored_position(&self) -> Option<(i32, i32)> {
+    self.restored_position
+  }
+
+  /// Records the current size and position as the restored ones, unless maximized or fullscreen.
+  pub(crate) fn track_restored_placement(&mut self) {
+    if self.is_maximized() || self.is_fullscreen() {
+      return;
+    }
+    let (width, height) = self.size();
+    // minimizing reports a zero size on some platforms
+    if width > 0 && height > 0 {
+      self.restored_size = (width, height);
+    }
+    if let Ok(position) = self.position() {
+      self.restored_position = Some(position);
+    }
+  }
+
+  pub fn is_maximized(&self) -> bool {
+    self.window.is_maximized()
+  }
+
+  pub fn is_fullscreen(&self) -> bool {
+    self.window.fullscreen().is_some()
+  }
+
   pub fn set_visible(&self, visible: bool) {
     self.window.set_visible(visible);
   }
 
-  pub fn center_on_monitor(&self) {
-    let monitor = self.window.current_monitor().unwrap();
-    let monitor_center = PhysicalPosition::new(
-      monitor.position().x + (monitor.size().width as f32 * 0.5).floor() as i32,
-      monitor.position().y + (monitor.size().height as f32 * 0.5).floor() as i32,
-    );
+  pub fn current_monitor(&self) -> Result<Monitor> {
+    self.window.current_monitor()
+      .map(Monitor::from)
+      .context("Failed to find the window's current monitor")
+  }
+
+  pub fn primary_monitor(&self) -> Result<Monitor> {
+    Monitor::select(
+      self.window.available_monitors(),
+      self.window.primary_monitor(),
+      &MonitorSelection::Primary,
+    )
+  }
+
+  pub fn available_monitors(&self) -> Vec<Monitor> {
+    self.window.available_monitors()
+      .map(Monitor::from)
+      .collect()
+  }
+
+  pub fn select_monitor(&self, selection: &MonitorSelection) -> Result<Monitor> {
+    Monitor::select(
+      self.window.available_monitors(),
+      self.window.primary_monitor(),
+      selection,
+    )
+  }
+
+  pub fn center_on_monitor(&self) -> Result<()> {
+    let monitor = self.current_monitor()?;
+    self.center_on(&monitor);
+    Ok(())
+  }
+
+  pub fn center_on(&self, monitor: &Monitor) {
+    let monitor_center = monitor.center();
     let window_offset = PhysicalPosition::new(
-      monitor_center.x - (self.window.outer_size().width as f32 * 0.5).floor() as i32,
-      monitor_center.y - (self.window.outer_size().height as f32 * 0.5).floor() as i32,
+      monitor_center.0 - (self.window.outer_size().width as f32 * 0.5).floor() as i32,
+      monitor_center.1 - (self.window.outer_size().height as f32 * 0.5).floor() as i32,
     );
     self.window.set_outer_position(window_offset);
   }
 
+  /// Moves the window to the top left corner of the given monitor.
+  pub fn move_to(&self, monitor: &Monitor) {
+    self.window.set_outer_position(PhysicalPosition::new(monitor.position.0, monitor.position.1));
+  }
+
   pub(crate) fn request_redraw(&self) {
     self.window.request_redraw();
   }
@@ -1,5 +1,8 @@
 // mod shader;
 
+// lets `#[derive(Vertex)]` refer to `::koyote` from inside this crate too
+extern crate self as koyote;
+
 pub mod prelude;
 
 pub mod core;
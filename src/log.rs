@@ -1,11 +1,21 @@
-use strum::Display;
+use std::sync::OnceLock;
+use serde::Deserialize;
+use strum::{Display, EnumString};
 use tracing_subscriber::{
-  filter::LevelFilter
+  filter::LevelFilter,
+  fmt,
+  prelude::*,
+  reload,
+  Registry,
 };
 
+/// Lets the level be changed after `init`, e.g. by a config loaded once the app has set up logging.
+static LEVEL_HANDLE: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
 
-#[derive(Default, Display)]
-#[strum(serialize_all = "snake_case")]
+
+#[derive(Default, Display, EnumString, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+#[serde(rename_all = "snake_case")]
 pub enum Level {
   Trace,
   Debug,
@@ -45,11 +55,42 @@ pub fn init_debug(_user_logging_level: Option<Level>) {
 }
 
 pub fn init(user_logging_level: Option<Level>) {
-  tracing_subscriber::fmt()
-    .with_max_level(match user_logging_level {
-      None => LevelFilter::OFF,
-      Some(l) => l.into()
-    })
-    .with_thread_names(true)
-    .init();
-}
\ No newline at end of file
+  if !try_init(user_logging_level) {
+    panic!("Logging was already initialized");
+  }
+}
+
+/// Like `init`, but returns `false` instead of panicking if logging was already initialized.
+pub fn try_init(user_logging_level: Option<Level>) -> bool {
+  let (filter, handle) = reload::Layer::new(level_filter(user_logging_level));
+  let initialized = tracing_subscriber::registry()
+    .with(filter)
+    .with(fmt::layer().with_thread_names(true))
+    .try_init()
+    .is_ok();
+  if initialized {
+    let _ = LEVEL_HANDLE.set(handle);
+  }
+  initialized
+}
+
+/// Whether logging was initialized through `init` or `try_init`.
+pub fn is_initialized() -> bool {
+  LEVEL_HANDLE.get().is_some()
+}
+
+/// Changes the level of logging set up with `init` or `try_init`, initializing it if nothing was set up yet.
+/// Returns `false` if another subscriber is installed, whose level can't be changed.
+pub fn set_level(user_logging_level: Option<Level>) -> bool {
+  match LEVEL_HANDLE.get() {
+    Some(handle) => handle.reload(level_filter(user_logging_level)).is_ok(),
+    None => try_init(user_logging_level),
+  }
+}
+
+fn level_filter(user_logging_level: Option<Level>) -> LevelFilter {
+  match user_logging_level {
+    None => LevelFilter::OFF,
+    Some(l) => l.into()
+  }
+}
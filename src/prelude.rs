@@ -1,15 +1,36 @@
 pub use crate::{
   core::{
-    event::{InputEvent, WindowEvent},
+    config::FrameworkConfig,
+    event::{InputEvent, WindowEvent, Theme},
     flow::Flow,
     framework::Koyote,
+    pacing::LoopMode,
     runnable::Runnable,
     time::Time,
     error::{AppError, Required},
   },
   graphics::{
     Graphics,
-    window::Window,
+    buffer::typed::{BufferMode, IndexBuffer, StorageBuffer, UniformBuffer, VertexBuffer},
+    device::{DeviceFeature, DeviceSelection, DeviceSelector},
+    frame::Frame,
+    image::storage::StorageImage,
+    texture::{Texture, TextureOptions, sampler::SamplerDesc},
+    vertex::{Vertex, VertexLayout},
+    mesh::{GpuMesh, Mesh, MeshHandle, MeshVertex},
+    descriptor::{DescriptorAllocator, DescriptorSet, DescriptorSetLayout},
+    pipeline::{
+      compute::{ComputePipeline, ComputePipelineBuilder},
+      layout::PipelineLayout,
+      state::{BlendMode, CompareOp, CullMode, DepthMode, FrontFace, PipelineState, PolygonMode, Topology},
+    },
+    render_pass::{Attachment, AttachmentFormats, Framebuffer, RenderPass, Subpass},
+    graph::{BufferUsage, ImageUsage, RenderGraph, TransientImage},
+    target::RenderTarget,
+    readback::RgbaImage,
+    capture::FrameCapture,
+    window::{Window, WindowCreateInfo, WindowId},
+    monitor::{Monitor, MonitorSelection, VideoMode},
     shader::Shader,
   },
   input::{
@@ -0,0 +1,46 @@
+//! Runs on lavapipe or any other Vulkan device. Skipped unless `KOYOTE_LAVAPIPE_TESTS` is set, since it needs a
+//! Vulkan driver. Machines with a GPU can still force lavapipe with `KOYOTE_DEVICE=llvmpipe`.
+
+use std::collections::HashSet;
+use koyote::graphics::context::RenderContext;
+use koyote::graphics::shader::ShaderCreateInfo;
+use koyote::graphics::shader::stage::Stage;
+use koyote::prelude::*;
+
+fn headless_context() -> Option<RenderContext> {
+  if std::env::var_os("KOYOTE_LAVAPIPE_TESTS").is_none() {
+    eprintln!("KOYOTE_LAVAPIPE_TESTS is not set, skipping");
+    return None;
+  }
+
+  let selection = DeviceSelection::default().with_cpu_fallback(true);
+  Some(RenderContext::headless(&selection).expect("failed to create headless context"))
+}
+
+#[test]
+fn run_compute_round_trip() -> Result<()> {
+  let Some(context) = headless_context() else {
+    return Ok(());
+  };
+
+  let pipeline = ComputePipelineBuilder::from_context(&context)
+    .with_shader(ShaderCreateInfo {
+      path: "res/shaders/double.hlsl".into(),
+      stages: HashSet::from([Stage::Compute]),
+    })
+    .build()?;
+
+  let input: Vec<u32> = (0..1000).collect();
+  let values = StorageBuffer::new(&context, &input)?;
+
+  let mut allocator = DescriptorAllocator::new(&context);
+  let set = allocator.allocate(&pipeline.descriptor_set_layouts()[0])?;
+  set.writer().storage_buffer(0, &values)?.update(&context);
+
+  let workgroups = pipeline.workgroup_count([input.len() as u32, 1, 1]);
+  let output = context.run_compute(&pipeline, &[&set], workgroups, &values)?;
+
+  let expected: Vec<u32> = input.iter().map(|v| v * 2).collect();
+  assert_eq!(output, expected);
+  Ok(())
+}